@@ -0,0 +1,52 @@
+use crate::options::Options;
+use crate::theme::*;
+
+/// Which kind of address to print beside each instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address
+{
+    Offset, // Offset from the start of the file
+    Rva,    // Relative Virtual Address (PE only)
+    Va,     // Virtual Address using the PE image base (PE only)
+}
+
+/// Where the disassembled bytes were found within the file and image.
+///
+/// For binary files (`pe: OFF`) every field is zero, so all addresses are
+/// equal to the offset within the bytecode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Origin
+{
+    pub file_offset: u64, // PointerToRawData of the code section
+    pub rva: u64,         // VirtualAddress of the code section
+    pub image_base: u64,  // ImageBase from the optional header
+}
+
+impl Origin
+{
+    /// Converts an offset within the code section to the requested address.
+    pub fn address(&self, address: Address, offset: usize) -> u64
+    {
+        match address
+        {
+            Address::Offset => self.file_offset + offset as u64,
+            Address::Rva => self.rva + offset as u64,
+            Address::Va => self.image_base + self.rva + offset as u64,
+        }
+    }
+}
+
+/// Renders the address column for an instruction at `offset`.
+pub fn emit_address(offset: usize, options: &Options) -> Option<String>
+{
+    let address = options.address?;
+    let resolved = options.origin.address(address, offset);
+
+    let column = match address
+    {
+        Address::Va => format!("{:04X}  {:016X}", offset, resolved),
+        _ => format!("{:04X}  {:08X}", offset, resolved),
+    };
+
+    Some(color_address(column, options))
+}
@@ -1,7 +1,9 @@
+use crate::error::Error;
 use crate::natural_index::NaturalIndex;
 use crate::options::Options;
 use crate::theme::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Argument
 {
     Index16(u16),
@@ -12,34 +14,62 @@ pub enum Argument
     ImmediateI16(i16),
     ImmediateI32(i32),
     ImmediateI64(i64),
+    Data(u8), // Raw byte of a DB pseudo-instruction
 }
 
-impl Emit for Argument
+impl Argument
 {
-    fn emit(&self, options: &Options) -> String
+    /// The value of an immediate argument, or `None` for natural indexes.
+    pub fn immediate(&self) -> Option<i64>
     {
         match self
         {
-            Self::Index16(index) =>
-            {
-                let natural_index = NaturalIndex::from_u16(*index);
+            Self::Index16(_) | Self::Index32(_) | Self::Index64(_) => None,
+            Self::ImmediateU16(immediate) => Some(*immediate as i64),
+            Self::ImmediateU32(immediate) => Some(*immediate as i64),
+            Self::ImmediateI16(immediate) => Some(*immediate as i64),
+            Self::ImmediateI32(immediate) => Some(*immediate as i64),
+            Self::ImmediateI64(immediate) => Some(*immediate),
+            Self::Data(byte) => Some(*byte as i64),
+        }
+    }
 
-                format!("{}", natural_index.emit(options))
-            }
+    /// The decoded natural index, or `None` for immediate data.
+    pub fn natural_index(&self, size_of_void_ptr: u64) -> Option<NaturalIndex>
+    {
+        self.decode_index(size_of_void_ptr).and_then(Result::ok)
+    }
 
-            Self::Index32(index) =>
-            {
-                let natural_index = NaturalIndex::from_u32(*index);
+    /// Fails for a natural index whose width bits do not fit in it.
+    pub fn validate(&self) -> Result<(), Error>
+    {
+        self.decode_index(8).transpose().map(drop)
+    }
 
-                format!("{}", natural_index.emit(options))
-            }
+    fn decode_index(&self, size_of_void_ptr: u64) -> Option<Result<NaturalIndex, Error>>
+    {
+        match self
+        {
+            Self::Index16(index) => Some(NaturalIndex::from_u16(*index, size_of_void_ptr)),
+            Self::Index32(index) => Some(NaturalIndex::from_u32(*index, size_of_void_ptr)),
+            Self::Index64(index) => Some(NaturalIndex::from_u64(*index, size_of_void_ptr)),
+            _ => None,
+        }
+    }
+}
 
-            Self::Index64(index) =>
+impl Emit for Argument
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        match self
+        {
+            // The decoder rejects invalid indexes, so they are never rendered
+            Self::Index16(_) | Self::Index32(_) | Self::Index64(_) => match self.natural_index(options.pointer_size)
             {
-                let natural_index = NaturalIndex::from_u64(*index);
-
-                format!("{}", natural_index.emit(options))
-            }
+                Some(natural_index) => natural_index.emit(options),
+                None => color_error(String::from("<invalid index>"), options),
+            },
 
             Self::ImmediateU16(immediate) => color_immediate(immediate.to_string(), options),
 
@@ -50,6 +80,8 @@ impl Emit for Argument
             Self::ImmediateI32(immediate) => color_immediate(immediate.to_string(), options),
 
             Self::ImmediateI64(immediate) => color_immediate(immediate.to_string(), options),
+
+            Self::Data(byte) => color_immediate(format!("0x{:02X}", byte), options),
         }
     }
 }
@@ -0,0 +1,740 @@
+/*
+Assembles the exact syntax that Spore emits back into bytecode, e.g.:
+
+    MOVnw R1, @R0(+1, +16)
+    CALL32EXa @R1(+1, +0)
+    JMP8cs -3
+
+Blank lines, comments and label definitions (`loc_0040:`) are skipped. Since
+`JMP32` and `JMP64` do not mark relative addresses in their mnemonic, a jump is
+relative unless its comment contains "Absolute Address", which is what the
+disassembler prints.
+
+Only the instruction column is read, so listings have to be printed with
+`address: OFF bytecode: OFF` to be assembled again.
+*/
+
+use arrayvec::ArrayVec;
+
+use crate::error::Error;
+use crate::natural_index::NaturalIndex;
+use crate::opcode::OpCode;
+use crate::operand::Operand;
+
+const ARITHMETIC: [OpCode; 24] = [
+    OpCode::ADD,
+    OpCode::AND,
+    OpCode::ASHR,
+    OpCode::CMPeq,
+    OpCode::CMPlte,
+    OpCode::CMPgte,
+    OpCode::CMPulte,
+    OpCode::CMPugte,
+    OpCode::DIV,
+    OpCode::DIVU,
+    OpCode::EXTNDB,
+    OpCode::EXTNDD,
+    OpCode::EXTNDW,
+    OpCode::MOD,
+    OpCode::MODU,
+    OpCode::MUL,
+    OpCode::MULU,
+    OpCode::NEG,
+    OpCode::NOT,
+    OpCode::OR,
+    OpCode::SHL,
+    OpCode::SHR,
+    OpCode::SUB,
+    OpCode::XOR,
+];
+
+const CMPI: [(&str, OpCode); 5] = [
+    ("eq", OpCode::CMPIeq),
+    ("lte", OpCode::CMPIlte),
+    ("gte", OpCode::CMPIgte),
+    ("ulte", OpCode::CMPIulte),
+    ("ugte", OpCode::CMPIugte),
+];
+
+/// An argument as written in the source, before its encoded width is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value
+{
+    Index
+    {
+        negative: bool,
+        natural: u64,
+        constant: u64,
+    },
+
+    Immediate(i128),
+}
+
+/// One comma separated part of an instruction, i.e. `@R1(+1, +16)` or `-3`.
+#[derive(Debug, Clone, Copy, Default)]
+struct Part
+{
+    operand: Option<Operand>,
+    value: Option<Value>,
+}
+
+type Bytecode = ArrayVec<u8, 18>;
+
+/// Assembles every line of `source` into one contiguous block of bytecode.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error>
+{
+    let mut bytes = Vec::new();
+
+    for (i, line) in source.lines().enumerate()
+    {
+        let bytecode = assemble_instruction(line).map_err(|error| Error::AtLine(i + 1, Box::new(error)))?;
+
+        bytes.extend(bytecode);
+    }
+
+    Ok(bytes)
+}
+
+/// Assembles a single line. Lines without an instruction produce no bytes.
+pub fn assemble_instruction(line: &str) -> Result<Bytecode, Error>
+{
+    let (code, comment) = match line.find(';')
+    {
+        Some(i) => line.split_at(i),
+        None => (line, ""),
+    };
+
+    let code = code.trim();
+
+    if code.is_empty() || code.ends_with(':')
+    {
+        return Ok(Bytecode::new());
+    }
+
+    let (mnemonic, rest) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+    let parts = split_parts(rest).into_iter().map(parse_part).collect::<Result<Vec<_>, _>>()?;
+    let relative = !comment.contains("Absolute Address");
+
+    encode(mnemonic, &parts, relative)
+}
+
+fn syntax(message: String) -> Error
+{
+    Error::Syntax(message)
+}
+
+/// Splits on commas that are not inside of a natural index.
+fn split_parts(text: &str) -> Vec<&str>
+{
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices()
+    {
+        match c
+        {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 =>
+            {
+                parts.push(text[start .. i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    if !text[start ..].trim().is_empty() || !parts.is_empty()
+    {
+        parts.push(text[start ..].trim());
+    }
+
+    parts
+}
+
+/// Parses an operand the way the disassembler prints it, such as
+/// `@R1(+5, +24)` or `R2 -8`, along with the byte offset it adds to the
+/// register for a natural unit of `pointer_size` bytes.
+pub fn parse_operand_offset(text: &str, pointer_size: u64) -> Result<(Operand, i64), Error>
+{
+    let part = parse_part(text.trim())?;
+    let operand = part.operand.ok_or_else(|| syntax(format!("Missing register: {}", text)))?;
+
+    let offset = match part.value
+    {
+        None => 0,
+        Some(Value::Immediate(immediate)) => immediate as i64,
+        Some(Value::Index { negative, natural, constant }) =>
+        {
+            let offset = natural.wrapping_mul(pointer_size).wrapping_add(constant) as i64;
+
+            if negative
+            {
+                -offset
+            }
+            else
+            {
+                offset
+            }
+        }
+    };
+
+    Ok((operand, offset))
+}
+
+fn parse_part(text: &str) -> Result<Part, Error>
+{
+    let starts_with_value = text.starts_with(|c: char| c == '(' || c == '-' || c == '+' || c.is_ascii_digit());
+
+    if starts_with_value
+    {
+        return Ok(Part { operand: None, value: Some(parse_value(text)?) });
+    }
+
+    let end = text.find(|c: char| c == '(' || c.is_whitespace()).unwrap_or(text.len());
+    let (operand, value) = text.split_at(end);
+    let value = value.trim();
+
+    Ok(Part {
+        operand: Some(parse_operand(operand)?),
+        value: if value.is_empty() { None } else { Some(parse_value(value)?) },
+    })
+}
+
+fn parse_operand(text: &str) -> Result<Operand, Error>
+{
+    let (indirect, name) = match text.strip_prefix('@')
+    {
+        Some(name) => (true, name),
+        None => (false, text),
+    };
+
+    match name
+    {
+        "FLAGS" => Operand::new_dedicated(0, indirect),
+        "IP" => Operand::new_dedicated(1, indirect),
+        _ =>
+        {
+            let index = name
+                .strip_prefix('R')
+                .and_then(|index| index.parse::<u8>().ok())
+                .ok_or_else(|| syntax(format!("Invalid operand: {}", text)))?;
+
+            Operand::new_general_purpose(index, indirect)
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<i128, Error>
+{
+    let (negative, digits) = match text.strip_prefix('-')
+    {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+
+    let value = match digits.strip_prefix("0x")
+    {
+        Some(hex) => i128::from_str_radix(hex, 16),
+        None => digits.parse::<i128>(),
+    }
+    .map_err(|_| syntax(format!("Invalid number: {}", text)))?;
+
+    Ok(if negative { -value } else { value })
+}
+
+fn parse_value(text: &str) -> Result<Value, Error>
+{
+    let index = match text.strip_prefix('(').and_then(|index| index.strip_suffix(')'))
+    {
+        Some(index) => index,
+        None => return Ok(Value::Immediate(parse_number(text)?)),
+    };
+
+    let (natural, constant) = index.split_once(',').ok_or_else(|| syntax(format!("Invalid index: {}", text)))?;
+    let (natural, constant) = (natural.trim(), constant.trim());
+    let negative = natural.starts_with('-');
+    let magnitude = |unit: &str| -> Result<u64, Error> {
+        u64::try_from(parse_number(unit)?.unsigned_abs()).map_err(|_| syntax(format!("Index out of range: {}", text)))
+    };
+
+    Ok(Value::Index { negative, natural: magnitude(natural)?, constant: magnitude(constant)? })
+}
+
+fn opcode_byte(op: OpCode, bit7: bool, bit6: bool) -> u8
+{
+    let byte: u8 = op.try_into().unwrap();
+
+    byte | (bit7 as u8) << 7 | (bit6 as u8) << 6
+}
+
+fn operand_bits(operand: Operand) -> u8
+{
+    match operand
+    {
+        Operand::GeneralPurpose { register_index, indirect } => (indirect as u8) << 3 | register_index,
+        Operand::Dedicated { register_index, indirect } => (indirect as u8) << 3 | register_index,
+    }
+}
+
+fn is_indirect(operand: Operand) -> bool
+{
+    match operand
+    {
+        Operand::GeneralPurpose { indirect, .. } | Operand::Dedicated { indirect, .. } => indirect,
+    }
+}
+
+fn width_of(postfix: &str) -> Option<u32>
+{
+    match postfix
+    {
+        "b" => Some(8),
+        "w" => Some(16),
+        "d" => Some(32),
+        "q" => Some(64),
+        _ => None,
+    }
+}
+
+/// Encodes the width postfix of MOVI, MOVIn and MOVREL data.
+fn width_bits(size_in_bits: u32) -> u8
+{
+    match size_in_bits
+    {
+        8 => 0,
+        16 => 1,
+        32 => 2,
+        _ => 3,
+    }
+}
+
+fn encode_value(bytes: &mut Bytecode, value: Value, size_in_bits: u32) -> Result<(), Error>
+{
+    let encoded = match value
+    {
+        Value::Index { negative, natural, constant } => NaturalIndex::encode(negative, natural, constant, size_in_bits)
+            .ok_or_else(|| syntax(format!("Index does not fit in {} bits", size_in_bits)))?,
+
+        Value::Immediate(immediate) =>
+        {
+            let min = -(1i128 << (size_in_bits - 1));
+            let max = (1i128 << size_in_bits) - 1;
+
+            if !(min ..= max).contains(&immediate)
+            {
+                return Err(syntax(format!("Immediate does not fit in {} bits: {}", size_in_bits, immediate)));
+            }
+
+            immediate as u64
+        }
+    };
+
+    bytes.extend(encoded.to_le_bytes()[.. size_in_bits as usize / 8].iter().cloned());
+
+    Ok(())
+}
+
+fn expect_parts(mnemonic: &str, parts: &[Part], count: usize) -> Result<(), Error>
+{
+    if parts.len() != count
+    {
+        return Err(syntax(format!("{} expects {} operand(s), found {}", mnemonic, count, parts.len())));
+    }
+
+    Ok(())
+}
+
+fn expect_operand(mnemonic: &str, part: &Part) -> Result<Operand, Error>
+{
+    part.operand.ok_or_else(|| syntax(format!("{} is missing an operand", mnemonic)))
+}
+
+fn expect_value(mnemonic: &str, part: &Part) -> Result<Value, Error>
+{
+    part.value.ok_or_else(|| syntax(format!("{} is missing an argument", mnemonic)))
+}
+
+fn expect_index(mnemonic: &str, part: &Part) -> Result<Option<Value>, Error>
+{
+    match part.value
+    {
+        Some(Value::Immediate(_)) => Err(syntax(format!("{} only supports an index here", mnemonic))),
+        value => Ok(value),
+    }
+}
+
+fn encode(mnemonic: &str, parts: &[Part], relative: bool) -> Result<Bytecode, Error>
+{
+    let mut bytes = Bytecode::new();
+
+    match mnemonic
+    {
+        "RET" =>
+        {
+            expect_parts(mnemonic, parts, 0)?;
+            bytes.push(OpCode::RET.to());
+        }
+
+        "DB" =>
+        {
+            if parts.is_empty() || parts.len() > bytes.capacity()
+            {
+                let message = format!("{} expects 1 to {} operands, found {}", mnemonic, bytes.capacity(), parts.len());
+
+                return Err(syntax(message));
+            }
+
+            for part in parts
+            {
+                encode_value(&mut bytes, expect_value(mnemonic, part)?, 8)?;
+            }
+        }
+
+        "BREAK" =>
+        {
+            expect_parts(mnemonic, parts, 1)?;
+            bytes.push(OpCode::BREAK.to());
+            encode_value(&mut bytes, expect_value(mnemonic, &parts[0])?, 8)?;
+        }
+
+        "STORESP" | "LOADSP" =>
+        {
+            expect_parts(mnemonic, parts, 2)?;
+
+            let op = if mnemonic == "STORESP" { OpCode::STORESP } else { OpCode::LOADSP };
+            let op1 = expect_operand(mnemonic, &parts[0])?;
+            let op2 = expect_operand(mnemonic, &parts[1])?;
+
+            bytes.push(op.to());
+            bytes.push(operand_bits(op2) << 4 | operand_bits(op1));
+        }
+
+        "PUSHn" | "POPn" => encode_stack(&mut bytes, mnemonic, parts, false)?,
+
+        _ => encode_postfixed(&mut bytes, mnemonic, parts, relative)?,
+    }
+
+    Ok(bytes)
+}
+
+fn encode_stack(bytes: &mut Bytecode, mnemonic: &str, parts: &[Part], is_64_bit: bool) -> Result<(), Error>
+{
+    expect_parts(mnemonic, parts, 1)?;
+
+    let op = match mnemonic
+    {
+        "PUSHn" => OpCode::PUSHn,
+        "POPn" => OpCode::POPn,
+        _ if mnemonic.starts_with("PUSH") => OpCode::PUSH,
+        _ => OpCode::POP,
+    };
+
+    let op1 = expect_operand(mnemonic, &parts[0])?;
+
+    bytes.push(opcode_byte(op, parts[0].value.is_some(), is_64_bit));
+    bytes.push(operand_bits(op1));
+
+    if let Some(value) = parts[0].value
+    {
+        encode_value(bytes, value, 16)?;
+    }
+
+    Ok(())
+}
+
+/// Instructions whose mnemonic is made up of a name and several postfixes.
+fn encode_postfixed(bytes: &mut Bytecode, mnemonic: &str, parts: &[Part], relative: bool) -> Result<(), Error>
+{
+    let unknown = || syntax(format!("Unknown instruction: {}", mnemonic));
+
+    if let Some(condition) = mnemonic.strip_prefix("JMP8")
+    {
+        let (conditional, condition_bit_set) = parse_condition(condition).ok_or_else(unknown)?;
+
+        expect_parts(mnemonic, parts, 1)?;
+
+        let displacement = match expect_value(mnemonic, &parts[0])?
+        {
+            Value::Immediate(displacement) if (-128 ..= 127).contains(&displacement) => displacement as i8,
+            _ => return Err(syntax(format!("{} expects an 8 bit displacement", mnemonic))),
+        };
+
+        bytes.push(opcode_byte(OpCode::JMP8, conditional, condition_bit_set));
+        bytes.push(displacement as u8);
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("JMP")
+    {
+        let (is_64_bit, condition) = split_width(rest).ok_or_else(unknown)?;
+        let (conditional, condition_bit_set) = parse_condition(condition).ok_or_else(unknown)?;
+        let byte1 = (conditional as u8) << 7 | (condition_bit_set as u8) << 6 | (relative as u8) << 4;
+
+        expect_parts(mnemonic, parts, 1)?;
+        encode_branch(bytes, mnemonic, &parts[0], OpCode::JMP, is_64_bit, byte1)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("CALL")
+    {
+        let (is_64_bit, rest) = split_width(rest).ok_or_else(unknown)?;
+        let (is_native_call, rest) = match rest.strip_prefix("EX")
+        {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+
+        let is_relative_address = match rest
+        {
+            "" => true,
+            "a" => false,
+            _ => return Err(unknown()),
+        };
+
+        let byte1 = (is_native_call as u8) << 5 | (is_relative_address as u8) << 4;
+
+        expect_parts(mnemonic, parts, 1)?;
+        encode_branch(bytes, mnemonic, &parts[0], OpCode::CALL, is_64_bit, byte1)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("PUSH").or_else(|| mnemonic.strip_prefix("POP"))
+    {
+        let (is_64_bit, rest) = split_width(rest).ok_or_else(unknown)?;
+
+        if !rest.is_empty()
+        {
+            return Err(unknown());
+        }
+
+        encode_stack(bytes, mnemonic, parts, is_64_bit)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("CMPI")
+    {
+        let (is_64_bit, rest) = split_width(rest).ok_or_else(unknown)?;
+        let immediate_width = rest.get(.. 1).and_then(width_of).filter(|width| *width >= 16).ok_or_else(unknown)?;
+        let op = CMPI.iter().find(|(condition, _)| *condition == &rest[1 ..]).ok_or_else(unknown)?.1;
+
+        expect_parts(mnemonic, parts, 2)?;
+
+        let op1 = expect_operand(mnemonic, &parts[0])?;
+        let index = expect_index(mnemonic, &parts[0])?;
+
+        bytes.push(opcode_byte(op, immediate_width == 32, is_64_bit));
+        bytes.push((index.is_some() as u8) << 4 | operand_bits(op1));
+
+        if let Some(index) = index
+        {
+            encode_value(bytes, index, 16)?;
+        }
+
+        encode_value(bytes, expect_value(mnemonic, &parts[1])?, immediate_width)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("MOVIn")
+    {
+        let index_width = width_of(rest).filter(|width| *width >= 16).ok_or_else(unknown)?;
+
+        encode_move_immediate(bytes, mnemonic, parts, OpCode::MOVIn, 0, index_width)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("MOVI")
+    {
+        let move_width = rest.get(.. 1).and_then(width_of).ok_or_else(unknown)?;
+        let immediate_width = rest.get(1 ..).and_then(width_of).filter(|width| *width >= 16).ok_or_else(unknown)?;
+
+        encode_move_immediate(bytes, mnemonic, parts, OpCode::MOVI, width_bits(move_width), immediate_width)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("MOVREL")
+    {
+        let immediate_width = width_of(rest).filter(|width| *width >= 16).ok_or_else(unknown)?;
+
+        encode_move_immediate(bytes, mnemonic, parts, OpCode::MOVREL, 0, immediate_width)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("MOVsn").or_else(|| mnemonic.strip_prefix("MOVn"))
+    {
+        let natural = mnemonic.starts_with("MOVn");
+        let op = match (natural, rest)
+        {
+            (true, "" | "w") => OpCode::MOVnw,
+            (true, "d") => OpCode::MOVnd,
+            (false, "" | "w") => OpCode::MOVsnw,
+            (false, "d") => OpCode::MOVsnd,
+            _ => return Err(unknown()),
+        };
+
+        let index_width = if let OpCode::MOVnw | OpCode::MOVsnw = op { 16 } else { 32 };
+
+        encode_move(bytes, mnemonic, parts, op, index_width, true)?;
+    }
+    else if let Some(rest) = mnemonic.strip_prefix("MOV")
+    {
+        let op = match rest
+        {
+            "b" | "bw" => OpCode::MOVbw,
+            "w" | "ww" => OpCode::MOVww,
+            "d" | "dw" => OpCode::MOVdw,
+            "q" | "qw" => OpCode::MOVqw,
+            "bd" => OpCode::MOVbd,
+            "wd" => OpCode::MOVwd,
+            "dd" => OpCode::MOVdd,
+            "qd" => OpCode::MOVqd,
+            "qq" => OpCode::MOVqq,
+            _ => return Err(unknown()),
+        };
+
+        let index_width = rest.get(1 ..).and_then(width_of).unwrap_or(16);
+
+        encode_move(bytes, mnemonic, parts, op, index_width, false)?;
+    }
+    else
+    {
+        let split = mnemonic.len().saturating_sub(2);
+        let (name, width) = mnemonic.get(.. split).zip(mnemonic.get(split ..)).ok_or_else(unknown)?;
+        let is_64_bit = match width
+        {
+            "32" => false,
+            "64" => true,
+            _ => return Err(unknown()),
+        };
+
+        let op = *ARITHMETIC.iter().find(|op| format!("{:?}", op) == name).ok_or_else(unknown)?;
+
+        expect_parts(mnemonic, parts, 2)?;
+
+        let op1 = expect_operand(mnemonic, &parts[0])?;
+        let op2 = expect_operand(mnemonic, &parts[1])?;
+
+        if parts[0].value.is_some()
+        {
+            return Err(syntax(format!("{} does not support an argument for operand 1", mnemonic)));
+        }
+
+        bytes.push(opcode_byte(op, parts[1].value.is_some(), is_64_bit));
+        bytes.push(operand_bits(op2) << 4 | operand_bits(op1));
+
+        if let Some(value) = parts[1].value
+        {
+            encode_value(bytes, value, 16)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits a leading `32` or `64` from the rest of the postfixes.
+fn split_width(postfixes: &str) -> Option<(bool, &str)>
+{
+    if let Some(rest) = postfixes.strip_prefix("32")
+    {
+        Some((false, rest))
+    }
+    else
+    {
+        postfixes.strip_prefix("64").map(|rest| (true, rest))
+    }
+}
+
+/// Returns whether a jump is conditional and whether it jumps if the
+/// condition bit is set.
+fn parse_condition(condition: &str) -> Option<(bool, bool)>
+{
+    match condition
+    {
+        "" => Some((false, false)),
+        "cc" => Some((true, false)),
+        "cs" => Some((true, true)),
+        _ => None,
+    }
+}
+
+/// JMP32, JMP64, CALL32 and CALL64.
+fn encode_branch(
+    bytes: &mut Bytecode,
+    mnemonic: &str,
+    part: &Part,
+    op: OpCode,
+    is_64_bit: bool,
+    byte1: u8,
+) -> Result<(), Error>
+{
+    if is_64_bit
+    {
+        if part.operand.is_some()
+        {
+            return Err(syntax(format!("{} does not support an operand", mnemonic)));
+        }
+
+        bytes.push(opcode_byte(op, true, true));
+        bytes.push(byte1);
+        encode_value(bytes, expect_value(mnemonic, part)?, 64)?;
+    }
+    else
+    {
+        let op1 = expect_operand(mnemonic, part)?;
+
+        bytes.push(opcode_byte(op, part.value.is_some(), false));
+        bytes.push(byte1 | operand_bits(op1));
+
+        if let Some(value) = part.value
+        {
+            encode_value(bytes, value, 32)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// MOVI, MOVIn and MOVREL.
+fn encode_move_immediate(
+    bytes: &mut Bytecode,
+    mnemonic: &str,
+    parts: &[Part],
+    op: OpCode,
+    move_width: u8,
+    data_width: u32,
+) -> Result<(), Error>
+{
+    expect_parts(mnemonic, parts, 2)?;
+
+    let op1 = expect_operand(mnemonic, &parts[0])?;
+    let index = expect_index(mnemonic, &parts[0])?;
+    let data = expect_value(mnemonic, &parts[1])?;
+
+    if parts[1].operand.is_some()
+    {
+        return Err(syntax(format!("{} does not support operand 2", mnemonic)));
+    }
+
+    bytes.push(op.to() | width_bits(data_width) << 6);
+    bytes.push((index.is_some() as u8) << 6 | move_width << 4 | operand_bits(op1));
+
+    if let Some(index) = index
+    {
+        encode_value(bytes, index, 16)?;
+    }
+
+    encode_value(bytes, data, data_width)
+}
+
+/// MOVn, MOVsn and every other MOV.
+fn encode_move(
+    bytes: &mut Bytecode,
+    mnemonic: &str,
+    parts: &[Part],
+    op: OpCode,
+    index_width: u32,
+    allow_immediate: bool,
+) -> Result<(), Error>
+{
+    expect_parts(mnemonic, parts, 2)?;
+
+    let op1 = expect_operand(mnemonic, &parts[0])?;
+    let op2 = expect_operand(mnemonic, &parts[1])?;
+    let arg1 = expect_index(mnemonic, &parts[0])?;
+    let arg2 = if allow_immediate && !is_indirect(op2) { parts[1].value } else { expect_index(mnemonic, &parts[1])? };
+
+    bytes.push(opcode_byte(op, arg1.is_some(), arg2.is_some()));
+    bytes.push(operand_bits(op2) << 4 | operand_bits(op1));
+
+    for value in [arg1, arg2].into_iter().flatten()
+    {
+        encode_value(bytes, value, index_width)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,558 @@
+use std::io::Write;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, style, terminal};
+
+use crate::address::emit_address;
+use crate::function::Functions;
+use crate::image::Section;
+use crate::instruction::Instruction;
+use crate::label::{Label, Labels};
+use crate::options::Options;
+use crate::theme::*;
+
+const SIDEBAR_WIDTH: usize = 24;
+const HEX_ROWS: usize = 4;
+const BYTES_PER_ROW: usize = 16;
+
+const REVERSE: &str = "\x1B[7m";
+const RESET: &str = "\x1B[0m";
+
+/// A decoded code section, as it would be printed by the listing.
+pub struct Listing<'a>
+{
+    pub section: Section<'a>, // Origin should include the load base
+    pub instructions: Vec<Instruction>,
+    pub labels: Labels,
+    pub functions: Functions,
+}
+
+/// The keys the browser responds to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key
+{
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Follow,  // Jump to the target of an instruction or a sidebar entry
+    Back,    // Return to where the last jump was made from
+    Forward, // Undo going back
+    Switch,  // Move focus between the listing and the sidebar
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus
+{
+    Listing,
+    Sidebar,
+}
+
+enum Line
+{
+    Text(String),              // Section banner, function separator or label
+    Instruction(usize, usize), // Index of the listing and of the instruction
+}
+
+/// A section or function in the sidebar.
+struct Entry
+{
+    name: String,
+    line: usize,
+}
+
+/// An interactive view of one or more listings with a sidebar of sections and
+/// functions, a hex pane and a history of jumps.
+pub struct Browser<'a>
+{
+    listings: Vec<Listing<'a>>,
+    options: Options,
+    lines: Vec<Line>,
+    positions: Vec<Vec<usize>>, // Line of every instruction of every listing
+    entries: Vec<Entry>,
+    focus: Focus,
+    cursor: usize,      // Selected line
+    top: usize,         // First visible line
+    selected: usize,    // Selected sidebar entry
+    sidebar_top: usize, // First visible sidebar entry
+    back: Vec<usize>,
+    forward: Vec<usize>,
+    status: String,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Browser<'a>
+{
+    /// Lays out the listings one after the other, starting at the entry point.
+    pub fn new(listings: Vec<Listing<'a>>, options: &Options) -> Self
+    {
+        let options = Options { bytecode: false, pad_output: false, ..*options };
+        let mut lines = Vec::new();
+        let mut positions = Vec::new();
+        let mut entries = Vec::new();
+        let mut cursor = None;
+
+        for (l, listing) in listings.iter().enumerate()
+        {
+            let section = &listing.section;
+            let name = if section.name.is_empty() { String::from("(file)") } else { section.name.clone() };
+
+            entries.push(Entry { name, line: lines.len() });
+            lines.push(Line::Text(section.emit(&options)));
+            positions.push(Vec::with_capacity(listing.instructions.len()));
+
+            for (i, instruction) in listing.instructions.iter().enumerate()
+            {
+                if let Some(function) = listing.functions.get(instruction.offset)
+                {
+                    entries.push(Entry { name: format!("  {}", function.name), line: lines.len() });
+                    lines.push(Line::Text(String::new()));
+                    lines.push(Line::Text(function.emit(&options)));
+                }
+
+                if let Some(label) = listing.labels.get(instruction.offset)
+                {
+                    if matches!(label, Label::Entry(_))
+                    {
+                        cursor = cursor.or(Some(lines.len()));
+                    }
+
+                    lines.push(Line::Text(label.emit(&options)));
+                }
+
+                positions[l].push(lines.len());
+                lines.push(Line::Instruction(l, i));
+            }
+        }
+
+        let first = lines.iter().position(|line| matches!(line, Line::Instruction(..)));
+
+        Self {
+            listings,
+            options,
+            lines,
+            positions,
+            entries,
+            focus: Focus::Listing,
+            cursor: cursor.or(first).unwrap_or(0),
+            top: 0,
+            selected: 0,
+            sidebar_top: 0,
+            back: Vec::new(),
+            forward: Vec::new(),
+            status: String::new(),
+            width: 80,
+            height: 24,
+        }
+    }
+
+    /// Sets the size of the terminal in columns and rows.
+    pub fn resize(&mut self, width: usize, height: usize)
+    {
+        self.width = width;
+        self.height = height;
+        self.scroll();
+    }
+
+    /// The instruction at the cursor, or the first one after it.
+    pub fn selected(&self) -> Option<&Instruction>
+    {
+        self.current().map(|(l, i)| &self.listings[l].instructions[i])
+    }
+
+    /// Responds to a key, returning false when the browser should close.
+    pub fn handle(&mut self, key: Key) -> bool
+    {
+        let page = self.page();
+        self.status.clear();
+
+        match (key, self.focus)
+        {
+            (Key::Quit, _) => return false,
+            (Key::Switch, Focus::Listing) => self.focus = Focus::Sidebar,
+            (Key::Switch, Focus::Sidebar) => self.focus = Focus::Listing,
+            (Key::Back, _) => self.go_back(),
+            (Key::Forward, _) => self.go_forward(),
+
+            (Key::Follow, Focus::Listing) => self.follow(),
+            (Key::Follow, Focus::Sidebar) =>
+            {
+                if let Some(line) = self.entries.get(self.selected).map(|entry| entry.line)
+                {
+                    self.jump(line);
+                    self.focus = Focus::Listing;
+                }
+            }
+
+            (_, Focus::Listing) => self.cursor = moved(self.cursor, key, page, self.lines.len()),
+            (_, Focus::Sidebar) => self.selected = moved(self.selected, key, page, self.entries.len()),
+        }
+
+        self.scroll();
+        true
+    }
+
+    /// The screen as `height` lines of exactly `width` columns.
+    pub fn render(&self) -> Vec<String>
+    {
+        let page = self.page();
+        let listing_width = self.width.saturating_sub(SIDEBAR_WIDTH + 1);
+
+        if page == 0 || listing_width == 0
+        {
+            let mut screen = vec![" ".repeat(self.width); self.height];
+
+            if let Some(first) = screen.first_mut()
+            {
+                *first = fit("Terminal is too small", self.width);
+            }
+
+            return screen;
+        }
+
+        let mut screen = Vec::with_capacity(self.height);
+
+        for row in 0 .. page
+        {
+            let sidebar = match self.entries.get(self.sidebar_top + row)
+            {
+                Some(entry) if self.sidebar_top + row == self.selected && self.focus == Focus::Sidebar =>
+                {
+                    reversed(&entry.name, SIDEBAR_WIDTH)
+                }
+                Some(entry) => fit(&color_comment(entry.name.clone(), &self.options), SIDEBAR_WIDTH),
+                None => " ".repeat(SIDEBAR_WIDTH),
+            };
+
+            let line = self.top + row;
+            let listing = match self.lines.get(line)
+            {
+                Some(_) if line == self.cursor && self.focus == Focus::Listing =>
+                {
+                    reversed(&self.line(line, &Options { theme: None, ..self.options }), listing_width)
+                }
+                Some(_) => fit(&self.line(line, &self.options), listing_width),
+                None => " ".repeat(listing_width),
+            };
+
+            screen.push(format!("{}│{}", sidebar, listing));
+        }
+
+        screen.push(fit(&format!("{:─<1$}", "── bytes ", self.width), self.width));
+        screen.extend(self.hex());
+
+        let position = match self.current()
+        {
+            Some((l, i)) =>
+            {
+                let listing = &self.listings[l];
+                format!(" {} {:04X}", listing.section.name, listing.instructions[i].offset)
+            }
+            None => String::new(),
+        };
+        let help = "↑↓ move  Enter follow  ← back  → forward  Tab sidebar  q quit ";
+        let left = format!("{}  {}", position, self.status);
+        let gap = self.width.saturating_sub(left.chars().count() + help.chars().count());
+
+        screen.push(reversed(&format!("{}{}{}", left, " ".repeat(gap), help), self.width));
+        screen.truncate(self.height);
+        screen
+    }
+
+    /// Takes over the terminal until `Key::Quit`.
+    pub fn run(mut self) -> std::io::Result<()>
+    {
+        let mut stdout = std::io::stdout();
+
+        terminal::enable_raw_mode()?;
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        let result = self.event_loop(&mut stdout);
+
+        execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+
+        result
+    }
+
+    fn event_loop(&mut self, stdout: &mut std::io::Stdout) -> std::io::Result<()>
+    {
+        loop
+        {
+            let (width, height) = terminal::size()?;
+            self.resize(width as usize, height as usize);
+
+            for (row, line) in self.render().iter().enumerate()
+            {
+                queue!(stdout, cursor::MoveTo(0, row as u16), style::Print(line))?;
+            }
+
+            stdout.flush()?;
+
+            let key = match event::read()?
+            {
+                Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) => match code
+                {
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Quit),
+                    KeyCode::Up | KeyCode::Char('k') => Some(Key::Up),
+                    KeyCode::Down | KeyCode::Char('j') => Some(Key::Down),
+                    KeyCode::PageUp => Some(Key::PageUp),
+                    KeyCode::PageDown | KeyCode::Char(' ') => Some(Key::PageDown),
+                    KeyCode::Home | KeyCode::Char('g') => Some(Key::Home),
+                    KeyCode::End | KeyCode::Char('G') => Some(Key::End),
+                    KeyCode::Enter => Some(Key::Follow),
+                    KeyCode::Left | KeyCode::Backspace | KeyCode::Char('b') => Some(Key::Back),
+                    KeyCode::Right | KeyCode::Char('f') => Some(Key::Forward),
+                    KeyCode::Tab => Some(Key::Switch),
+                    KeyCode::Esc | KeyCode::Char('q') => Some(Key::Quit),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            if key.is_some_and(|key| !self.handle(key))
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Rows available to the listing and sidebar.
+    fn page(&self) -> usize
+    {
+        self.height.saturating_sub(HEX_ROWS + 2)
+    }
+
+    fn current(&self) -> Option<(usize, usize)>
+    {
+        self.lines[self.cursor.min(self.lines.len()) ..].iter().find_map(|line| match line
+        {
+            Line::Instruction(l, i) => Some((*l, *i)),
+            Line::Text(_) => None,
+        })
+    }
+
+    fn line(&self, line: usize, options: &Options) -> String
+    {
+        match self.lines[line]
+        {
+            Line::Text(ref text) => text.clone(),
+            Line::Instruction(l, i) =>
+            {
+                let listing = &self.listings[l];
+                let options = Options { origin: listing.section.origin, ..*options };
+
+                listing.instructions[i].emit(&options)
+            }
+        }
+    }
+
+    /// The bytes around the selected instruction, with its own highlighted.
+    fn hex(&self) -> Vec<String>
+    {
+        let mut rows = vec![" ".repeat(self.width); HEX_ROWS];
+        let (listing, instruction) = match self.current()
+        {
+            Some((l, i)) => (&self.listings[l], &self.listings[l].instructions[i]),
+            None => return rows,
+        };
+
+        let bytes = listing.section.contents();
+        let options = Options { origin: listing.section.origin, ..self.options };
+        let start = instruction.offset / BYTES_PER_ROW * BYTES_PER_ROW;
+
+        for (row, text) in rows.iter_mut().enumerate()
+        {
+            let offset = start + row * BYTES_PER_ROW;
+
+            if offset >= bytes.len()
+            {
+                break;
+            }
+
+            let chunk = &bytes[offset .. (offset + BYTES_PER_ROW).min(bytes.len())];
+            let mut line = emit_address(offset, &options).unwrap_or_else(|| format!("{:04X}", offset)) + "  ";
+
+            for (i, byte) in chunk.iter().enumerate()
+            {
+                if (instruction.offset .. instruction.end()).contains(&(offset + i))
+                {
+                    line += &format!("{}{:02X}{} ", REVERSE, byte, RESET);
+                }
+                else
+                {
+                    line += &color_bytecode(format!("{:02X}", byte), &options);
+                    line += " ";
+                }
+            }
+
+            line += &"   ".repeat(BYTES_PER_ROW - chunk.len());
+            line += &chunk
+                .iter()
+                .map(|byte| {
+                    if byte.is_ascii_graphic()
+                    {
+                        *byte as char
+                    }
+                    else
+                    {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+
+            *text = fit(&line, self.width);
+        }
+
+        rows
+    }
+
+    /// Jumps to the target of the selected branch, call or `MOVREL`, which
+    /// may be in another section.
+    fn follow(&mut self)
+    {
+        let (listing, instruction) = match self.current()
+        {
+            Some((l, i)) => (&self.listings[l], &self.listings[l].instructions[i]),
+            None => return,
+        };
+
+        let origin = listing.section.origin;
+        let relative = instruction.reference().and_then(|(_, target)| origin.rva.checked_add_signed(target));
+        let absolute = instruction.absolute_reference().and_then(|(_, address)| address.checked_sub(origin.image_base));
+
+        match relative.or(absolute)
+        {
+            Some(rva) => match self.line_at(rva)
+            {
+                Some(line) => self.jump(line),
+                None => self.status = format!("No code at RVA {:08X}", rva),
+            },
+            None => self.status = String::from("Nothing to follow"),
+        }
+    }
+
+    /// The line of the instruction at an RVA.
+    fn line_at(&self, rva: u64) -> Option<usize>
+    {
+        self.listings.iter().enumerate().find_map(|(l, listing)| {
+            let offset = usize::try_from(rva.checked_sub(listing.section.origin.rva)?).ok()?;
+            let i = listing.instructions.binary_search_by_key(&offset, |instruction| instruction.offset).ok()?;
+
+            Some(self.positions[l][i])
+        })
+    }
+
+    fn jump(&mut self, line: usize)
+    {
+        self.back.push(self.cursor);
+        self.forward.clear();
+        self.cursor = line;
+    }
+
+    fn go_back(&mut self)
+    {
+        match self.back.pop()
+        {
+            Some(line) =>
+            {
+                self.forward.push(self.cursor);
+                self.cursor = line;
+            }
+            None => self.status = String::from("Nothing to go back to"),
+        }
+    }
+
+    fn go_forward(&mut self)
+    {
+        match self.forward.pop()
+        {
+            Some(line) =>
+            {
+                self.back.push(self.cursor);
+                self.cursor = line;
+            }
+            None => self.status = String::from("Nothing to go forward to"),
+        }
+    }
+
+    /// Keeps the cursor and the selected sidebar entry on screen.
+    fn scroll(&mut self)
+    {
+        let page = self.page().max(1);
+
+        self.top = visible(self.top, self.cursor, page);
+        self.sidebar_top = visible(self.sidebar_top, self.selected, page);
+    }
+}
+
+fn moved(position: usize, key: Key, page: usize, length: usize) -> usize
+{
+    let last = length.saturating_sub(1);
+
+    match key
+    {
+        Key::Up => position.saturating_sub(1),
+        Key::Down => (position + 1).min(last),
+        Key::PageUp => position.saturating_sub(page),
+        Key::PageDown => (position + page).min(last),
+        Key::Home => 0,
+        Key::End => last,
+        _ => position,
+    }
+}
+
+fn visible(top: usize, position: usize, page: usize) -> usize
+{
+    if position < top
+    {
+        position
+    }
+    else if position >= top + page
+    {
+        position + 1 - page
+    }
+    else
+    {
+        top
+    }
+}
+
+/// Cuts or pads themed text to `width` columns, skipping over escape codes.
+fn fit(text: &str, width: usize) -> String
+{
+    let mut fitted = String::new();
+    let mut columns = 0;
+    let mut chars = text.chars();
+
+    while let Some(character) = chars.next()
+    {
+        if character == '\x1B'
+        {
+            fitted.push(character);
+            fitted.extend(chars.by_ref().take_while(|character| *character != 'm'));
+            fitted.push('m');
+        }
+        else if columns < width
+        {
+            fitted.push(character);
+            columns += 1;
+        }
+    }
+
+    if fitted.contains('\x1B')
+    {
+        fitted += RESET;
+    }
+
+    fitted + &" ".repeat(width - columns)
+}
+
+fn reversed(text: &str, width: usize) -> String
+{
+    format!("{}{}{}", REVERSE, fit(text, width), RESET)
+}
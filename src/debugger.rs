@@ -0,0 +1,422 @@
+/*
+A single-step debugger on top of the VM.
+
+Every command is one line of text, e.g. `b 1010`, `s 4` or `x @R1(+5, +24)`,
+and its output is returned instead of printed so the REPL only has to read
+lines and print what comes back. Addresses are VM addresses, which are RVAs for
+PE images and offsets for binary files, and are always hexadecimal.
+
+With `services()`, CALLEX runs the mock UEFI services and whatever the program
+writes to the console is printed before the output of the command.
+*/
+
+use std::collections::BTreeSet;
+
+use crate::assembler::parse_operand_offset;
+use crate::error::Error;
+use crate::instruction::{decode_at, Instruction};
+use crate::opcode::OpCode;
+use crate::operand::Operand;
+use crate::options::Options;
+use crate::services::Services;
+use crate::theme::*;
+use crate::vm::{Step, Vm};
+
+const MAX_STEPS: usize = 10_000_000; // Gives up on continue so infinite loops do not hang the REPL
+const BYTES_PER_ROW: usize = 16;
+
+const HELP: &str = "\
+Commands (addresses are hexadecimal, counts decimal, an empty line repeats the last command):
+    s, step [COUNT]       Execute instructions, stepping into calls
+    n, next               Execute an instruction, stepping over calls
+    c, continue           Run until a breakpoint, BREAK 3, CALLEX or RET
+    b, break [ADDRESS]    Set a breakpoint, or list them without an address
+    d, delete ADDRESS     Remove a breakpoint
+    r, registers          Show R0-R7, FLAGS and IP
+    stack [COUNT]         Show natural units from the top of the stack
+    x OPERAND [COUNT]     Dump memory at an address or operand, e.g. @R1(+5, +24)
+    l, list [COUNT]       Disassemble from IP
+    q, quit               Leave the debugger";
+
+/// Whether the program can still be stepped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State
+{
+    Running,
+    Exited, // RET with nothing left on the call stack
+}
+
+pub struct Debugger
+{
+    pub vm: Vm,
+    pub breakpoints: BTreeSet<u64>,
+    options: Options,
+    state: State,
+    last: String,               // Repeated when the command is empty
+    services: Option<Services>, // Runs CALLEX instead of skipping it
+    printed: usize,             // Bytes of the console output already printed
+}
+
+impl Debugger
+{
+    pub fn new(vm: Vm, options: &Options) -> Self
+    {
+        let options = Options { address: None, bytecode: false, pad_output: false, ..*options };
+
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            options,
+            state: State::Running,
+            last: String::new(),
+            services: None,
+            printed: 0,
+        }
+    }
+
+    pub fn services(mut self, services: Services) -> Self
+    {
+        self.services = Some(services);
+        self
+    }
+
+    /// Runs a command, returning its output or `None` to quit.
+    pub fn command(&mut self, line: &str) -> Option<String>
+    {
+        let line = match line.trim()
+        {
+            "" => self.last.clone(),
+            line => line.to_string(),
+        };
+
+        self.last = line.clone();
+
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line.as_str(), ""));
+        let rest = rest.trim();
+
+        let output = match name
+        {
+            "" => Ok(String::new()),
+            "q" | "quit" => return None,
+            "h" | "help" => Ok(HELP.to_string()),
+            "s" | "step" => count(rest, 1).map(|count| self.step(count)),
+            "n" | "next" => Ok(self.next()),
+            "c" | "continue" => Ok(self.resume(None)),
+            "b" | "break" if rest.is_empty() => Ok(self.list_breakpoints()),
+            "b" | "break" => address(rest).map(|address| {
+                self.breakpoints.insert(address);
+                format!("Breakpoint at {}", self.address(address))
+            }),
+            "d" | "delete" => address(rest).map(|address| match self.breakpoints.remove(&address)
+            {
+                true => format!("Deleted breakpoint at {}", self.address(address)),
+                false => format!("No breakpoint at {}", self.address(address)),
+            }),
+            "r" | "registers" => Ok(self.registers()),
+            "stack" => count(rest, 8).map(|count| self.stack(count)),
+            "x" => self.examine(rest),
+            "l" | "list" => count(rest, 8).map(|count| self.list(count)),
+            _ => Err(Error::Syntax(format!("Unknown command: {} (try help)", name))),
+        };
+
+        let output = output.unwrap_or_else(|error| color_error(error.to_string(), &self.options));
+
+        Some(self.console() + &output)
+    }
+
+    /// Console output of the program since the last command.
+    fn console(&mut self) -> String
+    {
+        let console = match &self.services
+        {
+            Some(services) => services.host.console[self.printed ..].to_string(),
+            None => String::new(),
+        };
+
+        self.printed += console.len();
+        console
+    }
+
+    /// The instruction at IP rendered like the listing.
+    pub fn current(&self) -> String
+    {
+        self.disassemble(self.vm.ip, 1)
+    }
+
+    fn step(&mut self, count: usize) -> String
+    {
+        for _ in 0 .. count
+        {
+            if let Some(stop) = self.execute()
+            {
+                return stop;
+            }
+        }
+
+        self.current()
+    }
+
+    /// Steps over a relative or absolute `CALL` by running until it returns.
+    fn next(&mut self) -> String
+    {
+        let call = decode_at(&self.vm.memory, self.vm.ip as usize)
+            .ok()
+            .flatten()
+            .filter(|instruction| instruction.op == Some(OpCode::CALL) && !instruction.is_native_call());
+
+        match call
+        {
+            Some(call) => self.resume(Some((self.vm.ip + call.bytecode.len() as u64, self.vm.registers[0]))),
+            None => self.step(1),
+        }
+    }
+
+    /// Runs until a breakpoint or anything the VM stops for. With a return
+    /// address and stack pointer, it also stops once the call returns.
+    fn resume(&mut self, until: Option<(u64, u64)>) -> String
+    {
+        for i in 0 .. MAX_STEPS
+        {
+            if i > 0 && self.breakpoints.contains(&self.vm.ip)
+            {
+                return format!("Breakpoint at {}\n{}", self.address(self.vm.ip), self.current());
+            }
+
+            if let Some(stop) = self.execute()
+            {
+                return stop;
+            }
+
+            if until.is_some_and(|(ip, r0)| self.vm.ip == ip && self.vm.registers[0] >= r0)
+            {
+                return self.current();
+            }
+        }
+
+        format!("Stopped after {} instructions\n{}", MAX_STEPS, self.current())
+    }
+
+    /// Executes one instruction, describing why execution stopped if it did.
+    fn execute(&mut self) -> Option<String>
+    {
+        if self.state == State::Exited
+        {
+            return Some(color_error(String::from("The program has exited"), &self.options));
+        }
+
+        let stop = match self.vm.step()
+        {
+            Ok(Step::Continue) => return None,
+            Ok(Step::Breakpoint) => String::from("BREAK 3"),
+            Ok(Step::Native(address)) =>
+            {
+                match self.services.as_mut().map(|services| services.call(&mut self.vm, address))
+                {
+                    Some(Ok(_)) => return None,
+                    Some(Err(error)) => return Some(color_error(error.to_string(), &self.options)),
+                    None => format!("CALLEX to native code at 0x{:X} was skipped", address),
+                }
+            }
+            Ok(Step::Halt) =>
+            {
+                self.state = State::Exited;
+                return Some(format!("Program returned 0x{:X}", self.vm.registers[7]));
+            }
+            Err(error) => return Some(color_error(error.to_string(), &self.options)),
+        };
+
+        Some(format!("{}\n{}", color_comment(stop, &self.options), self.current()))
+    }
+
+    fn list_breakpoints(&self) -> String
+    {
+        if self.breakpoints.is_empty()
+        {
+            return String::from("No breakpoints");
+        }
+
+        self.breakpoints.iter().map(|address| self.disassemble(*address, 1)).collect::<Vec<_>>().join("\n")
+    }
+
+    fn registers(&self) -> String
+    {
+        let register = |name: &str, value: u64| {
+            format!(
+                "{} {}",
+                color_operand(format!("{:<5}", name), &self.options),
+                color_immediate(format!("0x{:016X}", value), &self.options)
+            )
+        };
+
+        let mut lines = (0 .. 4)
+            .map(|i| {
+                let left = register(&format!("R{}", i), self.vm.registers[i]);
+                let right = register(&format!("R{}", i + 4), self.vm.registers[i + 4]);
+
+                format!("{}    {}", left, right)
+            })
+            .collect::<Vec<_>>();
+
+        lines.push(format!("{}    {}", register("FLAGS", self.vm.flags), register("IP", self.vm.ip)));
+        lines.join("\n")
+    }
+
+    fn stack(&self, count: usize) -> String
+    {
+        let natural = self.vm.pointer_size;
+        let mut lines = Vec::new();
+
+        for i in 0 .. count as u64
+        {
+            let address = self.vm.registers[0].wrapping_add(i * natural);
+            let value = match self.vm.read(address, natural as usize)
+            {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+
+            lines.push(format!(
+                "{}  {}  {}",
+                self.address(address),
+                color_comment(format!("@R0(+{}, +0)", i), &self.options),
+                color_immediate(format!("0x{:01$X}", value, natural as usize * 2), &self.options)
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Dumps memory at a hexadecimal address or at an operand such as
+    /// `@R1(+5, +24)`, which is resolved with the current register values.
+    fn examine(&self, arguments: &str) -> Result<String, Error>
+    {
+        // The count follows the closing parenthesis of an index
+        let split = arguments.rfind(')').map_or_else(|| arguments.find(char::is_whitespace), |end| Some(end + 1));
+        let (target, rest) = arguments.split_at(split.unwrap_or(arguments.len()));
+        let count = count(rest.trim(), BYTES_PER_ROW * 2)?;
+
+        let start = match address(target)
+        {
+            Ok(address) => address,
+            Err(_) =>
+            {
+                let (operand, offset) = parse_operand_offset(target, self.vm.pointer_size)?;
+                let base = match operand
+                {
+                    Operand::GeneralPurpose { register_index, .. } => self.vm.registers[register_index as usize],
+                    Operand::Dedicated { register_index: 0, .. } => self.vm.flags,
+                    Operand::Dedicated { .. } => self.vm.ip,
+                };
+
+                base.wrapping_add_signed(offset)
+            }
+        };
+
+        let mut lines = Vec::new();
+
+        for row in (0 .. count).step_by(BYTES_PER_ROW)
+        {
+            let address = start.wrapping_add(row as u64);
+            let bytes = (0 .. BYTES_PER_ROW.min(count - row))
+                .map_while(|i| self.vm.read(address.wrapping_add(i as u64), 1).ok().map(|byte| byte as u8))
+                .collect::<Vec<_>>();
+
+            if bytes.is_empty()
+            {
+                break;
+            }
+
+            let hex = bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+            let text = bytes.iter().map(|byte| {
+                if byte.is_ascii_graphic()
+                {
+                    *byte as char
+                }
+                else
+                {
+                    '.'
+                }
+            });
+
+            lines.push(format!(
+                "{}  {:<48} {}",
+                self.address(address),
+                color_bytecode(hex, &self.options),
+                text.collect::<String>()
+            ));
+        }
+
+        match lines.is_empty()
+        {
+            true => Err(Error::InvalidAddress(start)),
+            false => Ok(lines.join("\n")),
+        }
+    }
+
+    fn list(&self, count: usize) -> String
+    {
+        self.disassemble(self.vm.ip, count)
+    }
+
+    /// Up to `count` instructions from `address`, marking IP with `=>` and
+    /// breakpoints with `*`. Each one is decoded exactly where the previous one
+    /// ends, so zeros are shown as padding rather than skipped.
+    fn disassemble(&self, address: u64, count: usize) -> String
+    {
+        let mut lines = Vec::new();
+        let mut position = address as usize;
+
+        for _ in 0 .. count
+        {
+            let instruction = match decode_at(&self.vm.memory, position)
+            {
+                Ok(Some(instruction)) => instruction,
+                Ok(None) => Instruction::data(position, self.vm.memory[position], String::from("padding")),
+                Err(Error::Completed) => break,
+                Err(error) =>
+                {
+                    lines.push(color_error(error.to_string(), &self.options));
+                    break;
+                }
+            };
+
+            let address = instruction.offset as u64;
+            let marker = match (address == self.vm.ip, self.breakpoints.contains(&address))
+            {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+
+            position = instruction.end();
+            lines.push(format!("{} {}  {}", marker, self.address(address), instruction.emit(&self.options)));
+        }
+
+        if lines.is_empty()
+        {
+            lines.push(color_error(Error::InvalidAddress(address).to_string(), &self.options));
+        }
+
+        lines.join("\n")
+    }
+
+    fn address(&self, address: u64) -> String
+    {
+        color_address(format!("{:08X}", address), &self.options)
+    }
+}
+
+fn address(text: &str) -> Result<u64, Error>
+{
+    u64::from_str_radix(text.trim().trim_start_matches("0x"), 16)
+        .map_err(|_| Error::Syntax(format!("Invalid address: {}", text)))
+}
+
+fn count(text: &str, default: usize) -> Result<usize, Error>
+{
+    match text
+    {
+        "" => Ok(default),
+        text => text.parse().map_err(|_| Error::Syntax(format!("Invalid count: {}", text))),
+    }
+}
@@ -0,0 +1,438 @@
+/*
+Differential disassembly of two builds of the same code.
+
+Both sides are split into units: functions, plus runs of the remaining
+instructions that start at a label. Units are paired up before any
+instructions are compared: first those with the same code, then those with the
+same name and then those with at least half of their instructions in common.
+Code that was inserted therefore only shows up where it was inserted instead of
+shifting everything after it.
+
+Within a pair, instructions are aligned by their longest common subsequence, in
+which relative jumps, calls and MOVREL match regardless of their displacement.
+Instructions that only differ in their displacement are reported as moved.
+*/
+
+use std::collections::HashMap;
+
+use crate::function::Functions;
+use crate::graph::Graph;
+use crate::instruction::Instruction;
+use crate::label::{Label, Labels};
+use crate::opcode::OpCode;
+use crate::options::Options;
+use crate::theme::*;
+
+const CONTEXT: usize = 3; // Unchanged instructions shown around each change
+const MAX_COMPARISONS: usize = 1 << 24; // Most pairs of instructions compared to find out if two units are similar
+
+/// How an instruction of one side relates to the other side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change<'a>
+{
+    Same(&'a Instruction, &'a Instruction),  // Old and new instruction
+    Moved(&'a Instruction, &'a Instruction), // Only the displacement of a jump, call or MOVREL differs
+    Removed(&'a Instruction),
+    Added(&'a Instruction),
+}
+
+impl Change<'_>
+{
+    fn is_same(&self) -> bool
+    {
+        matches!(self, Self::Same(..))
+    }
+}
+
+/// A function or labelled run of code, with its instructions in order.
+#[derive(Debug, Clone)]
+pub struct Unit<'a>
+{
+    pub name: String,
+    pub offset: usize, // Offset of the first instruction
+    pub instructions: Vec<&'a Instruction>,
+}
+
+/// Splits decoded code into its functions and runs of the remaining
+/// instructions that start at a label.
+pub fn units<'a>(instructions: &'a [Instruction], labels: &Labels, functions: &Functions) -> Vec<Unit<'a>>
+{
+    let graph = Graph::new(instructions, labels);
+    let mut claimed = vec![false; instructions.len()];
+    let mut units = Vec::new();
+
+    for function in functions.iter()
+    {
+        let mut indexes =
+            graph.reachable(function.offset).iter().flat_map(|block| block.instructions.clone()).collect::<Vec<_>>();
+
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes.iter().for_each(|i| claimed[*i] = true);
+
+        units.push(Unit {
+            name: function.name.clone(),
+            offset: function.offset,
+            instructions: indexes.iter().map(|i| &instructions[*i]).collect(),
+        });
+    }
+
+    let mut run: Option<Unit> = None;
+
+    for (i, instruction) in instructions.iter().enumerate()
+    {
+        let label = labels.get(instruction.offset);
+
+        if claimed[i] || label.is_some()
+        {
+            units.extend(run.take());
+        }
+
+        if claimed[i]
+        {
+            continue;
+        }
+
+        run.get_or_insert_with(|| Unit {
+            name: label.copied().unwrap_or(Label::Location(instruction.offset)).name(),
+            offset: instruction.offset,
+            instructions: Vec::new(),
+        })
+        .instructions
+        .push(instruction);
+    }
+
+    units.extend(run);
+    units.sort_by_key(|unit| unit.offset);
+    units
+}
+
+/// The changes between a pair of units, or a unit that only one side has.
+#[derive(Debug, Clone)]
+pub struct UnitDiff<'a>
+{
+    pub old: Option<Unit<'a>>,
+    pub new: Option<Unit<'a>>,
+    pub changes: Vec<Change<'a>>,
+}
+
+impl UnitDiff<'_>
+{
+    pub fn name(&self) -> &str
+    {
+        self.new.as_ref().or(self.old.as_ref()).map_or("", |unit| unit.name.as_str())
+    }
+
+    pub fn is_unchanged(&self) -> bool
+    {
+        self.changes.iter().all(Change::is_same)
+    }
+}
+
+/// The changes between two builds of a code section, by unit.
+#[derive(Debug, Clone)]
+pub struct Diff<'a>
+{
+    pub units: Vec<UnitDiff<'a>>,
+}
+
+impl<'a> Diff<'a>
+{
+    pub fn new(old: Vec<Unit<'a>>, new: Vec<Unit<'a>>) -> Self
+    {
+        let mut numbers = HashMap::new();
+        let old_shapes = old.iter().map(|unit| shapes(&unit.instructions, &mut numbers)).collect::<Vec<_>>();
+        let new_shapes = new.iter().map(|unit| shapes(&unit.instructions, &mut numbers)).collect::<Vec<_>>();
+        let mut used = vec![false; old.len()];
+        let mut pairs = vec![None; new.len()];
+
+        // Same code first, since names of subroutines change with their offset
+        let passes: [&dyn Fn(usize, usize) -> bool; 2] =
+            [&|i, j| old_shapes[i] == new_shapes[j], &|i, j| old[i].name == new[j].name];
+
+        for matches in passes
+        {
+            for (j, pair) in pairs.iter_mut().enumerate().filter(|(_, pair)| pair.is_none())
+            {
+                if let Some(i) = (0 .. old.len()).find(|i| !used[*i] && matches(*i, j))
+                {
+                    used[i] = true;
+                    *pair = Some(i);
+                }
+            }
+        }
+
+        for (j, pair) in pairs.iter_mut().enumerate().filter(|(_, pair)| pair.is_none())
+        {
+            let similar = (0 .. old.len())
+                .filter(|i| !used[*i])
+                .map(|i| (common(&old_shapes[i], &new_shapes[j]), i))
+                .filter(|(common, i)| common * 2 >= old_shapes[*i].len().max(new_shapes[j].len()) && *common > 0)
+                .max_by_key(|(common, i)| (*common, std::cmp::Reverse(*i)));
+
+            if let Some((_, i)) = similar
+            {
+                used[i] = true;
+                *pair = Some(i);
+            }
+        }
+
+        let removed = (0 .. old.len()).filter(|i| !used[*i]).map(|i| UnitDiff {
+            old: Some(old[i].clone()),
+            new: None,
+            changes: old[i].instructions.iter().map(|instruction| Change::Removed(instruction)).collect(),
+        });
+
+        let units = pairs
+            .iter()
+            .zip(new.iter())
+            .enumerate()
+            .map(|(j, (pair, new))| match pair
+            {
+                Some(i) => UnitDiff {
+                    old: Some(old[*i].clone()),
+                    new: Some(new.clone()),
+                    changes: align(&old[*i].instructions, &new.instructions, &old_shapes[*i], &new_shapes[j]),
+                },
+                None => UnitDiff {
+                    old: None,
+                    new: Some(new.clone()),
+                    changes: new.instructions.iter().map(|instruction| Change::Added(instruction)).collect(),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        Self { units: units.into_iter().chain(removed).collect() }
+    }
+
+    /// How many instructions were moved, removed and added.
+    pub fn counts(&self) -> (usize, usize, usize)
+    {
+        let changes = self.units.iter().flat_map(|unit| unit.changes.iter());
+
+        changes.fold((0, 0, 0), |(moved, removed, added), change| match change
+        {
+            Change::Same(..) => (moved, removed, added),
+            Change::Moved(..) => (moved + 1, removed, added),
+            Change::Removed(_) => (moved, removed + 1, added),
+            Change::Added(_) => (moved, removed, added + 1),
+        })
+    }
+}
+
+/// Unified diff hunks for each unit that changed, followed by a summary.
+impl Emit for Diff<'_>
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        let options = &Options { address: None, bytecode: false, pad_output: false, ..*options };
+        let plain = &Options { theme: None, ..*options };
+        let mut lines = Vec::new();
+
+        for unit in self.units.iter().filter(|unit| !unit.is_unchanged())
+        {
+            for hunk in hunks(&unit.changes)
+            {
+                let (old, new) = hunk.iter().fold((None, None), |(old, new), change| match change
+                {
+                    Change::Same(a, b) | Change::Moved(a, b) => (old.or(Some(a.offset)), new.or(Some(b.offset))),
+                    Change::Removed(a) => (old.or(Some(a.offset)), new),
+                    Change::Added(b) => (old, new.or(Some(b.offset))),
+                });
+
+                let offset =
+                    |offset: Option<usize>| offset.map_or_else(|| String::from("----"), |o| format!("{:04X}", o));
+                let header = format!("@@ -{} +{} @@ {}", offset(old), offset(new), unit.name());
+
+                lines.push(color_address(header, options));
+
+                for change in hunk
+                {
+                    lines.push(match change
+                    {
+                        Change::Same(_, b) => format!("  {:04X}  {}", b.offset, b.emit(options)),
+                        Change::Moved(a, b) =>
+                        {
+                            let was = Instruction { comment: None, ..(*a).clone() };
+
+                            format!(
+                                "{}  {}{}",
+                                color_comment(format!("~ {:04X}", b.offset), options),
+                                b.emit(options),
+                                color_comment(format!("  ; moved, was {}", was.emit(plain)), options)
+                            )
+                        }
+                        Change::Removed(a) => color_error(format!("- {:04X}  {}", a.offset, a.emit(plain)), options),
+                        Change::Added(b) => color_label(format!("+ {:04X}  {}", b.offset, b.emit(plain)), options),
+                    });
+                }
+            }
+        }
+
+        let (moved, removed, added) = self.counts();
+        let changed = self.units.iter().filter(|unit| !unit.is_unchanged()).count();
+        let summary = format!(
+            "; {} of {} functions or labels changed, {} instructions removed, {} added, {} moved",
+            changed,
+            self.units.len(),
+            removed,
+            added,
+            moved
+        );
+
+        lines.push(color_comment(summary, options));
+        lines.join("\n")
+    }
+}
+
+/// Runs of changes with up to `CONTEXT` unchanged instructions around them.
+fn hunks<'a, 'b>(changes: &'b [Change<'a>]) -> Vec<&'b [Change<'a>]>
+{
+    let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+
+    for (i, _) in changes.iter().enumerate().filter(|(_, change)| !change.is_same())
+    {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + 1 + CONTEXT).min(changes.len());
+
+        match hunks.last_mut()
+        {
+            Some(hunk) if hunk.end >= start => hunk.end = end,
+            _ => hunks.push(start .. end),
+        }
+    }
+
+    hunks.into_iter().map(|hunk| &changes[hunk]).collect()
+}
+
+/// Aligns the instructions of two versions of a unit, given their shapes.
+fn align<'a>(
+    old: &[&'a Instruction],
+    new: &[&'a Instruction],
+    old_shapes: &[usize],
+    new_shapes: &[usize],
+) -> Vec<Change<'a>>
+{
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    // Only the part between the unchanged start and end needs the full search
+    let prefix = old_shapes.iter().zip(new_shapes.iter()).take_while(|(a, b)| a == b).count();
+    let suffix =
+        old_shapes[prefix ..].iter().rev().zip(new_shapes[prefix ..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+
+    pairs.extend((0 .. prefix).map(|k| (k, k)));
+    matches(&old_shapes[prefix .. old_end], &new_shapes[prefix .. new_end], (prefix, prefix), &mut pairs);
+    pairs.extend((0 .. suffix).map(|k| (old_end + k, new_end + k)));
+
+    let mut changes = Vec::new();
+
+    for (a, b) in pairs.into_iter().chain(std::iter::once((old.len(), new.len())))
+    {
+        changes.extend(old[i .. a].iter().map(|instruction| Change::Removed(instruction)));
+        changes.extend(new[j .. b].iter().map(|instruction| Change::Added(instruction)));
+
+        if a < old.len()
+        {
+            let same = Instruction { offset: 0, comment: None, ..old[a].clone() }
+                == Instruction { offset: 0, comment: None, ..new[b].clone() };
+
+            changes.push(if same { Change::Same(old[a], new[b]) } else { Change::Moved(old[a], new[b]) });
+        }
+
+        (i, j) = (a + 1, b + 1);
+    }
+
+    changes
+}
+
+/// Adds the indexes of a longest common subsequence of `old` and `new`, offset
+/// by `base`, to `pairs` in order. This is Hirschberg's algorithm, which only
+/// needs memory for one row of the table at a time.
+fn matches(old: &[usize], new: &[usize], base: (usize, usize), pairs: &mut Vec<(usize, usize)>)
+{
+    if old.is_empty() || new.is_empty()
+    {
+        return;
+    }
+
+    if old.len() == 1
+    {
+        pairs.extend(new.iter().position(|shape| *shape == old[0]).map(|j| (base.0, base.1 + j)));
+        return;
+    }
+
+    let middle = old.len() / 2;
+    let reversed = |shapes: &[usize]| shapes.iter().rev().copied().collect::<Vec<_>>();
+    let before = lengths(&old[.. middle], new);
+    let after = lengths(&reversed(&old[middle ..]), &reversed(new));
+    let split = (0 ..= new.len()).max_by_key(|j| (before[*j] + after[new.len() - j], std::cmp::Reverse(*j))).unwrap();
+
+    matches(&old[.. middle], &new[.. split], base, pairs);
+    matches(&old[middle ..], &new[split ..], (base.0 + middle, base.1 + split), pairs);
+}
+
+/// The length of the longest common subsequence of `old` and every prefix of
+/// `new`.
+fn lengths(old: &[usize], new: &[usize]) -> Vec<usize>
+{
+    let mut row = vec![0; new.len() + 1];
+
+    for shape in old
+    {
+        let mut diagonal = 0;
+
+        for (j, other) in new.iter().enumerate()
+        {
+            let above = row[j + 1];
+
+            row[j + 1] = if shape == other { diagonal + 1 } else { above.max(row[j]) };
+            diagonal = above;
+        }
+    }
+
+    row
+}
+
+/// How many instructions two units have in common, or 0 when they could not
+/// have half of them in common or are too large to compare.
+fn common(old: &[usize], new: &[usize]) -> usize
+{
+    let (shorter, longer) = (old.len().min(new.len()), old.len().max(new.len()));
+
+    if shorter * 2 < longer || shorter.saturating_mul(longer) > MAX_COMPARISONS
+    {
+        return 0;
+    }
+
+    lengths(old, new)[new.len()]
+}
+
+/// Numbers the instructions so that those that only differ in their location
+/// or the displacement of a relative or relocated target, which change
+/// whenever code before them does, get the same number. `numbers` keeps the
+/// number of each shape seen so far.
+fn shapes(instructions: &[&Instruction], numbers: &mut HashMap<String, usize>) -> Vec<usize>
+{
+    instructions
+        .iter()
+        .map(|instruction| {
+            let mut shape = Instruction { offset: 0, comment: None, ..(*instruction).clone() };
+
+            if instruction.reference().is_some() || instruction.absolute_reference().is_some()
+            {
+                shape.bytecode.clear();
+
+                match instruction.op
+                {
+                    Some(OpCode::MOVREL) => shape.argument2 = None,
+                    _ => shape.argument1 = None,
+                }
+            }
+
+            let next = numbers.len();
+            *numbers.entry(format!("{:?}", shape)).or_insert(next)
+        })
+        .collect()
+}
@@ -0,0 +1,481 @@
+use std::collections::BTreeMap;
+
+use crate::argument::Argument;
+use crate::instruction::{Instruction, Postfix};
+use crate::label::{Label, Labels};
+use crate::opcode::OpCode;
+use crate::operand::Operand;
+use crate::options::Options;
+
+/// Size of `EFI_TABLE_HEADER`, which starts the system and service tables.
+const TABLE_HEADER: u64 = 24;
+
+/// The layout of a UEFI table or protocol interface.
+///
+/// Every member after the header is pointer sized or padded to be, so member
+/// `i` is at the natural index `(+i, +header)` for both 32 and 64-bit targets.
+#[derive(Debug)]
+pub struct Table
+{
+    pub name: &'static str,
+    pub header: u64, // Constant bytes before the first member
+    pub members: &'static [(&'static str, Option<&'static Table>)], // Names and the tables they point to
+}
+
+impl Table
+{
+    /// The member at a natural index, which may also be written as a constant
+    /// offset for a particular pointer size.
+    pub fn member(&self, natural: i64, constant: i64, pointer_size: u64) -> Option<&(&'static str, Option<&Table>)>
+    {
+        let constant = constant.checked_sub(self.header as i64)?;
+        let pointer_size = pointer_size as i64;
+
+        if natural < 0 || constant < 0 || constant % pointer_size != 0
+        {
+            return None;
+        }
+
+        self.members.get((natural + constant / pointer_size) as usize)
+    }
+}
+
+pub static SIMPLE_TEXT_INPUT_PROTOCOL: Table = Table {
+    name: "EFI_SIMPLE_TEXT_INPUT_PROTOCOL",
+    header: 0,
+    members: &[("Reset", None), ("ReadKeyStroke", None), ("WaitForKey", None)],
+};
+
+pub static SIMPLE_TEXT_OUTPUT_PROTOCOL: Table = Table {
+    name: "EFI_SIMPLE_TEXT_OUTPUT_PROTOCOL",
+    header: 0,
+    members: &[
+        ("Reset", None),
+        ("OutputString", None),
+        ("TestString", None),
+        ("QueryMode", None),
+        ("SetMode", None),
+        ("SetAttribute", None),
+        ("ClearScreen", None),
+        ("SetCursorPosition", None),
+        ("EnableCursor", None),
+        ("Mode", None),
+    ],
+};
+
+pub static RUNTIME_SERVICES: Table = Table {
+    name: "EFI_RUNTIME_SERVICES",
+    header: TABLE_HEADER,
+    members: &[
+        ("GetTime", None),
+        ("SetTime", None),
+        ("GetWakeupTime", None),
+        ("SetWakeupTime", None),
+        ("SetVirtualAddressMap", None),
+        ("ConvertPointer", None),
+        ("GetVariable", None),
+        ("GetNextVariableName", None),
+        ("SetVariable", None),
+        ("GetNextHighMonotonicCount", None),
+        ("ResetSystem", None),
+        ("UpdateCapsule", None),
+        ("QueryCapsuleCapabilities", None),
+        ("QueryVariableInfo", None),
+    ],
+};
+
+pub static BOOT_SERVICES: Table = Table {
+    name: "EFI_BOOT_SERVICES",
+    header: TABLE_HEADER,
+    members: &[
+        ("RaiseTPL", None),
+        ("RestoreTPL", None),
+        ("AllocatePages", None),
+        ("FreePages", None),
+        ("GetMemoryMap", None),
+        ("AllocatePool", None),
+        ("FreePool", None),
+        ("CreateEvent", None),
+        ("SetTimer", None),
+        ("WaitForEvent", None),
+        ("SignalEvent", None),
+        ("CloseEvent", None),
+        ("CheckEvent", None),
+        ("InstallProtocolInterface", None),
+        ("ReinstallProtocolInterface", None),
+        ("UninstallProtocolInterface", None),
+        ("HandleProtocol", None),
+        ("Reserved", None),
+        ("RegisterProtocolNotify", None),
+        ("LocateHandle", None),
+        ("LocateDevicePath", None),
+        ("InstallConfigurationTable", None),
+        ("LoadImage", None),
+        ("StartImage", None),
+        ("Exit", None),
+        ("UnloadImage", None),
+        ("ExitBootServices", None),
+        ("GetNextMonotonicCount", None),
+        ("Stall", None),
+        ("SetWatchdogTimer", None),
+        ("ConnectController", None),
+        ("DisconnectController", None),
+        ("OpenProtocol", None),
+        ("CloseProtocol", None),
+        ("OpenProtocolInformation", None),
+        ("ProtocolsPerHandle", None),
+        ("LocateHandleBuffer", None),
+        ("LocateProtocol", None),
+        ("InstallMultipleProtocolInterfaces", None),
+        ("UninstallMultipleProtocolInterfaces", None),
+        ("CalculateCrc32", None),
+        ("CopyMem", None),
+        ("SetMem", None),
+        ("CreateEventEx", None),
+    ],
+};
+
+pub static SYSTEM_TABLE: Table = Table {
+    name: "EFI_SYSTEM_TABLE",
+    header: TABLE_HEADER,
+    members: &[
+        ("FirmwareVendor", None),
+        ("FirmwareRevision", None),
+        ("ConsoleInHandle", None),
+        ("ConIn", Some(&SIMPLE_TEXT_INPUT_PROTOCOL)),
+        ("ConsoleOutHandle", None),
+        ("ConOut", Some(&SIMPLE_TEXT_OUTPUT_PROTOCOL)),
+        ("StandardErrorHandle", None),
+        ("StdErr", Some(&SIMPLE_TEXT_OUTPUT_PROTOCOL)),
+        ("RuntimeServices", Some(&RUNTIME_SERVICES)),
+        ("BootServices", Some(&BOOT_SERVICES)),
+        ("NumberOfTableEntries", None),
+        ("ConfigurationTable", None),
+    ],
+};
+
+/// Where the arguments of an EFI entry point are relative to R0 on entry,
+/// past the return address.
+const IMAGE_HANDLE: (i64, i64) = (0, 16);
+const SYSTEM_TABLE_ARGUMENT: (i64, i64) = (1, 16);
+
+/// What a register or stack slot is known to hold.
+#[derive(Debug, Clone)]
+enum Value
+{
+    Stack(i64, i64), // Address relative to R0 on entry as natural units and bytes
+    Named(String, Option<&'static Table>),
+}
+
+/// The registers and stack slots known at a point in the code.
+#[derive(Debug, Clone, Default)]
+struct State
+{
+    registers: [Option<Value>; 8],
+    slots: BTreeMap<(i64, i64), Value>,
+}
+
+impl State
+{
+    fn entry() -> Self
+    {
+        let mut state = Self::default();
+
+        state.registers[0] = Some(Value::Stack(0, 0));
+        state.slots.insert(IMAGE_HANDLE, Value::Named(String::from("ImageHandle"), None));
+        state.slots.insert(SYSTEM_TABLE_ARGUMENT, Value::Named(String::from("SystemTable"), Some(&SYSTEM_TABLE)));
+        state
+    }
+
+    /// A function that is not the entry point only knows where its stack is.
+    fn subroutine() -> Self
+    {
+        let mut state = Self::default();
+
+        state.registers[0] = Some(Value::Stack(0, 0));
+        state
+    }
+
+    /// The value at `@register(index)`.
+    fn load(&self, register: usize, index: (i64, i64), options: &Options) -> Option<Value>
+    {
+        match self.registers[register].as_ref()?
+        {
+            Value::Stack(natural, constant) => self.slots.get(&(natural + index.0, constant + index.1)).cloned(),
+
+            Value::Named(name, Some(table)) =>
+            {
+                let (member, points_to) = table.member(index.0, index.1, options.pointer_size)?;
+
+                Some(Value::Named(format!("{}->{}", name, member), *points_to).simplify())
+            }
+
+            Value::Named(_, None) => None,
+        }
+    }
+
+    fn store(&mut self, register: usize, index: (i64, i64), value: Option<Value>)
+    {
+        if let Some(Value::Stack(natural, constant)) = self.registers[register]
+        {
+            let slot = (natural + index.0, constant + index.1);
+
+            match value
+            {
+                Some(value) => self.slots.insert(slot, value),
+                None => self.slots.remove(&slot),
+            };
+        }
+    }
+
+    /// Moves R0 by a natural index and constant, pushing `value` when it grows.
+    fn adjust_stack(&mut self, natural: i64, constant: i64, value: Option<Value>)
+    {
+        if let Some(Value::Stack(n, c)) = self.registers[0]
+        {
+            let top = (n + natural, c + constant);
+
+            self.registers[0] = Some(Value::Stack(top.0, top.1));
+
+            if natural < 0 || constant < 0
+            {
+                match value
+                {
+                    Some(value) => self.slots.insert(top, value),
+                    None => self.slots.remove(&top),
+                };
+            }
+        }
+    }
+}
+
+impl Value
+{
+    /// Tables reached through the system table are known by their member
+    /// name, so `SystemTable->ConOut` becomes `ConOut`.
+    fn simplify(self) -> Self
+    {
+        match self
+        {
+            Self::Named(name, table @ Some(_)) => match name.rsplit_once("->")
+            {
+                Some((_, member)) => Self::Named(member.to_string(), table),
+                None => Self::Named(name, table),
+            },
+
+            value => value,
+        }
+    }
+}
+
+/// A natural index argument as signed natural units and bytes.
+fn index(argument: Option<Argument>, options: &Options) -> Option<(i64, i64)>
+{
+    match argument
+    {
+        None => Some((0, 0)),
+        Some(argument) =>
+        {
+            let index = argument.natural_index(options.pointer_size)?;
+            let sign = index.sign as i64;
+
+            Some((sign * index.natural as i64, sign * index.constant as i64))
+        }
+    }
+}
+
+fn register(operand: Option<Operand>) -> Option<(usize, bool)>
+{
+    match operand?
+    {
+        Operand::GeneralPurpose { register_index, indirect } => Some((register_index as usize, indirect)),
+        Operand::Dedicated { .. } => None,
+    }
+}
+
+/// Comments each `CALLEX` with the UEFI service it calls when a simple
+/// dataflow pass can tell which table its operand points to, starting from the
+/// `SystemTable` argument of the entry point.
+///
+/// Registers are tracked straight down the listing, with the state reset at
+/// the start of every function.
+pub fn annotate_services(
+    instructions: &mut [Instruction],
+    labels: &Labels,
+    entry_point: Option<usize>,
+    options: &Options,
+)
+{
+    let mut state = State::subroutine();
+
+    for instruction in instructions.iter_mut()
+    {
+        match labels.get(instruction.offset)
+        {
+            Some(Label::Entry(_)) => state = State::entry(),
+            Some(Label::Subroutine(_)) => state = State::subroutine(),
+            _ if Some(instruction.offset) == entry_point => state = State::entry(),
+            _ => (),
+        }
+
+        if instruction.is_native_call()
+        {
+            if let Some(name) = callee(&state, instruction, options)
+            {
+                instruction.comment = Some(match instruction.comment.take()
+                {
+                    Some(comment) => format!("{}, {}", name, comment),
+                    None => name,
+                });
+            }
+
+            // R4 to R7 are not preserved across calls
+            state.registers[4 ..].iter_mut().for_each(|register| *register = None);
+            continue;
+        }
+
+        step(&mut state, instruction, options);
+    }
+}
+
+/// The name of the service called through `@Rn(index)` or a register that
+/// holds a member that was loaded earlier.
+fn callee(state: &State, instruction: &Instruction, options: &Options) -> Option<String>
+{
+    let (register, indirect) = register(instruction.operand1)?;
+
+    let value = if indirect
+    {
+        state.load(register, index(instruction.argument1, options)?, options)?
+    }
+    else
+    {
+        state.registers[register].clone()?
+    };
+
+    match value
+    {
+        Value::Named(name, _) => Some(name),
+        Value::Stack(..) => None,
+    }
+}
+
+fn step(state: &mut State, instruction: &Instruction, options: &Options)
+{
+    let op = match instruction.op
+    {
+        Some(op) => op,
+        None => return,
+    };
+
+    let destination = register(instruction.operand1);
+    let source = register(instruction.operand2);
+
+    match op
+    {
+        OpCode::MOVnw
+        | OpCode::MOVnd
+        | OpCode::MOVqw
+        | OpCode::MOVqd
+        | OpCode::MOVqq
+        | OpCode::MOVdw
+        | OpCode::MOVdd =>
+        {
+            let value = match (source, index(instruction.argument2, options))
+            {
+                (Some((register, true)), Some(index)) => state.load(register, index, options),
+
+                // Adding an index to R0 moves the stack
+                (Some((register, false)), Some((natural, constant))) => match state.registers[register]
+                {
+                    Some(Value::Stack(n, c)) => Some(Value::Stack(n + natural, c + constant)),
+                    ref value if (natural, constant) == (0, 0) => value.clone(),
+                    _ => None,
+                },
+
+                _ => None,
+            };
+
+            match (destination, index(instruction.argument1, options))
+            {
+                (Some((register, true)), Some(index)) => state.store(register, index, value),
+                (Some((register, false)), _) => state.registers[register] = value,
+                _ => (),
+            }
+        }
+
+        OpCode::PUSHn | OpCode::PUSH =>
+        {
+            let value = match destination
+            {
+                Some((register, false)) if instruction.argument1.is_none() => state.registers[register].clone(),
+                _ => None,
+            };
+
+            match op
+            {
+                OpCode::PUSHn => state.adjust_stack(-1, 0, value),
+                _ => state.adjust_stack(0, -width(instruction), value),
+            }
+        }
+
+        OpCode::POPn | OpCode::POP =>
+        {
+            let value = match state.registers[0]
+            {
+                Some(Value::Stack(n, c)) => state.slots.get(&(n, c)).cloned(),
+                _ => None,
+            };
+
+            match op
+            {
+                OpCode::POPn => state.adjust_stack(1, 0, None),
+                _ => state.adjust_stack(0, width(instruction), None),
+            }
+
+            if let Some((register, false)) = destination
+            {
+                state.registers[register] = value;
+            }
+        }
+
+        OpCode::CMPeq
+        | OpCode::CMPlte
+        | OpCode::CMPgte
+        | OpCode::CMPulte
+        | OpCode::CMPugte
+        | OpCode::CMPIeq
+        | OpCode::CMPIlte
+        | OpCode::CMPIgte
+        | OpCode::CMPIulte
+        | OpCode::CMPIugte
+        | OpCode::JMP
+        | OpCode::JMP8
+        | OpCode::RET
+        | OpCode::BREAK => (),
+
+        OpCode::CALL => state.registers[4 ..].iter_mut().for_each(|register| *register = None),
+
+        // Anything else that writes a register leaves it unknown
+        _ =>
+        {
+            if let Some((register, false)) = destination
+            {
+                state.registers[register] = None;
+            }
+        }
+    }
+}
+
+/// Bytes pushed or popped by `PUSH` and `POP`.
+fn width(instruction: &Instruction) -> i64
+{
+    if instruction.postfixes.contains(&Postfix::X64("64"))
+    {
+        8
+    }
+    else
+    {
+        4
+    }
+}
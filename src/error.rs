@@ -0,0 +1,66 @@
+use crate::opcode::OpCode;
+
+/// Everything that can go wrong while decoding bytecode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error
+{
+    Completed,                     // No bytes left to decode
+    UnexpectedEnd,                 // Instruction was cut off by the end of the bytes
+    InvalidOpCode(u8),             // First byte does not encode one of the 55 opcodes
+    RunawayBreak,                  // BREAK 0
+    ImmediateNotSupported(OpCode), // Operand 1 index bit set on a direct operand
+    InvalidWidth(OpCode),          // Width bits of 0 where only 16, 32 or 64 bit data is allowed
+    InvalidGeneralPurposeRegister(u8),
+    InvalidDedicatedRegister(u8),
+    InvalidIndex(u64),         // Natural index with more natural bits than fit in it
+    Syntax(String),            // Assembly source that could not be parsed or encoded
+    AtLine(usize, Box<Error>), // Error on a given line of assembly source
+    InvalidAddress(u64),       // VM memory access outside of the loaded image and stack
+    OutOfMemory(u64),          // VM allocation of a size that does not fit in its heap
+    DivideByZero,              // DIV, DIVU, MOD or MODU with a zero divisor
+    NotEbc(u16),               // PE machine type other than IMAGE_FILE_MACHINE_EBC
+    NotEfiSubsystem(u16),      // PE subsystem that is not an EFI application or driver
+    ImageTooLarge(u64),        // PE image whose sections span more memory than makes sense to map
+    Output(String),            // Writing the output failed, e.g. because stdout was closed
+}
+
+impl std::fmt::Display for Error
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            Self::Completed => write!(f, "Completed Disassembly"),
+            Self::UnexpectedEnd => write!(f, "Unexpected end of byte stream"),
+            Self::InvalidOpCode(op_value) => write!(f, "Invalid OpCode: 0x{:02X}", op_value),
+            Self::RunawayBreak => write!(f, "Runaway program break (found 2 zeros in a row, BREAK 0)"),
+            Self::ImmediateNotSupported(op) => write!(f, "Immediate data not supported for {:?}", op),
+            Self::InvalidWidth(op) => write!(f, "Invalid data width for {:?}", op),
+            Self::InvalidGeneralPurposeRegister(index) => write!(f, "Invalid general purpose register: R{}", index),
+            Self::InvalidDedicatedRegister(index) => write!(f, "Invalid dedicated register: {}", index),
+            Self::InvalidIndex(index) => write!(f, "Invalid natural index: 0x{:X}", index),
+            Self::Syntax(msg) => write!(f, "{}", msg),
+            Self::AtLine(line, error) => write!(f, "Line {}: {}", line, error),
+            Self::InvalidAddress(address) => write!(f, "Invalid memory access: 0x{:016X}", address),
+            Self::OutOfMemory(size) => write!(f, "Out of VM memory allocating 0x{:X} bytes", size),
+            Self::DivideByZero => write!(f, "Divide by zero"),
+            Self::NotEbc(machine) => write!(f, "Machine type 0x{:04X} is not EBC (0x0EBC)", machine),
+            Self::NotEfiSubsystem(subsystem) =>
+            {
+                write!(f, "Subsystem {} is not an EFI application or driver", subsystem)
+            }
+            Self::Output(msg) => write!(f, "Failed to write output: {}", msg),
+            Self::ImageTooLarge(size) => write!(f, "Image is too large to load: 0x{:X} bytes", size),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error
+{
+    fn from(error: std::io::Error) -> Self
+    {
+        Self::Output(error.to_string())
+    }
+}
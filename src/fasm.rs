@@ -0,0 +1,466 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use pelite::image::{
+    IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_MEM_DISCARDABLE, IMAGE_SCN_MEM_EXECUTE,
+    IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_SHARED, IMAGE_SCN_MEM_WRITE, IMAGE_SUBSYSTEM_EFI_BOOT_SERVICE_DRIVER,
+    IMAGE_SUBSYSTEM_EFI_RUNTIME_DRIVER,
+};
+
+use crate::error::Error;
+use crate::image::{Image, Section};
+use crate::instruction::{Decoder, Instruction, Reference};
+use crate::label::Label;
+use crate::opcode::OpCode;
+use crate::options::{Options, Traversal};
+use crate::relocation::Relocation;
+use crate::strings::{string_at, Encoding};
+use crate::theme::Emit;
+use crate::traversal::decode_recursive;
+
+/// Strings shorter than this are emitted as plain bytes.
+const MINIMUM_STRING_LENGTH: usize = 4;
+
+/// Labels keyed by RVA. The RVA of a binary file is its offset.
+type Symbols = BTreeMap<u64, Label>;
+
+/// Decoded bytes that will be emitted as instructions.
+struct Block<'a>
+{
+    rva: u64,
+    bytes: &'a [u8],
+    instructions: Vec<Instruction>,
+}
+
+impl Block<'_>
+{
+    fn decode<'a>(rva: u64, bytes: &'a [u8], entry_point: usize, options: &Options) -> Result<Block<'a>, Error>
+    {
+        let instructions = match options.traversal
+        {
+            Traversal::Linear => Decoder::new(bytes).resilient(options.resilient).collect::<Result<Vec<_>, _>>()?,
+            Traversal::Recursive => decode_recursive(bytes, &[entry_point]),
+        };
+
+        Ok(Block { rva, bytes, instructions })
+    }
+
+    fn contains(&self, rva: u64) -> bool
+    {
+        (self.rva .. self.rva + self.bytes.len() as u64).contains(&rva)
+    }
+
+    fn starts_instruction(&self, rva: u64) -> bool
+    {
+        self.instructions.iter().any(|instruction| self.rva + instruction.offset as u64 == rva)
+    }
+}
+
+/// Writes a PE image as fasmg-ebc source that assembles back into the image.
+///
+/// Relative jumps, calls and `MOVREL`s that land on an instruction or within
+/// a data section refer to a label so that the source can be edited. Bytes in
+/// code sections that are not part of an instruction are kept with `db`.
+///
+/// fasmg only generates fixups for labels, and the relocated immediates and
+/// pointers are written as the numbers found in the image, so the base
+/// relocations are written out as they are instead. An image whose relocations
+/// live outside a `.reloc` section gets one at the end.
+pub fn write_image<W: Write>(writer: &mut W, options: &Options, image: &Image) -> Result<(), Error>
+{
+    let mut blocks = Vec::new();
+
+    for section in image.code_sections()
+    {
+        let entry_point = section.offset_of(image.entry_point).unwrap_or(0);
+
+        blocks.push(Block::decode(section.origin.rva, section.contents(), entry_point, options)?);
+    }
+
+    let data = image.sections.iter().filter(|section| !section.is_executable() && !is_reloc(section));
+    let symbols = symbols(&blocks, &data.collect::<Vec<_>>(), Some(image.entry_point));
+    let format = match image.subsystem
+    {
+        IMAGE_SUBSYSTEM_EFI_BOOT_SERVICE_DRIVER => "efiboot",
+        IMAGE_SUBSYSTEM_EFI_RUNTIME_DRIVER => "efiruntime",
+        _ => "efi",
+    };
+    let plain = &Options { theme: None, ..*options };
+
+    writeln!(writer, "{}\n", image.emit(plain))?;
+    writeln!(writer, "include 'ebc.inc'")?;
+    writeln!(writer, "include 'format.inc'\n")?;
+    writeln!(writer, "format peebc {}", format)?;
+
+    if let Some(label) = symbols.get(&image.entry_point).filter(|label| matches!(label, Label::Entry(_)))
+    {
+        writeln!(writer, "entry {}", symbol(label))?;
+    }
+
+    let mut blocks = blocks.iter();
+
+    for section in image.sections.iter()
+    {
+        writeln!(writer, "\n{}", section_directive(section))?;
+
+        if section.is_executable()
+        {
+            write_code(writer, options, blocks.next().unwrap(), &symbols)?;
+        }
+        else if is_reloc(section)
+        {
+            write_relocations(writer, &image.relocations)?;
+        }
+        else
+        {
+            write_data(writer, section.origin.rva, section.contents(), &symbols)?;
+
+            if section.virtual_size > section.bytes.len() as u64
+            {
+                writeln!(writer, "    rb {}", section.virtual_size - section.bytes.len() as u64)?;
+            }
+        }
+    }
+
+    if !image.relocations.is_empty() && !image.sections.iter().any(is_reloc)
+    {
+        writeln!(writer, "\nsection '.reloc' data readable discardable")?;
+        write_relocations(writer, &image.relocations)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a file containing only bytecode as fasmg-ebc source.
+pub fn write_binary<W: Write>(writer: &mut W, options: &Options, bytes: &[u8]) -> Result<(), Error>
+{
+    let block = Block::decode(0, bytes, 0, options)?;
+    let symbols = symbols(std::slice::from_ref(&block), &[], None);
+
+    writeln!(writer, "include 'ebc.inc'")?;
+    write_code(writer, options, &block, &symbols)
+}
+
+/// Renders an instruction as fasmg-ebc source. The displacement of a relative
+/// jump, call or `MOVREL` is replaced with `target` when there is one.
+pub fn instruction_source(instruction: &Instruction, target: Option<&str>, options: &Options) -> String
+{
+    let options = &Options { theme: None, bytecode: false, address: None, offsets: false, ..*options };
+    let mut instruction = instruction.clone();
+    let mut target_source = String::new();
+
+    if instruction.op.is_none()
+    {
+        let bytes = instruction.bytecode.iter().map(|byte| format!("0x{:02X}", byte)).collect::<Vec<_>>();
+        let comment = instruction.comment.map(|comment| format!("  ;; {}", comment)).unwrap_or_default();
+
+        return format!("db {}{}", bytes.join(", "), comment);
+    }
+
+    if let Some(target) = target
+    {
+        if instruction.op == Some(OpCode::MOVREL)
+        {
+            instruction.argument2 = None;
+            target_source = format!(", {}", target);
+        }
+        else
+        {
+            // Only R0 can be the operand of a jump or call with a target
+            instruction.operand1 = None;
+            instruction.argument1 = None;
+            target_source = format!(" {}", target);
+        }
+
+        instruction.comment = None;
+    }
+
+    format!("{}{}", compact_indexes(&instruction.emit(options)), target_source)
+}
+
+/// Bytes as `db` and `du` directives, with NUL terminated ASCII and UTF-16
+/// strings written as text.
+pub fn data_directives(bytes: &[u8]) -> Vec<String>
+{
+    let mut directives = Vec::new();
+    let mut pending = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len()
+    {
+        match string_at(&bytes[i ..], MINIMUM_STRING_LENGTH)
+        {
+            Some(string) =>
+            {
+                let directive = if string.encoding == Encoding::Utf16 { "du" } else { "db" };
+
+                flush_bytes(&mut directives, &mut pending);
+                directives.push(format!("{} {}, 0", directive, quote(&string.text)));
+                i += string.length();
+            }
+
+            None =>
+            {
+                pending.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    flush_bytes(&mut directives, &mut pending);
+    directives
+}
+
+fn write_code<W: Write>(writer: &mut W, options: &Options, block: &Block, symbols: &Symbols) -> Result<(), Error>
+{
+    let mut position = 0;
+
+    for instruction in block.instructions.iter()
+    {
+        // Padding that the decoder skipped
+        if instruction.offset > position
+        {
+            write_data(
+                writer,
+                block.rva + position as u64,
+                &block.bytes[position .. instruction.offset],
+                &Symbols::new(),
+            )?;
+        }
+
+        if let Some(label) = symbols.get(&(block.rva + instruction.offset as u64))
+        {
+            writeln!(writer, "\n{}:", symbol(label))?;
+        }
+
+        let target = instruction
+            .reference()
+            .and_then(|(_, target)| u64::try_from(block.rva as i64 + target).ok())
+            .and_then(|target| symbols.get(&target))
+            .map(symbol);
+
+        writeln!(writer, "    {}", instruction_source(instruction, target.as_deref(), options))?;
+        position = instruction.end();
+    }
+
+    if position < block.bytes.len()
+    {
+        write_data(writer, block.rva + position as u64, &block.bytes[position ..], &Symbols::new())?;
+    }
+
+    Ok(())
+}
+
+/// Writes bytes, splitting them wherever a label points into them.
+fn write_data<W: Write>(writer: &mut W, rva: u64, bytes: &[u8], symbols: &Symbols) -> Result<(), Error>
+{
+    let end = rva + bytes.len() as u64;
+    let mut position = rva;
+
+    for (&label_rva, label) in symbols.range(rva .. end)
+    {
+        for directive in data_directives(&bytes[(position - rva) as usize .. (label_rva - rva) as usize])
+        {
+            writeln!(writer, "    {}", directive)?;
+        }
+
+        writeln!(writer, "\n{}:", symbol(label))?;
+        position = label_rva;
+    }
+
+    for directive in data_directives(&bytes[(position - rva) as usize ..])
+    {
+        writeln!(writer, "    {}", directive)?;
+    }
+
+    Ok(())
+}
+
+/// Labels for every target that can be written as one: the first byte of an
+/// instruction or any byte of a data section.
+fn symbols(blocks: &[Block], data: &[&Section], entry_point: Option<u64>) -> Symbols
+{
+    let mut symbols = Symbols::new();
+
+    // A label replaces any label of a lower rank at the same RVA
+    let mut insert = |label: Label| {
+        let rank = symbols.entry(label.offset() as u64).or_insert(label);
+        *rank = (*rank).max(label);
+    };
+
+    let label_for = |rva: u64, reference: Reference| {
+        let offset = rva as usize;
+
+        if let Some(block) = blocks.iter().find(|block| block.contains(rva))
+        {
+            return block.starts_instruction(rva).then_some(match reference
+            {
+                Reference::Call => Label::Subroutine(offset),
+                Reference::Jump | Reference::Load => Label::Location(offset),
+            });
+        }
+
+        let in_data = data
+            .iter()
+            .any(|section| (section.origin.rva .. section.origin.rva + section.contents().len() as u64).contains(&rva));
+
+        in_data.then_some(Label::Data(offset))
+    };
+
+    for block in blocks
+    {
+        for instruction in block.instructions.iter()
+        {
+            let (reference, target) = match instruction.reference()
+            {
+                Some(reference) => reference,
+                None => continue,
+            };
+
+            if let Some(label) =
+                u64::try_from(block.rva as i64 + target).ok().and_then(|target| label_for(target, reference))
+            {
+                insert(label);
+            }
+        }
+    }
+
+    if let Some(Label::Location(offset)) = entry_point.and_then(|entry_point| label_for(entry_point, Reference::Jump))
+    {
+        insert(Label::Entry(offset));
+    }
+
+    symbols
+}
+
+/// The name of a label in source. `entry` is a directive of the PE format, so
+/// the entry point is named after the EDK2 convention instead.
+fn symbol(label: &Label) -> String
+{
+    match label
+    {
+        Label::Entry(_) => String::from("efi_main"),
+        _ => label.name(),
+    }
+}
+
+/// Base relocations as the blocks of a relocation directory, one per 4 KiB
+/// page.
+fn write_relocations<W: Write>(writer: &mut W, relocations: &[Relocation]) -> Result<(), Error>
+{
+    writeln!(writer, "    data 5")?; // IMAGE_DIRECTORY_ENTRY_BASERELOC
+
+    for page in relocations.chunk_by(|a, b| a.rva >> 12 == b.rva >> 12)
+    {
+        let mut entries = page
+            .iter()
+            .map(|relocation| format!("0x{:04X}", (relocation.kind as u64) << 12 | relocation.rva & 0xFFF))
+            .collect::<Vec<_>>();
+
+        // Blocks are padded to 32 bits with an IMAGE_REL_BASED_ABSOLUTE entry
+        if entries.len() % 2 != 0
+        {
+            entries.push(String::from("0x0000"));
+        }
+
+        writeln!(writer, "    dd 0x{:08X}, {}", page[0].rva & !0xFFF, 8 + entries.len() * 2)?;
+        writeln!(writer, "    dw {}", entries.join(", "))?;
+    }
+
+    writeln!(writer, "    end data")?;
+    Ok(())
+}
+
+fn is_reloc(section: &Section) -> bool
+{
+    section.name.trim_end_matches('\0') == ".reloc"
+}
+
+fn section_directive(section: &Section) -> String
+{
+    const FLAGS: [(u32, &str); 7] = [
+        (IMAGE_SCN_CNT_CODE, "code"),
+        (IMAGE_SCN_CNT_INITIALIZED_DATA, "data"),
+        (IMAGE_SCN_MEM_READ, "readable"),
+        (IMAGE_SCN_MEM_WRITE, "writeable"),
+        (IMAGE_SCN_MEM_EXECUTE, "executable"),
+        (IMAGE_SCN_MEM_SHARED, "shareable"),
+        (IMAGE_SCN_MEM_DISCARDABLE, "discardable"),
+    ];
+
+    let name = section.name.trim_end_matches('\0');
+
+    FLAGS
+        .iter()
+        .filter(|(flag, _)| section.characteristics & flag != 0)
+        .fold(format!("section '{}'", name), |directive, (_, flag)| directive + " " + flag)
+}
+
+/// Removes the spaces that Spore puts within natural indexes, since fasmg-ebc
+/// reads `(+1,+16)` as a single argument.
+fn compact_indexes(line: &str) -> String
+{
+    let mut depth = 0;
+
+    line.chars()
+        .filter(|character| {
+            match character
+            {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ' ' if depth > 0 => return false,
+                _ => (),
+            }
+
+            true
+        })
+        .collect()
+}
+
+/// Quotes printable runs of a string and writes control characters as bytes.
+fn quote(text: &str) -> String
+{
+    let mut pieces = Vec::new();
+    let mut quoted = String::new();
+
+    for character in text.chars()
+    {
+        if character.is_ascii_control()
+        {
+            if !quoted.is_empty()
+            {
+                pieces.push(format!("'{}'", quoted));
+                quoted.clear();
+            }
+
+            pieces.push(format!("0x{:02X}", character as u32));
+        }
+        else if character == '\''
+        {
+            quoted += "''";
+        }
+        else
+        {
+            quoted.push(character);
+        }
+    }
+
+    if !quoted.is_empty()
+    {
+        pieces.push(format!("'{}'", quoted));
+    }
+
+    pieces.join(", ")
+}
+
+fn flush_bytes(directives: &mut Vec<String>, pending: &mut Vec<u8>)
+{
+    for chunk in pending.chunks(16)
+    {
+        let bytes = chunk.iter().map(|byte| format!("0x{:02X}", byte)).collect::<Vec<_>>();
+
+        directives.push(format!("db {}", bytes.join(", ")));
+    }
+
+    pending.clear();
+}
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::graph::Graph;
+use crate::instruction::{Instruction, Reference};
+use crate::label::{Label, Labels};
+use crate::options::Options;
+use crate::theme::*;
+
+/// Code reached from a call target or the entry point without following calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function
+{
+    pub name: String,
+    pub offset: usize,              // Offset of the first instruction
+    pub end: usize,                 // Offset after the last reachable instruction
+    pub instructions: usize,        // Number of reachable instructions
+    pub calls: Vec<usize>,          // Targets of relative calls
+    pub external_calls: Vec<usize>, // Offsets of CALLEX instructions, which are not followed
+}
+
+/// The separator printed before the first instruction of a function.
+impl Emit for Function
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        color_comment(format!("; ---- function {} ----", self.name), options)
+    }
+}
+
+/// Functions found by recursive descent.
+#[derive(Debug, Default)]
+pub struct Functions
+{
+    functions: BTreeMap<usize, Function>,
+}
+
+impl Functions
+{
+    /// Follows relative calls starting at the entry point, or at the first
+    /// instruction when there is no entry point.
+    pub fn discover(instructions: &[Instruction], labels: &Labels, entry_point: Option<usize>) -> Self
+    {
+        let graph = Graph::new(instructions, labels);
+        let mut functions = BTreeMap::new();
+        let mut pending = entry_point
+            .or_else(|| instructions.first().map(|instruction| instruction.offset))
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        while let Some(offset) = pending.pop()
+        {
+            if functions.contains_key(&offset)
+            {
+                continue;
+            }
+
+            let blocks = graph.reachable(offset);
+
+            if blocks.is_empty()
+            {
+                continue;
+            }
+
+            let reachable = blocks.iter().flat_map(|block| &instructions[block.instructions.clone()]);
+            let mut calls = BTreeSet::new();
+            let mut external_calls = Vec::new();
+            let mut count = 0;
+            let mut end = offset;
+
+            for instruction in reachable
+            {
+                if instruction.is_native_call()
+                {
+                    external_calls.push(instruction.offset);
+                }
+                else if let Some((Reference::Call, target)) = instruction.reference()
+                {
+                    if let Ok(target) = usize::try_from(target)
+                    {
+                        calls.insert(target);
+                    }
+                }
+
+                count += 1;
+                end = end.max(instruction.end());
+            }
+
+            pending.extend(calls.iter().rev().filter(|target| graph.get(**target).is_some()));
+
+            functions.insert(offset, Function {
+                name: labels.get(offset).copied().unwrap_or(Label::Subroutine(offset)).name(),
+                offset,
+                end,
+                instructions: count,
+                calls: calls.into_iter().collect(),
+                external_calls,
+            });
+        }
+
+        Self { functions }
+    }
+
+    pub fn get(&self, offset: usize) -> Option<&Function>
+    {
+        self.functions.get(&offset)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Function>
+    {
+        self.functions.values()
+    }
+}
+
+/// The function table printed after the listing.
+impl Emit for Functions
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        let width = self.iter().map(|function| function.name.len()).max().unwrap_or(0).max("Function".len());
+        let mut lines = vec![
+            String::from("; ---- functions ----"),
+            format!("; {:<width$}  Start  End    Instructions  Calls  External", "Function"),
+        ];
+
+        for function in self.iter()
+        {
+            let calls = function
+                .calls
+                .iter()
+                .map(|target| self.get(*target).map_or_else(|| format!("{:04X}", target), |f| f.name.clone()))
+                .collect::<Vec<_>>();
+
+            lines.push(format!(
+                "; {:<width$}  {:04X}   {:04X}   {:<12}  {:<5}  {}{}",
+                function.name,
+                function.offset,
+                function.end,
+                function.instructions,
+                function.calls.len(),
+                function.external_calls.len(),
+                if calls.is_empty() { String::new() } else { format!("  -> {}", calls.join(", ")) },
+            ));
+        }
+
+        color_comment(lines.join("\n"), options)
+    }
+}
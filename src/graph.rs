@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use crate::instruction::{Instruction, Postfix};
+use crate::label::{Label, Labels};
+use crate::opcode::OpCode;
+use crate::options::Options;
+use crate::theme::Emit;
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge
+{
+    Next(usize),     // Falls through into the block that follows
+    Jump(usize),     // Unconditional jump
+    Taken(usize),    // Conditional jump when the condition holds
+    NotTaken(usize), // Conditional jump when it does not
+}
+
+impl Edge
+{
+    pub fn target(&self) -> usize
+    {
+        match self
+        {
+            Self::Next(target) | Self::Jump(target) | Self::Taken(target) | Self::NotTaken(target) => *target,
+        }
+    }
+}
+
+/// A run of instructions that is only entered at the top and only left at the
+/// bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock
+{
+    pub offset: usize,              // Offset of the first instruction
+    pub instructions: Range<usize>, // Indexes into the decoded instructions
+    pub edges: Vec<Edge>,
+}
+
+/// Where an instruction sends control next.
+pub(crate) enum Flow
+{
+    Next,
+    Jump(Option<i64>),   // Unresolved when the target is in a register
+    Branch(Option<i64>), // Conditional jump
+    Return,
+}
+
+pub(crate) fn flow(instruction: &Instruction) -> Flow
+{
+    let target = instruction.reference().map(|(_, target)| target);
+
+    match instruction.op
+    {
+        Some(OpCode::JMP8 | OpCode::JMP) =>
+        {
+            let conditional = instruction
+                .postfixes
+                .iter()
+                .any(|postfix| matches!(postfix, Postfix::Opcode("cc" | "cs") | Postfix::Plain("cc" | "cs")));
+
+            if conditional
+            {
+                Flow::Branch(target)
+            }
+            else
+            {
+                Flow::Jump(target)
+            }
+        }
+
+        Some(OpCode::RET) => Flow::Return,
+
+        _ => Flow::Next,
+    }
+}
+
+/// The control-flow graph of decoded instructions. Calls do not end a block
+/// since control comes back to the instruction after them.
+#[derive(Debug, Default)]
+pub struct Graph
+{
+    blocks: BTreeMap<usize, BasicBlock>,
+}
+
+impl Graph
+{
+    /// Splits instructions into blocks at every label and after every jump or
+    /// `RET`.
+    pub fn new(instructions: &[Instruction], labels: &Labels) -> Self
+    {
+        let mut leaders = labels.iter().map(|label| label.offset()).collect::<BTreeSet<_>>();
+
+        for (i, instruction) in instructions.iter().enumerate()
+        {
+            if i == 0 || !matches!(flow(&instructions[i - 1]), Flow::Next)
+            {
+                leaders.insert(instruction.offset);
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut start = 0;
+
+        for i in 0 .. instructions.len()
+        {
+            let next = instructions.get(i + 1).map(|instruction| instruction.offset);
+
+            if next.is_some_and(|next| !leaders.contains(&next))
+            {
+                continue;
+            }
+
+            let offset = instructions[start].offset;
+            blocks.insert(offset, BasicBlock { offset, instructions: start .. i + 1, edges: Vec::new() });
+            start = i + 1;
+        }
+
+        let starts = blocks.keys().copied().collect::<BTreeSet<_>>();
+        let block_at = |target: i64| usize::try_from(target).ok().filter(|target| starts.contains(target));
+
+        for block in blocks.values_mut()
+        {
+            let last = &instructions[block.instructions.end - 1];
+            let next = block_at(last.end() as i64);
+
+            block.edges = match flow(last)
+            {
+                Flow::Next => next.map(Edge::Next).into_iter().collect(),
+                Flow::Jump(target) => target.and_then(block_at).map(Edge::Jump).into_iter().collect(),
+                Flow::Branch(target) =>
+                {
+                    let taken = target.and_then(block_at).map(Edge::Taken);
+
+                    taken.into_iter().chain(next.map(Edge::NotTaken)).collect()
+                }
+                Flow::Return => Vec::new(),
+            };
+        }
+
+        Self { blocks }
+    }
+
+    pub fn get(&self, offset: usize) -> Option<&BasicBlock>
+    {
+        self.blocks.get(&offset)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BasicBlock>
+    {
+        self.blocks.values()
+    }
+
+    /// Every block reachable from `offset` without following calls, in order
+    /// of offset.
+    pub fn reachable(&self, offset: usize) -> Vec<&BasicBlock>
+    {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![offset];
+
+        while let Some(offset) = pending.pop()
+        {
+            if let Some(block) = self.blocks.get(&offset).filter(|_| seen.insert(offset))
+            {
+                pending.extend(block.edges.iter().map(|edge| edge.target()));
+            }
+        }
+
+        seen.iter().filter_map(|offset| self.blocks.get(offset)).collect()
+    }
+
+    /// The graph of the function starting at `offset` in Graphviz DOT, with
+    /// each node containing the instructions of a block.
+    pub fn dot(&self, offset: usize, instructions: &[Instruction], labels: &Labels, options: &Options) -> String
+    {
+        let options = &Options { bytecode: false, address: None, ..*options };
+        let name = |offset: usize| labels.get(offset).copied().unwrap_or(Label::Location(offset)).name();
+        let mut lines = vec![
+            format!("digraph \"{}\" {{", name(offset)),
+            String::from("    node [shape=box, fontname=\"monospace\"];"),
+        ];
+
+        for block in self.reachable(offset)
+        {
+            let mut text = labels.get(block.offset).map(|label| label.emit(options)).into_iter().collect::<Vec<_>>();
+            text.extend(instructions[block.instructions.clone()].iter().map(|instruction| instruction.emit(options)));
+
+            let rows = text.iter().map(|line| html(line) + "<br align=\"left\"/>").collect::<String>();
+
+            lines.push(format!("    \"{}\" [label=<{}>];", name(block.offset), rows));
+        }
+
+        for block in self.reachable(offset)
+        {
+            for edge in block.edges.iter()
+            {
+                let style = match edge
+                {
+                    Edge::Taken(_) => " [color=\"green\"]",
+                    Edge::NotTaken(_) => " [color=\"red\"]",
+                    Edge::Next(_) | Edge::Jump(_) => "",
+                };
+
+                lines.push(format!("    \"{}\" -> \"{}\"{};", name(block.offset), name(edge.target()), style));
+            }
+        }
+
+        lines.push(String::from("}"));
+        lines.join("\n")
+    }
+}
+
+/// Converts themed text into a Graphviz HTML-like label. Like a terminal,
+/// each true color escape code sets the font color until the next one or a
+/// reset.
+fn html(line: &str) -> String
+{
+    let mut html = String::new();
+    let mut rest = line;
+    let mut open = false;
+
+    while let Some(start) = rest.find('\x1B')
+    {
+        html += &escape(&rest[.. start]);
+        rest = &rest[start ..];
+
+        let end = match rest.find('m')
+        {
+            Some(end) => end,
+            None => break,
+        };
+
+        if open
+        {
+            html += "</font>";
+            open = false;
+        }
+
+        if let ["38", "2", r, g, b] = rest[2 .. end].split(';').collect::<Vec<_>>()[..]
+        {
+            let [r, g, b] = [r, g, b].map(|value| value.parse::<u8>().unwrap_or(0));
+            html += &format!("<font color=\"#{:02X}{:02X}{:02X}\">", r, g, b);
+            open = true;
+        }
+
+        rest = &rest[end + 1 ..];
+    }
+
+    html += &escape(rest);
+
+    if open
+    {
+        html += "</font>";
+    }
+
+    html
+}
+
+fn escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,329 @@
+use std::ops::Range;
+
+use pelite::base_relocs::BaseRelocs;
+use pelite::image::{
+    IMAGE_NT_OPTIONAL_HDR32_MAGIC, IMAGE_SCN_CNT_CODE, IMAGE_SCN_MEM_EXECUTE, IMAGE_SUBSYSTEM_EFI_APPLICATION,
+    IMAGE_SUBSYSTEM_EFI_BOOT_SERVICE_DRIVER, IMAGE_SUBSYSTEM_EFI_ROM, IMAGE_SUBSYSTEM_EFI_RUNTIME_DRIVER,
+};
+use pelite::pe64::headers::{SectionHeader, SectionHeaders};
+use pelite::{pe32, pe64};
+
+use crate::address::Origin;
+use crate::error::Error;
+use crate::options::Options;
+use crate::relocation::Relocation;
+use crate::theme::*;
+
+pub const IMAGE_FILE_MACHINE_EBC: u16 = 0x0EBC;
+
+const EXECUTABLE: u32 = IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE;
+const MAX_MAPPED_SIZE: u64 = 0x1000_0000; // Far more than any EFI image needs
+
+/// A section of a PE image.
+#[derive(Debug, Clone)]
+pub struct Section<'a>
+{
+    pub name: String,
+    pub origin: Origin,       // Location of the section within the file and image
+    pub bytes: &'a [u8],      // Raw data of the section, including file alignment padding
+    pub virtual_size: u64,    // Size of the section once loaded, which may be zero
+    pub characteristics: u32, // Flags from the section header
+}
+
+impl<'a> Section<'a>
+{
+    pub fn is_executable(&self) -> bool
+    {
+        self.characteristics & EXECUTABLE != 0
+    }
+
+    /// The raw data without file alignment padding.
+    pub fn contents(&self) -> &'a [u8]
+    {
+        match self.virtual_size as usize
+        {
+            0 => self.bytes,
+            size => &self.bytes[.. size.min(self.bytes.len())],
+        }
+    }
+
+    /// The offset within the section of an RVA that points into it.
+    pub fn offset_of(&self, rva: u64) -> Option<usize>
+    {
+        let offset = rva.checked_sub(self.origin.rva)?;
+
+        (offset < self.bytes.len() as u64).then_some(offset as usize)
+    }
+
+    /// The offsets of the part of the contents within a range of RVAs, if
+    /// there is any.
+    pub fn offsets(&self, range: &Range<u64>) -> Option<Range<usize>>
+    {
+        let start = range.start.max(self.origin.rva);
+        let end = range.end.min(self.origin.rva + self.contents().len() as u64);
+
+        (start < end).then(|| (start - self.origin.rva) as usize .. (end - self.origin.rva) as usize)
+    }
+
+    /// The part of the contents within a range of RVAs, if there is any.
+    pub fn slice(&self, range: &Range<u64>) -> Option<Section<'a>>
+    {
+        let offsets = self.offsets(range)?;
+        let offset = offsets.start as u64;
+
+        Some(Section {
+            name: self.name.clone(),
+            origin: Origin {
+                file_offset: self.origin.file_offset + offset,
+                rva: self.origin.rva + offset,
+                ..self.origin
+            },
+            bytes: &self.contents()[offsets],
+            virtual_size: 0,
+            characteristics: self.characteristics,
+        })
+    }
+}
+
+impl Emit for Section<'_>
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        let banner =
+            format!("; ---- section {} (RVA {:08X}, {} bytes) ----", self.name, self.origin.rva, self.bytes.len());
+
+        color_comment(banner, options)
+    }
+}
+
+/// The parts of a PE image that are needed for disassembly.
+#[derive(Debug, Clone)]
+pub struct Image<'a>
+{
+    pub sections: Vec<Section<'a>>,   // Every section in header order
+    pub relocations: Vec<Relocation>, // Base relocations in order of RVA
+    pub entry_point: u64,             // AddressOfEntryPoint as an RVA
+    pub image_base: u64,
+    pub pe32: bool,     // PE32 rather than PE32+ optional header
+    pub machine: u16,   // Machine from the file header
+    pub subsystem: u16, // Subsystem from the optional header
+}
+
+impl<'a> Image<'a>
+{
+    /// Parses a PE32 or PE32+ image depending on its optional header magic.
+    pub fn parse(bytes: &'a [u8]) -> pelite::Result<Self>
+    {
+        if optional_header_magic(bytes) == Some(IMAGE_NT_OPTIONAL_HDR32_MAGIC)
+        {
+            use pelite::pe32::Pe;
+
+            let file = pe32::PeFile::from_bytes(bytes)?;
+            let optional_header = file.optional_header();
+            let image_base = optional_header.ImageBase as u64;
+
+            Ok(Self {
+                sections: sections(file.section_headers(), image_base, |section_header| {
+                    file.get_section_bytes(section_header)
+                })?,
+                relocations: relocations(file.base_relocs())?,
+                entry_point: optional_header.AddressOfEntryPoint as u64,
+                image_base,
+                pe32: true,
+                machine: file.file_header().Machine,
+                subsystem: optional_header.Subsystem,
+            })
+        }
+        else
+        {
+            use pelite::pe64::Pe;
+
+            let file = pe64::PeFile::from_bytes(bytes)?;
+            let optional_header = file.optional_header();
+            let image_base = optional_header.ImageBase;
+
+            Ok(Self {
+                sections: sections(file.section_headers(), image_base, |section_header| {
+                    file.get_section_bytes(section_header)
+                })?,
+                relocations: relocations(file.base_relocs())?,
+                entry_point: optional_header.AddressOfEntryPoint as u64,
+                image_base,
+                pe32: false,
+                machine: file.file_header().Machine,
+                subsystem: optional_header.Subsystem,
+            })
+        }
+    }
+
+    /// Sections that contain code, which are the ones that get disassembled.
+    pub fn code_sections(&self) -> impl Iterator<Item = &Section<'a>>
+    {
+        self.sections.iter().filter(|section| section.is_executable())
+    }
+
+    /// The image as the loader lays it out in memory, with every section at
+    /// its RVA and the relocations applied as if it were loaded at `base`.
+    /// Images that would be larger than `MAX_MAPPED_SIZE` are rejected.
+    pub fn map(&self, base: u64) -> Result<Vec<u8>, Error>
+    {
+        let end =
+            |section: &Section| section.origin.rva.saturating_add(section.virtual_size.max(section.bytes.len() as u64));
+        let size = self.sections.iter().map(end).max().unwrap_or(0);
+
+        if size > MAX_MAPPED_SIZE
+        {
+            return Err(Error::ImageTooLarge(size));
+        }
+
+        let mut memory = vec![0; size as usize];
+
+        for section in self.sections.iter()
+        {
+            let start = section.origin.rva as usize;
+            let contents = section.contents();
+
+            memory[start .. start + contents.len()].copy_from_slice(contents);
+        }
+
+        let delta = base.wrapping_sub(self.image_base);
+
+        for relocation in self.relocations.iter()
+        {
+            let start = relocation.rva as usize;
+
+            if let Some(bytes) = memory.get_mut(start .. start + relocation.size())
+            {
+                relocation.apply(bytes, delta);
+            }
+        }
+
+        Ok(memory)
+    }
+
+    /// Reasons to believe that the image does not contain EBC.
+    pub fn validate(&self) -> Vec<Error>
+    {
+        let mut errors = Vec::new();
+
+        if self.machine != IMAGE_FILE_MACHINE_EBC
+        {
+            errors.push(Error::NotEbc(self.machine));
+        }
+
+        if !matches!(
+            self.subsystem,
+            IMAGE_SUBSYSTEM_EFI_APPLICATION
+                | IMAGE_SUBSYSTEM_EFI_BOOT_SERVICE_DRIVER
+                | IMAGE_SUBSYSTEM_EFI_RUNTIME_DRIVER
+        )
+        {
+            errors.push(Error::NotEfiSubsystem(self.subsystem));
+        }
+
+        errors
+    }
+}
+
+/// The header block printed before the listing of a PE image.
+impl Emit for Image<'_>
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        let lines = [
+            format!("; Format:      {}", if self.pe32 { "PE32" } else { "PE32+" }),
+            format!("; Machine:     0x{:04X} ({})", self.machine, machine_name(self.machine)),
+            format!("; Subsystem:   {} ({})", self.subsystem, subsystem_name(self.subsystem)),
+            format!("; Image Base:  {:016X}", self.image_base),
+            format!("; Entry Point: {:08X}", self.entry_point),
+        ];
+
+        color_comment(lines.join("\n"), options)
+    }
+}
+
+pub fn machine_name(machine: u16) -> &'static str
+{
+    match machine
+    {
+        IMAGE_FILE_MACHINE_EBC => "EBC",
+        0x014C => "i386",
+        0x8664 => "x64",
+        0x01C0 | 0x01C4 => "ARM",
+        0xAA64 => "ARM64",
+        0x5032 => "RISC-V 32",
+        0x5064 => "RISC-V 64",
+        _ => "Unknown",
+    }
+}
+
+pub fn subsystem_name(subsystem: u16) -> &'static str
+{
+    match subsystem
+    {
+        IMAGE_SUBSYSTEM_EFI_APPLICATION => "EFI application",
+        IMAGE_SUBSYSTEM_EFI_BOOT_SERVICE_DRIVER => "EFI boot service driver",
+        IMAGE_SUBSYSTEM_EFI_RUNTIME_DRIVER => "EFI runtime driver",
+        IMAGE_SUBSYSTEM_EFI_ROM => "EFI ROM",
+        _ => "Not EFI",
+    }
+}
+
+/// The `Magic` field of the optional header, which follows the PE signature
+/// and file header.
+fn optional_header_magic(bytes: &[u8]) -> Option<u16>
+{
+    const E_LFANEW: usize = 0x3C;
+    const MAGIC: usize = 4 + 20;
+
+    let read_u32 = |at: usize| Some(u32::from_le_bytes(bytes.get(at .. at + 4)?.try_into().ok()?));
+    let nt_headers = read_u32(E_LFANEW)? as usize;
+    let magic = bytes.get(nt_headers.checked_add(MAGIC)? ..)?.get(.. 2)?;
+
+    Some(u16::from_le_bytes([magic[0], magic[1]]))
+}
+
+/// Walks the section headers of either kind of image.
+fn sections<'a>(
+    section_headers: &SectionHeaders,
+    image_base: u64,
+    get_section_bytes: impl Fn(&SectionHeader) -> pelite::Result<&'a [u8]>,
+) -> pelite::Result<Vec<Section<'a>>>
+{
+    let mut sections = Vec::new();
+
+    for section_header in section_headers
+    {
+        sections.push(Section {
+            name: String::from_utf8_lossy(section_header.name_bytes()).to_string(),
+            origin: Origin {
+                file_offset: section_header.PointerToRawData as u64,
+                rva: section_header.VirtualAddress as u64,
+                image_base,
+            },
+            bytes: get_section_bytes(section_header)?,
+            virtual_size: section_header.VirtualSize as u64,
+            characteristics: section_header.Characteristics,
+        });
+    }
+
+    Ok(sections)
+}
+
+/// Flattens the base relocation blocks, which are empty when the image has no
+/// relocation directory.
+fn relocations(base_relocs: pelite::Result<BaseRelocs>) -> pelite::Result<Vec<Relocation>>
+{
+    let mut relocations = Vec::new();
+
+    match base_relocs
+    {
+        Ok(base_relocs) => base_relocs.for_each(|rva, kind| relocations.push(Relocation { rva: rva as u64, kind })),
+        Err(error) if error.is_null() => (),
+        Err(error) => return Err(error),
+    }
+
+    relocations.sort_by_key(|relocation| relocation.rva);
+
+    Ok(relocations)
+}
@@ -70,52 +70,487 @@ Instruction Type Breakdown:
 
 use arrayvec::ArrayVec;
 
+use crate::address::emit_address;
 use crate::argument::*;
 use crate::bits::*;
+use crate::error::Error;
 use crate::opcode::*;
 use crate::operand::*;
 use crate::options::Options;
 use crate::theme::*;
 
-fn read_value<T: Iterator<Item = u8>, const WIDTH: usize>(bytes: &mut T) -> Result<[u8; WIDTH], String>
+/// A single postfix appended to an instruction's base name, i.e. the `64` and
+/// `a` in `CALL64a`. The variant determines how the postfix is colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Postfix
+{
+    Opcode(&'static str),
+    Plain(&'static str),
+    X8(&'static str),
+    X16(&'static str),
+    X32(&'static str),
+    X64(&'static str),
+}
+
+impl Postfix
+{
+    pub fn text(&self) -> &'static str
+    {
+        match self
+        {
+            Self::Opcode(text)
+            | Self::Plain(text)
+            | Self::X8(text)
+            | Self::X16(text)
+            | Self::X32(text)
+            | Self::X64(text) => text,
+        }
+    }
+}
+
+impl Emit for Postfix
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        match self
+        {
+            Self::Opcode(text) => color_opcode(text.to_string(), options),
+            Self::Plain(text) => text.to_string(),
+            Self::X8(text) => color_x8(text.to_string(), options),
+            Self::X16(text) => color_x16(text.to_string(), options),
+            Self::X32(text) => color_x32(text.to_string(), options),
+            Self::X64(text) => color_x64(text.to_string(), options),
+        }
+    }
+}
+
+/// How an instruction refers to a location relative to itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference
+{
+    Jump, // JMP8, JMP32, JMP64
+    Call, // CALL32, CALL64
+    Load, // MOVREL
+}
+
+/// A decoded instruction, independent of how it will be rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction
+{
+    pub offset: usize,              // Byte offset of the instruction within the input
+    pub bytecode: ArrayVec<u8, 18>, // Raw bytes that make up the instruction
+    pub op: Option<OpCode>,         // None for DB pseudo-instructions
+    pub name: String,               // Base mnemonic without postfixes (e.g. "CMPI")
+    pub postfixes: ArrayVec<Postfix, 4>,
+    pub operand1: Option<Operand>,
+    pub argument1: Option<Argument>,
+    pub operand2: Option<Operand>,
+    pub argument2: Option<Argument>,
+    pub comment: Option<String>,
+}
+
+impl Instruction
+{
+    /// An instruction with no bytes, postfixes, operands or arguments.
+    pub fn new(op: OpCode) -> Self
+    {
+        Self {
+            offset: 0,
+            bytecode: ArrayVec::new(),
+            op: Some(op),
+            name: format!("{:?}", op),
+            postfixes: ArrayVec::new(),
+            operand1: None,
+            argument1: None,
+            operand2: None,
+            argument2: None,
+            comment: None,
+        }
+    }
+
+    /// A `DB` pseudo-instruction for a byte that could not be decoded.
+    pub fn data(offset: usize, byte: u8, comment: String) -> Self
+    {
+        Self {
+            offset,
+            bytecode: [byte].into_iter().collect(),
+            op: None,
+            name: String::from("DB"),
+            postfixes: ArrayVec::new(),
+            operand1: None,
+            argument1: Some(Argument::Data(byte)),
+            operand2: None,
+            argument2: None,
+            comment: Some(comment),
+        }
+    }
+
+    /// A `DB` pseudo-instruction for a run of bytes that is not code, with its
+    /// printable ASCII characters as the comment.
+    pub fn data_block(offset: usize, bytes: &[u8]) -> Self
+    {
+        let ascii = bytes
+            .iter()
+            .map(|byte| {
+                if byte.is_ascii_graphic() || *byte == b' '
+                {
+                    *byte as char
+                }
+                else
+                {
+                    '.'
+                }
+            })
+            .collect::<String>();
+
+        Self {
+            offset,
+            bytecode: bytes.iter().cloned().collect(),
+            op: None,
+            name: String::from("DB"),
+            postfixes: ArrayVec::new(),
+            operand1: None,
+            argument1: None,
+            operand2: None,
+            argument2: None,
+            comment: Some(ascii),
+        }
+    }
+
+    /// Number of bytes the instruction occupies.
+    pub fn length(&self) -> usize
+    {
+        self.bytecode.len()
+    }
+
+    /// Offset of the byte directly after the instruction.
+    pub fn end(&self) -> usize
+    {
+        self.offset + self.length()
+    }
+
+    /// Resolves the target of a relative jump, call or `MOVREL` to an offset
+    /// relative to the start of the decoded bytes.
+    ///
+    /// Targets that depend on the runtime value of a register other than R0
+    /// cannot be resolved statically.
+    pub fn reference(&self) -> Option<(Reference, i64)>
+    {
+        let end = self.end() as i64;
+        let immediate = self.argument1.and_then(|arg| arg.immediate());
+
+        match self.op?
+        {
+            OpCode::JMP8 => Some((Reference::Jump, end + immediate? * 2)), // Jumps in 16 bit words
+
+            OpCode::JMP | OpCode::CALL =>
+            {
+                let byte1_bits = bits_rev(self.bytecode[1]);
+                let is_native_call = self.is_native_call();
+                let is_relative_address = byte1_bits[4];
+                let is_64_bit = self.operand1.is_none();
+
+                // CALL64 is always decoded as an absolute address
+                if !is_relative_address || is_native_call || (is_64_bit && self.op == Some(OpCode::CALL))
+                {
+                    return None;
+                }
+
+                // R0 is treated as zero when used as a direct operand
+                match self.operand1
+                {
+                    None | Some(Operand::GeneralPurpose { register_index: 0, indirect: false }) => (),
+                    _ => return None,
+                }
+
+                let reference = if self.op == Some(OpCode::CALL) { Reference::Call } else { Reference::Jump };
+
+                Some((reference, end + immediate.unwrap_or(0)))
+            }
+
+            OpCode::MOVREL => Some((Reference::Load, end + self.argument2?.immediate()?)),
+
+            _ => None,
+        }
+    }
+
+    /// The address encoded in the 64 bit immediate of an absolute `JMP64` or
+    /// `CALL64`, which is a virtual address when the image is loaded at its
+    /// preferred base.
+    pub fn absolute_reference(&self) -> Option<(Reference, u64)>
+    {
+        let is_relative_address = bits_rev(*self.bytecode.get(1)?)[4];
+
+        match self.op?
+        {
+            _ if self.operand1.is_some() || self.is_native_call() => None,
+            OpCode::JMP if !is_relative_address => Some((Reference::Jump, self.argument1?.immediate()? as u64)),
+            OpCode::CALL => Some((Reference::Call, self.argument1?.immediate()? as u64)),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction is a `CALLEX` to native code.
+    pub fn is_native_call(&self) -> bool
+    {
+        self.op == Some(OpCode::CALL) && bits_rev(self.bytecode[1])[5]
+    }
+
+    /// The full mnemonic including postfixes, without any coloring.
+    pub fn mnemonic(&self) -> String
+    {
+        self.postfixes.iter().fold(self.name.clone(), |name, postfix| name + postfix.text())
+    }
+
+    pub fn emit_mnemonic(&self, options: &Options) -> String
+    {
+        self.postfixes
+            .iter()
+            .fold(color_opcode(self.name.clone(), options), |name, postfix| name + &postfix.emit(options))
+    }
+}
+
+impl Emit for Instruction
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        let mut line = String::new();
+
+        if let Some(address) = emit_address(self.offset, options)
+        {
+            line += &address;
+            line += "  ";
+        }
+
+        if options.bytecode
+        {
+            const TWO_CHARS_AND_A_SPACE: usize = 3;
+            let mut bytecode_output = String::with_capacity(self.bytecode.len() * TWO_CHARS_AND_A_SPACE);
+
+            for byte in self.bytecode.iter()
+            {
+                bytecode_output += format!("{:<02X?} ", byte).as_str();
+            }
+
+            bytecode_output = color_bytecode(bytecode_output, options);
+
+            line += &format!("{:>84} ", bytecode_output);
+        }
+
+        line += &self.emit_mnemonic(options);
+
+        // Data blocks list their bytes in place of an argument
+        if self.op.is_none() && self.argument1.is_none()
+        {
+            let bytes = self.bytecode.iter().map(|byte| color_immediate(format!("0x{:02X}", byte), options));
+
+            line += &format!(" {}", bytes.collect::<Vec<_>>().join(", "));
+        }
+
+        if let Some(op1) = &self.operand1
+        {
+            line += &format!(" {}", op1.emit(options));
+        }
+
+        if let Some(arg1) = &self.argument1
+        {
+            let text = arg1.emit(options);
+
+            match arg1
+            {
+                Argument::Index16(_) | Argument::Index32(_) | Argument::Index64(_) => line += &text,
+                _ => line += &format!(" {}", text),
+            }
+        }
+
+        if self.operand2.is_some() || self.argument2.is_some()
+        {
+            line += ",";
+        }
+
+        if let Some(op2) = &self.operand2
+        {
+            line += &format!(" {}", op2.emit(options));
+        }
+
+        if let Some(arg2) = &self.argument2
+        {
+            match arg2
+            {
+                Argument::Index16(_) | Argument::Index32(_) | Argument::Index64(_) =>
+                {
+                    if self.operand2.is_none()
+                    {
+                        line += " ";
+                    }
+
+                    line += &arg2.emit(options);
+                }
+
+                _ => line += &format!(" {}", arg2.emit(options)),
+            }
+        }
+
+        let mut comments = self.comment.iter().cloned().collect::<Vec<_>>();
+
+        if options.offsets
+        {
+            let offsets = [self.argument1, self.argument2]
+                .iter()
+                .flatten()
+                .filter_map(|argument| argument.natural_index(options.pointer_size))
+                .map(|index| format!("{:+} / {:+}", index.resolve(4), index.resolve(8)))
+                .collect::<Vec<_>>();
+
+            if !offsets.is_empty()
+            {
+                comments.push(format!("{} (32 / 64-bit)", offsets.join(", ")));
+            }
+        }
+
+        if !comments.is_empty()
+        {
+            line += &color_comment(format!("  ;; {}", comments.join(", ")), options);
+        }
+
+        line
+    }
+}
+
+/// Decodes every instruction in a byte slice, tracking the offset of each.
+///
+/// A resilient decoder never stops at bytes it cannot decode. Instead, it
+/// yields a `DB` pseudo-instruction for the first byte and resynchronises at
+/// the byte after it.
+pub struct Decoder<'a>
+{
+    bytes: &'a [u8],
+    position: usize,
+    resilient: bool,
+}
+
+impl<'a> Decoder<'a>
+{
+    pub fn new(bytes: &'a [u8]) -> Self
+    {
+        Self { bytes, position: 0, resilient: false }
+    }
+
+    pub fn resilient(mut self, resilient: bool) -> Self
+    {
+        self.resilient = resilient;
+        self
+    }
+
+    /// Starts decoding at `position` rather than the first byte.
+    pub fn seek(mut self, position: usize) -> Self
+    {
+        self.position = position;
+        self
+    }
+
+    /// Offset of the next byte to be decoded.
+    pub fn position(&self) -> usize
+    {
+        self.position
+    }
+}
+
+/// Decodes exactly the instruction at `position`. Unlike `Decoder`, zeros are
+/// not skipped as padding: they give `Ok(None)`.
+pub fn decode_at(bytes: &[u8], position: usize) -> Result<Option<Instruction>, Error>
+{
+    let mut bytes = bytes.get(position ..).unwrap_or_default().iter().cloned().peekable();
+    let instruction = OpCode::decode(&mut bytes)?;
+
+    Ok(instruction.map(|instruction| Instruction { offset: position, ..instruction }))
+}
+
+impl Iterator for Decoder<'_>
+{
+    type Item = Result<Instruction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        while self.position < self.bytes.len()
+        {
+            let mut bytes = self.bytes[self.position ..].iter().cloned().peekable();
+            let remaining = bytes.len();
+            let result = OpCode::decode(&mut bytes);
+            let consumed = remaining - bytes.len();
+            let start = self.position;
+
+            match result
+            {
+                Ok(Some(mut instruction)) =>
+                {
+                    self.position += consumed;
+                    instruction.offset = start + consumed - instruction.length();
+                    return Some(Ok(instruction));
+                }
+
+                Ok(None) => self.position += consumed, // Skipped padding
+
+                Err(error) if self.resilient =>
+                {
+                    let byte = self.bytes[start];
+                    let comment = match error
+                    {
+                        Error::InvalidOpCode(_) => String::from("invalid opcode"),
+                        _ => error.to_string(),
+                    };
+
+                    self.position = start + 1;
+                    return Some(Ok(Instruction::data(start, byte, comment)));
+                }
+
+                Err(error) =>
+                {
+                    self.position = self.bytes.len(); // Decoding cannot continue
+                    return Some(Err(error));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn read_value<T: Iterator<Item = u8>, const WIDTH: usize>(bytes: &mut T) -> Result<[u8; WIDTH], Error>
 {
     let mut value = [0u8; WIDTH];
 
-    for i in 0 .. value.len()
+    for byte in value.iter_mut()
     {
-        value[i] = bytes.next().ok_or("Unexpected end of byte stream")?;
+        *byte = bytes.next().ok_or(Error::UnexpectedEnd)?;
     }
 
     Ok(value)
 }
 
-pub fn parse_instruction1<W: std::io::Write, T: Iterator<Item = u8>>(
-    writer: &mut W,
-    options: &Options,
+pub fn parse_instruction1<T: Iterator<Item = u8>>(
     _bytes: &mut T,
     byte0: u8,
     _byte0_bits: [bool; 8],
     op: OpCode,
-) -> Result<(), String>
+) -> Result<Instruction, Error>
 {
-    let bytecode = [byte0];
-    disassemble_instruction(writer, options, &bytecode, op.emit(options), None, None, None, None, None);
+    let mut bytecode = ArrayVec::<_, 18>::new();
+    bytecode.push(byte0);
 
-    Ok(())
+    Ok(Instruction { bytecode, ..Instruction::new(op) })
 }
 
-pub fn parse_instruction2<W: std::io::Write, T: Iterator<Item = u8>>(
-    writer: &mut W,
-    options: &Options,
+pub fn parse_instruction2<T: Iterator<Item = u8>>(
     bytes: &mut T,
     byte0: u8,
     byte0_bits: [bool; 8],
     op: OpCode,
-) -> Result<(), String>
+) -> Result<Instruction, Error>
 {
-    let mut name = op.emit(options);
+    let mut postfixes = ArrayVec::<_, 4>::new();
 
-    let byte1 = bytes.next().ok_or("Unexpected end of bytes")?;
+    let byte1 = bytes.next().ok_or(Error::UnexpectedEnd)?;
 
     let arg1 = match op
     {
@@ -123,9 +558,7 @@ pub fn parse_instruction2<W: std::io::Write, T: Iterator<Item = u8>>(
         {
             if byte1 == 0
             {
-                let msg = String::from("Runaway program break (found 2 zeros in a row, BREAK 0)");
-
-                return Err(color_error(msg, options));
+                return Err(Error::RunawayBreak);
             }
 
             Argument::ImmediateU16(byte1 as u16)
@@ -138,16 +571,9 @@ pub fn parse_instruction2<W: std::io::Write, T: Iterator<Item = u8>>(
             if conditional
             {
                 let condition_bit_set = byte0_bits[6];
-                let postfix = if condition_bit_set
-                {
-                    color_opcode(String::from("cs"), options)
-                }
-                else
-                {
-                    color_opcode(String::from("cc"), options)
-                };
+                let postfix = if condition_bit_set { Postfix::Opcode("cs") } else { Postfix::Opcode("cc") };
 
-                name += &postfix;
+                postfixes.push(postfix);
             }
 
             Argument::ImmediateI16((byte1 as i8) as i16)
@@ -156,27 +582,23 @@ pub fn parse_instruction2<W: std::io::Write, T: Iterator<Item = u8>>(
         _ => unreachable!(),
     };
 
-    let bytecode = [byte0, byte1];
-    disassemble_instruction(writer, options, &bytecode, name, None, Some(arg1), None, None, None);
+    let bytecode = [byte0, byte1].into_iter().collect();
 
-    Ok(())
+    Ok(Instruction { bytecode, postfixes, argument1: Some(arg1), ..Instruction::new(op) })
 }
 
-pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
-    writer: &mut W,
-    options: &Options,
+pub fn parse_instruction3<T: Iterator<Item = u8>>(
     bytes: &mut T,
     byte0: u8,
     byte0_bits: [bool; 8],
     op: OpCode,
-) -> Result<(), String>
+) -> Result<Instruction, Error>
 {
-    let mut name = op.emit(options);
-    let mut postfix = String::with_capacity(5);
+    let mut postfixes = ArrayVec::<_, 4>::new();
     let immediate_data_present = byte0_bits[7];
     let is_64_bit = byte0_bits[6]; // Not used by PUSHn & POPn
 
-    let byte1 = bytes.next().ok_or("Unexpected end of bytes")?;
+    let byte1 = bytes.next().ok_or(Error::UnexpectedEnd)?;
     let byte1_bits = bits_rev(byte1);
 
     let mut bytecode = ArrayVec::<_, 18>::new();
@@ -187,28 +609,31 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
     {
         OpCode::CALL | OpCode::JMP | OpCode::PUSH | OpCode::POP =>
         {
-            let width_postfix =
-                if is_64_bit { color_x64(String::from("64"), options) } else { color_x32(String::from("32"), options) };
+            let width_postfix = if is_64_bit { Postfix::X64("64") } else { Postfix::X32("32") };
 
-            postfix += &width_postfix;
+            postfixes.push(width_postfix);
         }
 
         _ => (),
     }
 
-    let (op1, arg1, op2, arg2, comment) = match op
+    let (op1, arg1, comment) = match op
     {
         OpCode::CALL =>
         {
             let is_native_call = byte1_bits[5];
-            postfix += if is_native_call { "EX" } else { "" };
+
+            if is_native_call
+            {
+                postfixes.push(Postfix::Plain("EX"));
+            }
 
             let is_relative_address = byte1_bits[4];
             let operand1_is_indirect = byte1_bits[3];
             let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
             let op1 = if !is_64_bit
             {
-                Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect))
+                Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?)
             }
             else
             {
@@ -217,7 +642,7 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
 
             let arg1 = if is_64_bit
             {
-                postfix += "a"; // CALL64 is always an absolute address
+                postfixes.push(Postfix::Plain("a")); // CALL64 is always an absolute address
 
                 let value = read_value::<T, 8>(bytes)?;
                 bytecode.extend(value.iter().cloned());
@@ -226,9 +651,12 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
             }
             else
             {
-                postfix += if is_relative_address { "" } else { "a" };
+                if !is_relative_address
+                {
+                    postfixes.push(Postfix::Plain("a"));
+                }
 
-                let arg = if immediate_data_present
+                if immediate_data_present
                 {
                     let value = read_value::<T, 4>(bytes)?;
 
@@ -246,12 +674,10 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
                 else
                 {
                     None
-                };
-
-                arg
+                }
             };
 
-            (op1, arg1, None, None, None)
+            (op1, arg1, None)
         }
 
         OpCode::JMP =>
@@ -261,7 +687,7 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
 
             if conditional_jump
             {
-                postfix += if jump_if_condition_bit_set { "cs" } else { "cc" };
+                postfixes.push(if jump_if_condition_bit_set { Postfix::Plain("cs") } else { Postfix::Plain("cc") });
             }
 
             let relative_address = byte1_bits[4];
@@ -269,7 +695,7 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
             let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
             let op1 = if !is_64_bit
             {
-                Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect))
+                Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?)
             }
             else
             {
@@ -283,28 +709,23 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
 
                 Some(Argument::ImmediateI64(i64::from_le_bytes(value)))
             }
-            else
+            else if immediate_data_present
             {
-                let arg = if immediate_data_present
-                {
-                    let value = read_value::<T, 4>(bytes)?;
-                    bytecode.extend(value.iter().cloned());
+                let value = read_value::<T, 4>(bytes)?;
+                bytecode.extend(value.iter().cloned());
 
-                    if operand1_is_indirect
-                    {
-                        Some(Argument::Index32(u32::from_le_bytes(value)))
-                    }
-                    else
-                    {
-                        Some(Argument::ImmediateI32(i32::from_le_bytes(value)))
-                    }
+                if operand1_is_indirect
+                {
+                    Some(Argument::Index32(u32::from_le_bytes(value)))
                 }
                 else
                 {
-                    None
-                };
-
-                arg
+                    Some(Argument::ImmediateI32(i32::from_le_bytes(value)))
+                }
+            }
+            else
+            {
+                None
             };
 
             let comment = if relative_address
@@ -316,7 +737,7 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
                 Some(String::from("Absolute Address"))
             };
 
-            (op1, arg1, None, None, comment)
+            (op1, arg1, comment)
         }
 
         OpCode::PUSH | OpCode::POP | OpCode::PUSHn | OpCode::POPn =>
@@ -344,31 +765,23 @@ pub fn parse_instruction3<W: std::io::Write, T: Iterator<Item = u8>>(
                 None
             };
 
-            (Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)), arg1, None, None, None)
+            (Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?), arg1, None)
         }
 
         _ => unreachable!(),
     };
 
-    name += &postfix;
-
-    disassemble_instruction(writer, options, &bytecode, name, op1, arg1, op2, arg2, comment);
-
-    Ok(())
+    Ok(Instruction { bytecode, postfixes, operand1: op1, argument1: arg1, comment, ..Instruction::new(op) })
 }
 
-pub fn parse_instruction4<W: std::io::Write, T: Iterator<Item = u8>>(
-    writer: &mut W,
-    options: &Options,
+pub fn parse_instruction4<T: Iterator<Item = u8>>(
     bytes: &mut T,
     byte0: u8,
     _byte0_bits: [bool; 8],
     op: OpCode,
-) -> Result<(), String>
+) -> Result<Instruction, Error>
 {
-    let name = op.emit(options);
-
-    let byte1 = bytes.next().ok_or("Unexpected end of bytes")?;
+    let byte1 = bytes.next().ok_or(Error::UnexpectedEnd)?;
     let byte1_bits = bits_rev(byte1);
     let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
     let operand2_value = bits_to_byte_rev(&byte1_bits[4 ..= 6]);
@@ -381,34 +794,30 @@ pub fn parse_instruction4<W: std::io::Write, T: Iterator<Item = u8>>(
     {
         OpCode::STORESP =>
         {
-            (Operand::new_general_purpose(operand1_value, false), Operand::new_dedicated(operand2_value, false))
+            (Operand::new_general_purpose(operand1_value, false)?, Operand::new_dedicated(operand2_value, false)?)
         }
 
         OpCode::LOADSP =>
         {
-            (Operand::new_dedicated(operand1_value, false), Operand::new_general_purpose(operand2_value, false))
+            (Operand::new_dedicated(operand1_value, false)?, Operand::new_general_purpose(operand2_value, false)?)
         }
 
         _ => unreachable!(),
     };
 
-    disassemble_instruction(writer, options, &bytecode, name, Some(op1), None, Some(op2), None, None);
-
-    Ok(())
+    Ok(Instruction { bytecode, operand1: Some(op1), operand2: Some(op2), ..Instruction::new(op) })
 }
 
-pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
-    writer: &mut W,
-    options: &Options,
+pub fn parse_instruction5<T: Iterator<Item = u8>>(
     bytes: &mut T,
     byte0: u8,
     byte0_bits: [bool; 8],
     op: OpCode,
-) -> Result<(), String>
+) -> Result<Instruction, Error>
 {
-    let mut name = op.emit(options);
-    let mut postfixes = String::with_capacity(7);
-    let byte1 = bytes.next().ok_or("Unexpected end of byte stream")?;
+    let mut name = format!("{:?}", op);
+    let mut postfixes = ArrayVec::<_, 4>::new();
+    let byte1 = bytes.next().ok_or(Error::UnexpectedEnd)?;
     let byte1_bits = bits_rev(byte1);
 
     let mut bytecode = ArrayVec::<_, 18>::new();
@@ -422,31 +831,31 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
             let move_width = bits_to_byte_rev(&byte1_bits[4 ..= 5]);
             let postfix = match move_width
             {
-                0 => color_x8(String::from("b"), options),
-                1 => color_x16(String::from("w"), options),
-                2 => color_x32(String::from("d"), options),
-                3 => color_x64(String::from("q"), options),
+                0 => Postfix::X8("b"),
+                1 => Postfix::X16("w"),
+                2 => Postfix::X32("d"),
+                3 => Postfix::X64("q"),
                 _ => unreachable!(),
             };
 
-            postfixes += &postfix;
+            postfixes.push(postfix);
 
             let immediate_data_width = bits_to_byte_rev(&byte0_bits[6 ..= 7]);
             let postfix = match immediate_data_width
             {
-                1 => color_x16(String::from("w"), options),
-                2 => color_x32(String::from("d"), options),
-                3 => color_x64(String::from("q"), options),
-                _ => unreachable!(),
+                1 => Postfix::X16("w"),
+                2 => Postfix::X32("d"),
+                3 => Postfix::X64("q"),
+                _ => return Err(Error::InvalidWidth(op)),
             };
 
-            postfixes += &postfix;
+            postfixes.push(postfix);
 
             let operand1_index_present = byte1_bits[6];
             let operand1_is_indirect = byte1_bits[3];
             let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
 
-            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect));
+            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?);
 
             let arg1 = if operand1_index_present
             {
@@ -459,9 +868,7 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
                 }
                 else
                 {
-                    let msg = format!("Immediate data not supported for {}", op.emit(options));
-
-                    return Err(color_error(msg, options));
+                    return Err(Error::ImmediateNotSupported(op));
                 };
 
                 Some(arg)
@@ -505,8 +912,6 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
                 }
             };
 
-            name += &postfixes;
-
             (op1, arg1, arg2)
         }
 
@@ -516,49 +921,35 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
             let comparison_is_64_bit = byte0_bits[6];
 
             // Have to obliterate name due to the reordering below:
-            name = color_opcode(String::from("CMPI"), options);
+            name = String::from("CMPI");
 
-            let postfix = if comparison_is_64_bit
-            {
-                color_x64(String::from("64"), options)
-            }
-            else
-            {
-                color_x32(String::from("32"), options)
-            };
+            let postfix = if comparison_is_64_bit { Postfix::X64("64") } else { Postfix::X32("32") };
 
-            name += &postfix;
+            postfixes.push(postfix);
 
-            let postfix = if immediate_data_is_32_bit
-            {
-                color_x32(String::from("d"), options)
-            }
-            else
-            {
-                color_x16(String::from("w"), options)
-            };
+            let postfix = if immediate_data_is_32_bit { Postfix::X32("d") } else { Postfix::X16("w") };
 
-            name += &postfix;
+            postfixes.push(postfix);
 
             let postfix = match op
             {
-                OpCode::CMPIeq => color_opcode(String::from("eq"), options),
-                OpCode::CMPIlte => color_opcode(String::from("lte"), options),
-                OpCode::CMPIgte => color_opcode(String::from("gte"), options),
-                OpCode::CMPIulte => color_opcode(String::from("ulte"), options),
+                OpCode::CMPIeq => Postfix::Opcode("eq"),
+                OpCode::CMPIlte => Postfix::Opcode("lte"),
+                OpCode::CMPIgte => Postfix::Opcode("gte"),
+                OpCode::CMPIulte => Postfix::Opcode("ulte"),
 
-                OpCode::CMPIugte => color_opcode(String::from("ugte"), options),
+                OpCode::CMPIugte => Postfix::Opcode("ugte"),
 
                 _ => unreachable!(),
             };
 
-            name += &postfix;
+            postfixes.push(postfix);
 
             let operand1_index_present = byte1_bits[4];
             let operand1_is_indirect = byte1_bits[3];
             let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
 
-            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect));
+            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?);
 
             let arg1 = if operand1_index_present
             {
@@ -571,9 +962,7 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
                 }
                 else
                 {
-                    let msg = format!("Immediate data not supported for {}", op.emit(options));
-
-                    return Err(color_error(msg, options));
+                    return Err(Error::ImmediateNotSupported(op));
                 };
 
                 Some(arg)
@@ -618,19 +1007,19 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
             let operand2_index_width = bits_to_byte_rev(&byte0_bits[6 ..= 7]);
             let postfix = match operand2_index_width
             {
-                1 => color_x16(String::from("w"), options),
-                2 => color_x32(String::from("d"), options),
-                3 => color_x64(String::from("q"), options),
-                _ => unreachable!(),
+                1 => Postfix::X16("w"),
+                2 => Postfix::X32("d"),
+                3 => Postfix::X64("q"),
+                _ => return Err(Error::InvalidWidth(op)),
             };
 
-            postfixes += &postfix;
+            postfixes.push(postfix);
 
             let operand1_index_present = byte1_bits[6];
             let operand1_is_indirect = byte1_bits[3];
             let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
 
-            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect));
+            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?);
 
             let arg1 = if operand1_index_present
             {
@@ -643,9 +1032,7 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
                 }
                 else
                 {
-                    let msg = format!("Immediate data not supported for {}", op.emit(options));
-
-                    return Err(color_error(msg, options));
+                    return Err(Error::ImmediateNotSupported(op));
                 };
 
                 Some(arg)
@@ -689,8 +1076,6 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
                 }
             };
 
-            name += &postfixes;
-
             (op1, arg1, arg2)
         }
 
@@ -699,19 +1084,19 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
             let immediate_data_width = bits_to_byte_rev(&byte0_bits[6 ..= 7]);
             let postfix = match immediate_data_width
             {
-                1 => color_x16(String::from("w"), options),
-                2 => color_x32(String::from("d"), options),
-                3 => color_x64(String::from("q"), options),
-                _ => unreachable!(),
+                1 => Postfix::X16("w"),
+                2 => Postfix::X32("d"),
+                3 => Postfix::X64("q"),
+                _ => return Err(Error::InvalidWidth(op)),
             };
 
-            postfixes += &postfix;
+            postfixes.push(postfix);
 
             let operand1_index_present = byte1_bits[6];
             let operand1_is_indirect = byte1_bits[3];
             let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
 
-            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect));
+            let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?);
 
             let arg1 = if operand1_index_present
             {
@@ -724,9 +1109,7 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
                 }
                 else
                 {
-                    let msg = format!("Immediate data not supported for {}", op.emit(options));
-
-                    return Err(color_error(msg, options));
+                    return Err(Error::ImmediateNotSupported(op));
                 };
 
                 Some(arg)
@@ -770,37 +1153,38 @@ pub fn parse_instruction5<W: std::io::Write, T: Iterator<Item = u8>>(
                 }
             };
 
-            name += &postfixes;
-
             (op1, arg1, arg2)
         }
 
         _ => unreachable!(),
     };
 
-    disassemble_instruction(writer, options, &bytecode, name, op1, arg1, None, arg2, None);
-
-    Ok(())
+    Ok(Instruction {
+        bytecode,
+        name,
+        postfixes,
+        operand1: op1,
+        argument1: arg1,
+        argument2: arg2,
+        ..Instruction::new(op)
+    })
 }
 
-pub fn parse_instruction6<W: std::io::Write, T: Iterator<Item = u8>>(
-    writer: &mut W,
-    options: &Options,
+pub fn parse_instruction6<T: Iterator<Item = u8>>(
     bytes: &mut T,
     byte0: u8,
     byte0_bits: [bool; 8],
     op: OpCode,
-) -> Result<(), String>
+) -> Result<Instruction, Error>
 {
-    let mut name = op.emit(options);
+    let mut postfixes = ArrayVec::<_, 4>::new();
     let immediate_data_present = byte0_bits[7];
     let is_64_bit = byte0_bits[6];
-    let postfix =
-        if is_64_bit { color_x64(String::from("64"), options) } else { color_x32(String::from("32"), options) };
+    let postfix = if is_64_bit { Postfix::X64("64") } else { Postfix::X32("32") };
 
-    name += &postfix;
+    postfixes.push(postfix);
 
-    let byte1 = bytes.next().ok_or("Unexpected end of bytes")?;
+    let byte1 = bytes.next().ok_or(Error::UnexpectedEnd)?;
     let byte1_bits = bits_rev(byte1);
     let operand1_is_indirect = byte1_bits[3];
     let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
@@ -834,34 +1218,27 @@ pub fn parse_instruction6<W: std::io::Write, T: Iterator<Item = u8>>(
         }
     };
 
-    disassemble_instruction(
-        writer,
-        options,
-        &bytecode,
-        name,
-        Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)),
-        None,
-        Some(Operand::new_general_purpose(operand2_value, operand2_is_indirect)),
-        op1_x16_index_or_immediate,
-        None,
-    );
-
-    Ok(())
+    Ok(Instruction {
+        bytecode,
+        postfixes,
+        operand1: Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?),
+        operand2: Some(Operand::new_general_purpose(operand2_value, operand2_is_indirect)?),
+        argument2: op1_x16_index_or_immediate,
+        ..Instruction::new(op)
+    })
 }
 
-pub fn parse_instruction7<W: std::io::Write, T: Iterator<Item = u8>>(
-    writer: &mut W,
-    options: &Options,
+pub fn parse_instruction7<T: Iterator<Item = u8>>(
     bytes: &mut T,
     byte0: u8,
     byte0_bits: [bool; 8],
     op: OpCode,
-) -> Result<(), String>
+) -> Result<Instruction, Error>
 {
     let operand1_index_present = byte0_bits[7];
     let operand2_index_present = byte0_bits[6];
 
-    let byte1 = bytes.next().ok_or("Unexpected end of bytes")?;
+    let byte1 = bytes.next().ok_or(Error::UnexpectedEnd)?;
     let byte1_bits = bits_rev(byte1);
     let operand1_is_indirect = byte1_bits[3];
     let operand1_value = bits_to_byte_rev(&byte1_bits[0 ..= 2]);
@@ -872,9 +1249,9 @@ pub fn parse_instruction7<W: std::io::Write, T: Iterator<Item = u8>>(
     bytecode.push(byte0);
     bytecode.push(byte1);
 
-    let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect));
+    let op1 = Some(Operand::new_general_purpose(operand1_value, operand1_is_indirect)?);
 
-    let op2 = Some(Operand::new_general_purpose(operand2_value, operand2_is_indirect));
+    let op2 = Some(Operand::new_general_purpose(operand2_value, operand2_is_indirect)?);
 
     let (arg1, arg2) = match op
     {
@@ -1146,47 +1523,25 @@ pub fn parse_instruction7<W: std::io::Write, T: Iterator<Item = u8>>(
         }
     };
 
-    let mut name = format!("{:?}", op);
-    let mut chars = name.chars();
     let indices_present = operand1_index_present || operand2_index_present;
+    let mut postfixes = ArrayVec::<_, 4>::new();
 
-    let postfix = match op
+    let name = match op
     {
-        OpCode::MOVnw | OpCode::MOVsnw =>
+        OpCode::MOVnw | OpCode::MOVsnw | OpCode::MOVnd | OpCode::MOVsnd =>
         {
             // If indices are present, keep them
-            let move_width = if indices_present
-            {
-                String::from(chars.next_back().unwrap())
-            }
-            else
-            // Remove it to get to the guaranteed move width
+            if indices_present
             {
-                chars.next_back().unwrap();
-                String::from("")
-            };
+                let move_width =
+                    if let OpCode::MOVnw | OpCode::MOVsnw = op { Postfix::X16("w") } else { Postfix::X32("d") };
 
-            // COLOR IT
-            color_x16(move_width, options)
-        }
-
-        OpCode::MOVnd | OpCode::MOVsnd =>
-        {
-            // If indices are present, keep them
-            let move_width = if indices_present
-            {
-                String::from(chars.next_back().unwrap())
+                postfixes.push(move_width);
             }
-            else
-            // Remove it to get to the guaranteed move width
-            {
-                chars.next_back().unwrap();
-                String::from("")
-            };
-
-            // COLOR IT
 
-            color_x32(move_width, options)
+            // Remove it to get to the guaranteed move width
+            let name = format!("{:?}", op);
+            name[.. name.len() - 1].to_string()
         }
 
         OpCode::MOVbw
@@ -1199,163 +1554,52 @@ pub fn parse_instruction7<W: std::io::Write, T: Iterator<Item = u8>>(
         | OpCode::MOVqd
         | OpCode::MOVqq =>
         {
-            // If indices are present, keep them
-            let index_width = if indices_present
-            {
-                let width = String::from(chars.next_back().unwrap());
-
-                match op
-                {
-                    OpCode::MOVbw | OpCode::MOVww | OpCode::MOVdw | OpCode::MOVqw => color_x16(width, options),
-
-                    OpCode::MOVbd | OpCode::MOVwd | OpCode::MOVdd | OpCode::MOVqd => color_x32(width, options),
-
-                    OpCode::MOVqq => color_x64(width, options),
-
-                    _ => unreachable!(),
-                }
-            }
-            else
-            // Remove it to get to the guaranteed move width
-            {
-                chars.next_back().unwrap();
-                String::from("")
-            };
-
-            let move_width = String::from(chars.next_back().unwrap());
             let move_width = match op
             {
-                OpCode::MOVbw | OpCode::MOVbd => color_x8(move_width, options),
+                OpCode::MOVbw | OpCode::MOVbd => Postfix::X8("b"),
 
-                OpCode::MOVww | OpCode::MOVwd => color_x16(move_width, options),
+                OpCode::MOVww | OpCode::MOVwd => Postfix::X16("w"),
 
-                OpCode::MOVdw | OpCode::MOVdd => color_x32(move_width, options),
+                OpCode::MOVdw | OpCode::MOVdd => Postfix::X32("d"),
 
-                OpCode::MOVqw | OpCode::MOVqd | OpCode::MOVqq => color_x64(move_width, options),
+                OpCode::MOVqw | OpCode::MOVqd | OpCode::MOVqq => Postfix::X64("q"),
 
                 _ => unreachable!(),
             };
 
-            move_width + &index_width
-        }
-
-        _ => unreachable!(),
-    };
-
-    let postfixes_removed = chars.as_str().to_string();
-    name = color_opcode(postfixes_removed, options);
-    name += &postfix;
-
-    disassemble_instruction(writer, options, &bytecode, name, op1, arg1, op2, arg2, None);
-
-    Ok(())
-}
+            postfixes.push(move_width);
 
-pub fn disassemble_instruction<W: std::io::Write>(
-    writer: &mut W,
-    options: &Options,
-    bytecode: &[u8],
-    instruction: String, // Must concatenate postfixes manually
-    operand1: Option<Operand>,
-    argument1: Option<Argument>,
-    operand2: Option<Operand>,
-    argument2: Option<Argument>,
-    comment: Option<String>,
-)
-{
-    if options.bytecode
-    {
-        const TWO_CHARS_AND_A_SPACE: usize = 3;
-        let mut bytecode_output = String::with_capacity(bytecode.len() * TWO_CHARS_AND_A_SPACE);
-
-        for byte in bytecode.iter()
-        {
-            bytecode_output += format!("{:<02X?} ", byte).as_str();
-        }
-
-        bytecode_output = color_bytecode(bytecode_output, options);
-
-        write!(writer, "{:>84} ", bytecode_output).unwrap();
-    }
-
-    if options.pad_output
-    {
-        write!(writer, "{}", instruction).unwrap();
-    }
-    else
-    {
-        write!(writer, "{}", instruction).unwrap();
-    }
-
-    if let Some(op1) = operand1
-    {
-        write!(writer, " {}", op1.emit(options)).unwrap();
-    }
-
-    if let Some(arg1) = argument1
-    {
-        let text = arg1.emit(options);
-
-        match arg1
-        {
-            Argument::Index16(_index) => write!(writer, "{}", text).unwrap(),
-            Argument::Index32(_index) => write!(writer, "{}", text).unwrap(),
-            Argument::Index64(_index) => write!(writer, "{}", text).unwrap(),
-            _ => write!(writer, " {}", text).unwrap(),
-        }
-    }
-
-    if operand2.is_some() || argument2.is_some()
-    {
-        write!(writer, ",").unwrap();
-    }
-
-    if let Some(ref op2) = operand2
-    {
-        write!(writer, " {}", op2.emit(options)).unwrap();
-    }
-
-    if let Some(arg2) = argument2
-    {
-        match arg2
-        {
-            Argument::Index16(_index) =>
+            // If indices are present, keep them
+            if indices_present
             {
-                if operand2.is_none()
+                let index_width = match op
                 {
-                    write!(writer, " ").unwrap();
-                }
-                write!(writer, "{}", arg2.emit(options)).unwrap();
-            }
+                    OpCode::MOVbw | OpCode::MOVww | OpCode::MOVdw | OpCode::MOVqw => Postfix::X16("w"),
 
-            Argument::Index32(_index) =>
-            {
-                if operand2.is_none()
-                {
-                    write!(writer, " ").unwrap();
-                }
-                write!(writer, "{}", arg2.emit(options)).unwrap();
-            }
+                    OpCode::MOVbd | OpCode::MOVwd | OpCode::MOVdd | OpCode::MOVqd => Postfix::X32("d"),
 
-            Argument::Index64(_index) =>
-            {
-                if operand2.is_none()
-                {
-                    write!(writer, " ").unwrap();
-                }
-                write!(writer, "{}", arg2.emit(options)).unwrap();
+                    OpCode::MOVqq => Postfix::X64("q"),
+
+                    _ => unreachable!(),
+                };
+
+                postfixes.push(index_width);
             }
 
-            _ => write!(writer, " {}", arg2.emit(options)).unwrap(),
+            String::from("MOV")
         }
-    }
-
-    if let Some(line_comment) = comment
-    {
-        let the_comment = color_comment(format!("  ;; {}", line_comment), options);
 
-        write!(writer, "{}", the_comment).unwrap();
-    }
+        _ => unreachable!(),
+    };
 
-    writeln!(writer, "").unwrap();
+    Ok(Instruction {
+        bytecode,
+        name,
+        postfixes,
+        operand1: op1,
+        argument1: arg1,
+        operand2: op2,
+        argument2: arg2,
+        ..Instruction::new(op)
+    })
 }
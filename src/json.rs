@@ -0,0 +1,157 @@
+use crate::argument::Argument;
+use crate::instruction::{Instruction, Postfix};
+use crate::label::Label;
+use crate::natural_index::NaturalIndex;
+use crate::operand::Operand;
+use crate::options::Options;
+
+/// Machine-readable rendering, the counterpart of `Emit` for `output: JSON`.
+/// Values are never colored.
+pub trait Json
+{
+    fn json(&self, options: &Options) -> String;
+}
+
+/// Quotes and escapes a string.
+pub fn json_string(string: &str) -> String
+{
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+
+    for character in string.chars()
+    {
+        match character
+        {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            character if (character as u32) < 0x20 => quoted += &format!("\\u{:04X}", character as u32),
+            character => quoted.push(character),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Renders `Some` values or `null`.
+pub fn json_option<T: Json>(value: Option<&T>, options: &Options) -> String
+{
+    value.map_or_else(|| String::from("null"), |value| value.json(options))
+}
+
+/// Joins `"key": value` pairs into an object.
+pub fn json_object(fields: &[(&str, String)]) -> String
+{
+    let fields = fields.iter().map(|(key, value)| format!("{}: {}", json_string(key), value)).collect::<Vec<_>>();
+
+    format!("{{{}}}", fields.join(", "))
+}
+
+impl Json for NaturalIndex
+{
+    fn json(&self, _options: &Options) -> String
+    {
+        json_object(&[
+            ("sign", self.sign.to_string()),
+            ("constant", self.constant.to_string()),
+            ("natural", self.natural.to_string()),
+            ("offset", self.offset.to_string()),
+        ])
+    }
+}
+
+impl Json for Argument
+{
+    fn json(&self, options: &Options) -> String
+    {
+        let kind = match self
+        {
+            Self::Index16(_) => "index16",
+            Self::Index32(_) => "index32",
+            Self::Index64(_) => "index64",
+            Self::ImmediateU16(_) | Self::ImmediateI16(_) => "immediate16",
+            Self::ImmediateU32(_) | Self::ImmediateI32(_) => "immediate32",
+            Self::ImmediateI64(_) => "immediate64",
+            Self::Data(_) => "data",
+        };
+
+        match self.natural_index(options.pointer_size)
+        {
+            Some(index) => json_object(&[
+                ("kind", json_string(kind)),
+                ("value", index.value.to_string()),
+                ("index", index.json(options)),
+            ]),
+
+            None => json_object(&[("kind", json_string(kind)), ("value", self.immediate().unwrap_or(0).to_string())]),
+        }
+    }
+}
+
+impl Json for Operand
+{
+    fn json(&self, _options: &Options) -> String
+    {
+        let (register, indirect) = match self
+        {
+            Self::GeneralPurpose { register_index, indirect } => (format!("R{}", register_index), *indirect),
+
+            Self::Dedicated { register_index: 0, indirect } => (String::from("FLAGS"), *indirect),
+            Self::Dedicated { register_index: 1, indirect } => (String::from("IP"), *indirect),
+            Self::Dedicated { register_index, indirect } => (format!("DR{}", register_index), *indirect),
+        };
+
+        json_object(&[("register", json_string(&register)), ("indirect", indirect.to_string())])
+    }
+}
+
+impl Json for Label
+{
+    fn json(&self, _options: &Options) -> String
+    {
+        json_string(&self.name())
+    }
+}
+
+impl Json for Instruction
+{
+    fn json(&self, options: &Options) -> String
+    {
+        instruction_json(self, None, options)
+    }
+}
+
+/// One object per instruction, including the label that precedes it.
+pub fn instruction_json(instruction: &Instruction, label: Option<&Label>, options: &Options) -> String
+{
+    let bytes = instruction.bytecode.iter().map(|byte| byte.to_string()).collect::<Vec<_>>();
+    let postfixes = instruction.postfixes.iter().map(|postfix| json_string(postfix.text())).collect::<Vec<_>>();
+    let widths = instruction
+        .postfixes
+        .iter()
+        .filter(|postfix| matches!(postfix, Postfix::X8(_) | Postfix::X16(_) | Postfix::X32(_) | Postfix::X64(_)))
+        .map(|postfix| json_string(postfix.text()))
+        .collect::<Vec<_>>();
+    let opcode = instruction.op.map(|op| format!("{:?}", op));
+    let address = options.address.map(|address| options.origin.address(address, instruction.offset));
+
+    json_object(&[
+        ("offset", instruction.offset.to_string()),
+        ("address", address.map_or_else(|| String::from("null"), |address| address.to_string())),
+        ("label", json_option(label, options)),
+        ("bytes", format!("[{}]", bytes.join(", "))),
+        ("mnemonic", json_string(&instruction.mnemonic())),
+        ("opcode", opcode.as_deref().map_or_else(|| String::from("null"), json_string)),
+        ("name", json_string(&instruction.name)),
+        ("postfixes", format!("[{}]", postfixes.join(", "))),
+        ("widths", format!("[{}]", widths.join(", "))),
+        ("operand1", json_option(instruction.operand1.as_ref(), options)),
+        ("argument1", json_option(instruction.argument1.as_ref(), options)),
+        ("operand2", json_option(instruction.operand2.as_ref(), options)),
+        ("argument2", json_option(instruction.argument2.as_ref(), options)),
+        ("comment", instruction.comment.as_deref().map_or_else(|| String::from("null"), json_string)),
+    ])
+}
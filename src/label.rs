@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use crate::address::Address;
+use crate::instruction::{Instruction, Reference};
+use crate::options::Options;
+use crate::theme::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Label
+{
+    Data(usize),       // Target of a MOVREL outside of the code
+    Location(usize),   // Target of a jump
+    Subroutine(usize), // Target of a call
+    Entry(usize),      // AddressOfEntryPoint of a PE image
+}
+
+impl Label
+{
+    pub fn offset(&self) -> usize
+    {
+        match self
+        {
+            Self::Data(offset) | Self::Location(offset) | Self::Subroutine(offset) | Self::Entry(offset) => *offset,
+        }
+    }
+
+    pub fn name(&self) -> String
+    {
+        match self
+        {
+            Self::Data(offset) => format!("data_{:04X}", offset),
+            Self::Location(offset) => format!("loc_{:04X}", offset),
+            Self::Subroutine(offset) => format!("sub_{:04X}", offset),
+            Self::Entry(_) => String::from("entry"),
+        }
+    }
+}
+
+impl Emit for Label
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        color_label(format!("{}:", self.name()), options)
+    }
+}
+
+/// Synthetic labels for every jump and call target within the decoded bytes.
+#[derive(Debug, Default)]
+pub struct Labels
+{
+    labels: BTreeMap<usize, Label>,
+}
+
+impl Labels
+{
+    pub fn new(instructions: &[Instruction]) -> Self
+    {
+        let mut labels = BTreeMap::new();
+        let end = instructions.last().map(|instruction| instruction.end()).unwrap_or(0);
+
+        for instruction in instructions
+        {
+            let (reference, target) = match instruction.reference()
+            {
+                Some(reference) => reference,
+                None => continue,
+            };
+
+            if !(0 .. end as i64).contains(&target)
+            {
+                continue;
+            }
+
+            let offset = target as usize;
+            let label = match reference
+            {
+                Reference::Jump => Label::Location(offset),
+                Reference::Call => Label::Subroutine(offset),
+                Reference::Load => continue,
+            };
+
+            // A call target is a subroutine even if it is also jumped to
+            if !matches!(labels.get(&offset), Some(Label::Subroutine(_)))
+            {
+                labels.insert(offset, label);
+            }
+        }
+
+        Self { labels }
+    }
+
+    /// Adds a label, replacing any synthetic label at the same offset.
+    pub fn insert(&mut self, label: Label)
+    {
+        self.labels.insert(label.offset(), label);
+    }
+
+    pub fn get(&self, offset: usize) -> Option<&Label>
+    {
+        self.labels.get(&offset)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Label>
+    {
+        self.labels.values()
+    }
+
+    /// Replaces the comment of every relative jump, call and `MOVREL` with its
+    /// resolved target.
+    pub fn annotate(&self, instructions: &mut [Instruction], options: &Options)
+    {
+        for instruction in instructions.iter_mut()
+        {
+            if let Some((_, target)) = instruction.reference()
+            {
+                instruction.comment = Some(self.describe(target, options));
+            }
+        }
+    }
+
+    /// The label at `target` or its offset, followed by the address when an
+    /// RVA or VA has been requested.
+    fn describe(&self, target: i64, options: &Options) -> String
+    {
+        let name = match usize::try_from(target).ok().and_then(|offset| self.get(offset))
+        {
+            Some(label) => label.name(),
+            None if target < 0 => format!("-{:04X}", target.unsigned_abs()),
+            None => format!("{:04X}", target),
+        };
+
+        match options.address
+        {
+            Some(address @ (Address::Rva | Address::Va)) =>
+            {
+                let base = options.origin.address(address, 0);
+
+                format!("-> {} ({:08X})", name, base.wrapping_add_signed(target))
+            }
+
+            _ => format!("-> {}", name),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Spore - Disassembler for UEFI Bytecode
+//!
+//! Instructions are decoded into [`Instruction`] values by a [`Decoder`] and
+//! rendered separately through the [`Emit`](theme::Emit) trait.
+
+// OpCode variants are named exactly as they appear in the UEFI specification
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod address;
+pub mod argument;
+pub mod assembler;
+pub mod bits;
+pub mod browser;
+pub mod debugger;
+pub mod diff;
+pub mod efi;
+pub mod error;
+pub mod fasm;
+pub mod function;
+pub mod graph;
+pub mod image;
+pub mod instruction;
+pub mod json;
+pub mod label;
+pub mod listing;
+pub mod natural_index;
+pub mod opcode;
+pub mod operand;
+pub mod options;
+pub mod relocation;
+pub mod services;
+pub mod strings;
+pub mod theme;
+pub mod trace;
+pub mod traversal;
+pub mod vm;
+pub mod xref;
+
+#[cfg(test)]
+mod tests; // Integration tests
+
+pub use crate::error::Error;
+pub use crate::instruction::{Decoder, Instruction};
+pub use crate::opcode::OpCode;
+pub use crate::options::Options;
+pub use crate::vm::Vm;
@@ -0,0 +1,80 @@
+use std::io::Write;
+
+use crate::error::Error;
+use crate::fasm::instruction_source;
+use crate::function::Functions;
+use crate::instruction::Instruction;
+use crate::json::{instruction_json, Json};
+use crate::label::Labels;
+use crate::options::{Options, Output};
+use crate::theme::*;
+use crate::xref::Xrefs;
+
+/// Writes a single rendered instruction followed by a newline.
+pub fn disassemble_instruction<W: Write>(
+    writer: &mut W,
+    options: &Options,
+    instruction: &Instruction,
+) -> Result<(), Error>
+{
+    match options.output
+    {
+        Output::Text => writeln!(writer, "{}", instruction.emit(options))?,
+        Output::Json => writeln!(writer, "{}", instruction.json(options))?,
+        Output::Asm => writeln!(writer, "    {}", instruction_source(instruction, None, options))?,
+    }
+
+    Ok(())
+}
+
+/// Writes every instruction, preceded by a header line for each label and a
+/// separator for each function.
+///
+/// JSON output has no header lines since each object contains its label.
+pub fn disassemble_listing<W: Write>(
+    writer: &mut W,
+    options: &Options,
+    instructions: &[Instruction],
+    labels: &Labels,
+    functions: &Functions,
+    xrefs: &Xrefs,
+) -> Result<(), Error>
+{
+    for (i, instruction) in instructions.iter().enumerate()
+    {
+        let label = labels.get(instruction.offset);
+
+        if options.output == Output::Json
+        {
+            writeln!(writer, "{}", instruction_json(instruction, label, options))?;
+            continue;
+        }
+
+        let function = functions.get(instruction.offset);
+        let xrefs = if options.xrefs { xrefs.to(instruction.offset) } else { &[] };
+
+        if (label.is_some() || function.is_some() || !xrefs.is_empty()) && i > 0
+        {
+            writeln!(writer)?;
+        }
+
+        if let Some(function) = function
+        {
+            writeln!(writer, "{}", function.emit(options))?;
+        }
+
+        for xref in xrefs
+        {
+            writeln!(writer, "{}", xref.emit(options))?;
+        }
+
+        if let Some(label) = label
+        {
+            writeln!(writer, "{}", label.emit(options))?;
+        }
+
+        disassemble_instruction(writer, options, instruction)?;
+    }
+
+    Ok(())
+}
@@ -1,43 +1,83 @@
-mod argument;
-mod bits;
-mod instruction;
-mod natural_index;
-mod opcode;
-mod operand;
-mod options;
-mod theme;
-
-#[cfg(test)]
-mod tests; // Integration tests
-
-use pelite::pe64::{Pe, PeFile};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
 use pelite::FileMap;
+use spore_disassembler::address::{Address, Origin};
+use spore_disassembler::browser::{Browser, Listing};
+use spore_disassembler::debugger::Debugger;
+use spore_disassembler::diff::{units, Diff, Unit};
+use spore_disassembler::efi::annotate_services;
+use spore_disassembler::fasm;
+use spore_disassembler::function::Functions;
+use spore_disassembler::graph::Graph;
+use spore_disassembler::image::{Image, Section};
+use spore_disassembler::instruction::Instruction;
+use spore_disassembler::json::{json_object, json_string};
+use spore_disassembler::label::{Label, Labels};
+use spore_disassembler::listing::{disassemble_instruction, disassemble_listing};
+use spore_disassembler::options::{Output, Traversal};
+use spore_disassembler::relocation::{annotate_relocations, Relocation};
+use spore_disassembler::services::Services;
+use spore_disassembler::strings::Strings;
+use spore_disassembler::theme::*;
+use spore_disassembler::trace::{Coverage, CoverageReport};
+use spore_disassembler::traversal::decode_recursive;
+use spore_disassembler::vm::{Step, Vm};
+use spore_disassembler::xref::Xrefs;
+use spore_disassembler::{Decoder, Error, Options};
 
-use crate::opcode::OpCode;
-use crate::options::Options;
-use crate::theme::*;
+const HELP: &str = include_str!("CLI.txt");
+const DEFAULT_STEPS: usize = 1_000_000; // Instructions executed by trace: and coverage: unless steps: is given
 
-const CODE_SECTION: u32 = pelite::image::IMAGE_SCN_CNT_CODE;
-const HELP: &'static str = include_str!("CLI.txt");
+/// What to do with the decoded bytecode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode
+{
+    Listing, // Print the disassembly
+    Browse,  // Open the interactive browser (interactive: ON)
+    Debug,   // Step through the code (spore debug)
+    Trace,   // Print every instruction as it is executed (trace: ON)
+    Diff,    // Compare two files (spore diff)
+}
 
 /// Reads in an EFI Bytecode filename from STDIN and prints the disassembly.
 fn main()
 {
     let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    let mut mode = Mode::Listing;
+
+    match args.first().map(String::as_str)
+    {
+        Some("debug") => mode = Mode::Debug,
+        Some("diff") => mode = Mode::Diff,
+        _ => (),
+    }
 
-    if args.len() == 0
+    if mode != Mode::Listing
+    {
+        args.remove(0);
+    }
+
+    if args.is_empty() || (mode == Mode::Diff && args.len() < 2)
     {
         return println!("{}", HELP);
     }
 
     let filename = args.pop().unwrap();
+    let old_filename = if mode == Mode::Diff { args.pop() } else { None };
 
     if args.len() % 2 != 0
     {
         return println!("{}", HELP);
     }
 
-    let mut options = Options { theme: Some(SPORE), bytecode: true, pe: true, pad_output: true };
+    let mut options = Options::default();
+    let mut graph = None;
+    let mut load_base = None;
+    let mut coverage = false;
+    let mut steps = DEFAULT_STEPS;
+    let (mut start, mut length, mut end) = (None, None, None);
 
     for i in (0 .. args.len()).step_by(2)
     {
@@ -67,7 +107,7 @@ fn main()
 
             "bytecode:" =>
             {
-                if value != "ON".to_string() && value != "OFF".to_string()
+                if value != "ON" && value != "OFF"
                 {
                     return println!("{}", color_error(format!("Invalid bytecode setting: {}", value), &options),);
                 }
@@ -77,7 +117,7 @@ fn main()
 
             "pe:" =>
             {
-                if value != "ON".to_string() && value != "OFF".to_string()
+                if value != "ON" && value != "OFF"
                 {
                     return println!("{}", color_error(format!("Invalid pe setting: {}", value), &options),);
                 }
@@ -85,82 +125,839 @@ fn main()
                 options.pe = value == "ON";
             }
 
-            _ =>
+            "resilient:" =>
             {
-                return println!("{}", color_error(format!("Invalid setting: {}", option), &options),);
+                if value != "ON" && value != "OFF"
+                {
+                    return println!("{}", color_error(format!("Invalid resilient setting: {}", value), &options),);
+                }
+
+                options.resilient = value == "ON";
             }
-        }
-    }
 
-    match FileMap::open(filename.as_str())
-    {
-        Ok(file_bytes) =>
-        {
-            let byte_slice = if options.pe
+            "address:" =>
             {
-                let file = PeFile::from_bytes(&file_bytes);
-
-                if let Err(msg) = file
+                options.address = match value.as_str()
                 {
-                    let err_msg = format!(
-                        "Failed to open PE executable: {}\n{}",
-                        msg,
-                        ["Are you trying to load a binary file as a PE ", "executable (try pe: OFF)?"].join("")
-                    );
+                    "OFF" => None,
+                    "OFFSET" => Some(Address::Offset),
+                    "RVA" => Some(Address::Rva),
+                    "VA" => Some(Address::Va),
+
+                    _ =>
+                    {
+                        return println!("{}", color_error(format!("Invalid address setting: {}", value), &options),);
+                    }
+                };
+            }
 
-                    return println!("{}", color_error(err_msg, &options));
+            "force:" =>
+            {
+                if value != "ON" && value != "OFF"
+                {
+                    return println!("{}", color_error(format!("Invalid force setting: {}", value), &options),);
                 }
 
-                let file = file.unwrap();
-                let mut bytecode_section = None;
+                options.force = value == "ON";
+            }
 
-                // Find the section header for code
-                for section_header in file.section_headers()
+            "output:" =>
+            {
+                options.output = match value.as_str()
                 {
-                    if section_header.Characteristics & CODE_SECTION != 0
-                    {
-                        bytecode_section = Some(file.get_section_bytes(section_header).unwrap());
+                    "TEXT" => Output::Text,
+                    "JSON" => Output::Json,
+                    "ASM" => Output::Asm,
 
-                        break;
+                    _ =>
+                    {
+                        return println!("{}", color_error(format!("Invalid output setting: {}", value), &options),);
                     }
-                }
+                };
+            }
 
-                match bytecode_section
+            "pointer:" =>
+            {
+                options.pointer_size = match value.as_str()
                 {
-                    Some(bytecode) => bytecode,
-                    None =>
+                    "64" => 8,
+                    "32" => 4,
+
+                    _ =>
                     {
-                        return println!("{}", color_error("PE file is missing code section".to_string(), &options));
+                        return println!("{}", color_error(format!("Invalid pointer setting: {}", value), &options),);
                     }
+                };
+            }
+
+            "offsets:" =>
+            {
+                if value != "ON" && value != "OFF"
+                {
+                    return println!("{}", color_error(format!("Invalid offsets setting: {}", value), &options),);
                 }
+
+                options.offsets = value == "ON";
             }
-            else
+
+            "xrefs:" =>
             {
-                file_bytes.as_ref()
-            };
+                if value != "ON" && value != "OFF"
+                {
+                    return println!("{}", color_error(format!("Invalid xrefs setting: {}", value), &options),);
+                }
+
+                options.xrefs = value == "ON";
+            }
+
+            "interactive:" =>
+            {
+                if value != "ON" && value != "OFF"
+                {
+                    return println!("{}", color_error(format!("Invalid interactive setting: {}", value), &options),);
+                }
+
+                if mode != Mode::Debug && mode != Mode::Diff
+                {
+                    mode = if value == "ON" { Mode::Browse } else { Mode::Listing };
+                }
+            }
+
+            "trace:" =>
+            {
+                if value != "ON" && value != "OFF"
+                {
+                    return println!("{}", color_error(format!("Invalid trace setting: {}", value), &options),);
+                }
+
+                if mode != Mode::Debug && mode != Mode::Diff
+                {
+                    mode = if value == "ON" { Mode::Trace } else { Mode::Listing };
+                }
+            }
+
+            "coverage:" =>
+            {
+                if value != "ON" && value != "OFF"
+                {
+                    return println!("{}", color_error(format!("Invalid coverage setting: {}", value), &options),);
+                }
 
-            let mut bytes = byte_slice.iter().cloned().peekable();
+                coverage = value == "ON";
+            }
+
+            "steps:" =>
+            {
+                steps = match value.parse()
+                {
+                    Ok(steps) => steps,
+                    Err(_) =>
+                    {
+                        return println!("{}", color_error(format!("Invalid steps setting: {}", value), &options),);
+                    }
+                };
+            }
 
-            loop
+            "traversal:" =>
             {
-                let result = OpCode::disassemble(&options, &mut std::io::stdout(), &mut bytes);
+                options.traversal = match value.as_str()
+                {
+                    "LINEAR" => Traversal::Linear,
+                    "RECURSIVE" => Traversal::Recursive,
 
-                match result
+                    _ =>
+                    {
+                        return println!("{}", color_error(format!("Invalid traversal setting: {}", value), &options),);
+                    }
+                };
+            }
+
+            "base:" =>
+            {
+                load_base = match parse_hex(&value)
                 {
-                    Ok(_) => (),
+                    Some(base) => Some(base),
+                    None =>
+                    {
+                        return println!("{}", color_error(format!("Invalid base setting: {}", value), &options),);
+                    }
+                };
+            }
 
-                    Err(msg) =>
+            "start:" | "length:" | "end:" =>
+            {
+                let number = match parse_hex(&value)
+                {
+                    Some(number) => Some(number),
+                    None =>
                     {
-                        println!("{}", msg);
-                        break;
+                        let err_msg = format!("Invalid {} setting: {}", option.trim_end_matches(':'), value);
+
+                        return println!("{}", color_error(err_msg, &options),);
                     }
+                };
+
+                match option.as_str()
+                {
+                    "start:" => start = number,
+                    "length:" => length = number,
+                    _ => end = number,
                 }
             }
+
+            "graph:" =>
+            {
+                if !Path::new(&value).is_dir()
+                {
+                    return println!("{}", color_error(format!("Graph directory does not exist: {}", value), &options),);
+                }
+
+                graph = Some(PathBuf::from(value));
+            }
+
+            _ =>
+            {
+                return println!("{}", color_error(format!("Invalid setting: {}", option), &options),);
+            }
+        }
+    }
+
+    if let Some(old_filename) = old_filename
+    {
+        return diff(&options, &old_filename, &filename);
+    }
+
+    // Offsets in a binary file or RVAs in a PE image
+    let range = match (start, length, end)
+    {
+        (None, None, None) => None,
+        (_, Some(_), Some(_)) =>
+        {
+            return println!("{}", color_error("Use either length: or end:, not both".to_string(), &options));
+        }
+        (start, length, end) =>
+        {
+            let start: u64 = start.unwrap_or(0);
+            let end = length.map_or(end, |length| start.checked_add(length)).unwrap_or(u64::MAX);
+
+            if end <= start
+            {
+                let err_msg = format!("Invalid range: {:X} to {:X}", start, end);
+
+                return println!("{}", color_error(err_msg, &options));
+            }
+
+            Some(start .. end)
         }
+    };
 
+    let file_bytes = match read_input(&filename)
+    {
+        Ok(file_bytes) => file_bytes,
         Err(msg) =>
         {
             return println!("{}", color_error(format!("Error opening file: {}", msg), &options));
         }
+    };
+
+    if !options.pe
+    {
+        let file = Section {
+            name: String::new(),
+            origin: options.origin,
+            bytes: file_bytes.as_ref(),
+            virtual_size: 0,
+            characteristics: 0,
+        };
+
+        let file = match range.as_ref().map(|range| file.slice(range))
+        {
+            None => file,
+            Some(Some(slice)) => slice,
+            Some(None) => return println!("{}", color_error("Range is outside of the file".to_string(), &options)),
+        };
+
+        if options.output == Output::Asm
+        {
+            return fasm::write_binary(&mut std::io::stdout(), &options, file.bytes)
+                .unwrap_or_else(|error| failed(&options, error));
+        }
+
+        options.origin = file.origin;
+
+        let vm = Vm::new(file.bytes).pointer_size(options.pointer_size);
+
+        match mode
+        {
+            Mode::Debug => return debug(Debugger::new(vm, &options)),
+            Mode::Trace => return trace(&options, vm, None, steps, &[(0, &file)]),
+            _ => (),
+        }
+
+        let coverage = coverage.then(|| cover(&options, vm, None, steps));
+        let analysis = analyze(&options, &file, None, std::slice::from_ref(&file), &[], None, coverage.as_ref());
+
+        if mode == Mode::Browse
+        {
+            let Analysis { instructions, labels, functions, .. } = analysis;
+
+            return browse(&options, vec![Listing { section: file, instructions, labels, functions }]);
+        }
+
+        let graph = graph.as_deref().map(|graph| (graph, ""));
+
+        if let Err(error) = disassemble(&options, analysis, graph)
+        {
+            return failed(&options, error);
+        }
+
+        return completed(&options);
+    }
+
+    let image = match Image::parse(file_bytes.as_ref())
+    {
+        Ok(image) => image,
+        Err(msg) =>
+        {
+            let err_msg = format!(
+                "Failed to open PE executable: {}\n{}",
+                msg,
+                ["Are you trying to load a binary file as a PE ", "executable (try pe: OFF)?"].join("")
+            );
+
+            return println!("{}", color_error(err_msg, &options));
+        }
+    };
+
+    if options.output == Output::Text && (mode == Mode::Listing || mode == Mode::Trace)
+    {
+        println!("{}", image.emit(&options));
+
+        if let Some(base) = load_base
+        {
+            println!("{}", color_comment(format!("; Load Base:   {:016X}", base), &options));
+        }
+    }
+
+    for error in image.validate()
+    {
+        if !options.force && matches!(error, Error::NotEbc(_))
+        {
+            let err_msg = format!("{}\nThis is not an EBC image (try force: ON to disassemble it anyway)", error);
+
+            return println!("{}", color_error(err_msg, &options));
+        }
+
+        eprintln!("{}", color_error(format!("Warning: {}", error), &options));
+    }
+
+    if image.code_sections().next().is_none()
+    {
+        return println!("{}", color_error("PE file is missing code section".to_string(), &options));
+    }
+
+    let image_base = load_base.unwrap_or(image.image_base);
+    let coverage = match mode
+    {
+        Mode::Debug | Mode::Trace =>
+        {
+            let (vm, services) = match load(&options, &image)
+            {
+                Ok(loaded) => loaded,
+                Err(error) => return println!("{}", color_error(error.to_string(), &options)),
+            };
+
+            if mode == Mode::Debug
+            {
+                return debug(Debugger::new(vm, &options).services(services));
+            }
+
+            let sections = image
+                .code_sections()
+                .map(|section| Section { origin: Origin { image_base, ..section.origin }, ..section.clone() })
+                .collect::<Vec<_>>();
+            let sections = sections.iter().map(|section| (section.origin.rva, section)).collect::<Vec<_>>();
+
+            return trace(&options, vm, Some(services), steps, &sections);
+        }
+
+        _ if coverage => match load(&options, &image)
+        {
+            Ok((vm, services)) => Some(cover(&options, vm, Some(services), steps)),
+            Err(error) => return println!("{}", color_error(error.to_string(), &options)),
+        },
+
+        _ => None,
+    };
+
+    if options.output == Output::Asm
+    {
+        if range.is_some()
+        {
+            return println!("{}", color_error("A range cannot be reassembled as an image".to_string(), &options));
+        }
+
+        return fasm::write_image(&mut std::io::stdout(), &options, &image)
+            .unwrap_or_else(|error| failed(&options, error));
+    }
+
+    // Each section with the offsets of the part of it within the range
+    let sections = image
+        .code_sections()
+        .filter_map(|section| match &range
+        {
+            Some(range) => section.offsets(range).map(|offsets| (section, Some(offsets))),
+            None => Some((section, None)),
+        })
+        .collect::<Vec<_>>();
+
+    if sections.is_empty()
+    {
+        return println!("{}", color_error("Range does not contain any code".to_string(), &options));
+    }
+
+    // Functions in different sections can have the same name
+    let multiple_sections = image.code_sections().nth(1).is_some();
+    let mut listings = Vec::new();
+
+    for (section, offsets) in sections
+    {
+        if options.output == Output::Text && mode == Mode::Listing
+        {
+            let selected = range.as_ref().and_then(|range| section.slice(range));
+
+            println!("\n{}", selected.as_ref().unwrap_or(section).emit(&options));
+        }
+
+        options.origin = Origin { image_base, ..section.origin };
+
+        let entry_point = section.offset_of(image.entry_point);
+        let analysis =
+            analyze(&options, section, offsets, &image.sections, &image.relocations, entry_point, coverage.as_ref());
+
+        if mode == Mode::Browse
+        {
+            let Analysis { instructions, labels, functions, .. } = analysis;
+            let section = Section { origin: options.origin, ..section.clone() };
+
+            listings.push(Listing { section, instructions, labels, functions });
+            continue;
+        }
+
+        let prefix =
+            if multiple_sections { format!("{}_", section.name.trim_matches(['.', '\0'])) } else { String::new() };
+        let graph = graph.as_deref().map(|graph| (graph, prefix.as_str()));
+
+        if let Err(error) = disassemble(&options, analysis, graph)
+        {
+            return failed(&options, error);
+        }
+    }
+
+    if mode == Mode::Browse
+    {
+        return browse(&options, listings);
+    }
+
+    completed(&options);
+}
+
+/// A code section after decoding and every analysis pass.
+struct Analysis
+{
+    instructions: Vec<Instruction>,
+    labels: Labels,
+    functions: Functions,
+    strings: Strings,
+    xrefs: Xrefs,
+    coverage: Option<CoverageReport>, // Instructions executed in each function with coverage: ON
+    error: Option<Error>,             // First byte that could not be decoded
+}
+
+/// Decodes a code section, or only the bytes at `offsets` within it,
+/// labelling the entry point if it is within them, applying relocations for
+/// the load base and marking the instructions that were executed.
+fn analyze(
+    options: &Options,
+    section: &Section,
+    offsets: Option<Range<usize>>,
+    sections: &[Section],
+    relocations: &[Relocation],
+    entry_point: Option<usize>,
+    coverage: Option<&Coverage>,
+) -> Analysis
+{
+    let offsets = offsets.unwrap_or(0 .. section.contents().len());
+    let bytes = &section.contents()[offsets.clone()];
+    let entry_point = entry_point.filter(|offset| offsets.contains(offset));
+    let mut instructions = Vec::new();
+    let mut error = None;
+
+    if options.traversal == Traversal::Recursive
+    {
+        instructions = decode_recursive(bytes, &[entry_point.map_or(0, |offset| offset - offsets.start)]);
+    }
+    else
+    {
+        for result in Decoder::new(bytes).resilient(options.resilient)
+        {
+            match result
+            {
+                Ok(instruction) => instructions.push(instruction),
+                Err(err) => error = Some(err),
+            }
+        }
+    }
+
+    // Offsets stay relative to the section when only part of it is decoded
+    instructions.iter_mut().for_each(|instruction| instruction.offset += offsets.start);
+
+    // The origin uses the load base when one is given
+    let delta = options.origin.image_base.wrapping_sub(section.origin.image_base);
+    annotate_relocations(&mut instructions, section.origin.rva, relocations, delta);
+
+    let mut labels = Labels::new(&instructions);
+
+    if let Some(offset) = entry_point
+    {
+        labels.insert(Label::Entry(offset));
+    }
+
+    labels.annotate(&mut instructions, options);
+
+    // Files without a PE header are assumed to start at the entry point
+    let entry_point = entry_point.or((!options.pe).then_some(0));
+    annotate_services(&mut instructions, &labels, entry_point, options);
+
+    let strings = Strings::annotate(&mut instructions, section.origin.rva, sections, options);
+    let functions = Functions::discover(&instructions, &labels, entry_point);
+    let xrefs = Xrefs::new(&instructions, &options.origin);
+
+    // Binary files are executed from their first byte and PE images at RVAs
+    let base = if options.pe { section.origin.rva } else { 0 };
+    let coverage = coverage.map(|coverage| {
+        coverage.annotate(&mut instructions, base);
+        coverage.report(&instructions, &labels, &functions, base)
+    });
+
+    Analysis { instructions, labels, functions, strings, xrefs, coverage, error }
+}
+
+/// Prints the listing of a code section and writes the graph of each function
+/// when asked to.
+fn disassemble(options: &Options, analysis: Analysis, graph: Option<(&Path, &str)>) -> Result<(), Error>
+{
+    let Analysis { instructions, labels, functions, strings, xrefs, coverage, error } = analysis;
+
+    disassemble_listing(&mut std::io::stdout(), options, &instructions, &labels, &functions, &xrefs)?;
+
+    if options.output == Output::Text
+    {
+        println!("\n{}", functions.emit(options));
+
+        if !strings.is_empty()
+        {
+            println!("\n{}", strings.emit(options));
+        }
+
+        if let Some(coverage) = coverage
+        {
+            println!("\n{}", coverage.emit(options));
+        }
+    }
+
+    if let Some((directory, prefix)) = graph
+    {
+        if let Err(err) = write_graphs(directory, prefix, options, &instructions, &labels, &functions)
+        {
+            eprintln!("{}", color_error(format!("Failed to write graph: {}", err), options));
+        }
+    }
+
+    error.map_or(Ok(()), Err)
+}
+
+/// Prints a unified diff of the code sections of two files, pairing the
+/// sections of PE images by name.
+fn diff(options: &Options, old: &str, new: &str)
+{
+    let (old_input, new_input) = match (read_input(old), read_input(new))
+    {
+        (Ok(old_input), Ok(new_input)) => (old_input, new_input),
+        (Err(msg), _) | (_, Err(msg)) =>
+        {
+            return println!("{}", color_error(format!("Error opening file: {}", msg), options));
+        }
+    };
+
+    let (old_code, new_code) =
+        match (code_sections(options, old_input.as_ref()), code_sections(options, new_input.as_ref()))
+        {
+            (Ok(old_code), Ok(new_code)) => (old_code, new_code),
+            (Err(msg), _) | (_, Err(msg)) => return println!("{}", color_error(msg, options)),
+        };
+
+    println!("{}", color_error(format!("--- {}", old), options));
+    println!("{}", color_label(format!("+++ {}", new), options));
+
+    let names = new_code.iter().chain(old_code.iter()).map(|(name, _)| name);
+    let mut printed = Vec::new();
+
+    for name in names
+    {
+        if printed.contains(&name)
+        {
+            continue;
+        }
+
+        printed.push(name);
+
+        if !name.is_empty()
+        {
+            println!("\n{}", color_comment(format!("; ---- section {} ----", name), options));
+        }
+
+        println!("{}", Diff::new(section_units(&old_code, name), section_units(&new_code, name)).emit(options));
+    }
+}
+
+/// The units of the code section `name`, which may only be in one of the files.
+fn section_units<'a>(code: &'a [(String, Analysis)], name: &str) -> Vec<Unit<'a>>
+{
+    match code.iter().find(|(section, _)| section == name)
+    {
+        Some((_, analysis)) => units(&analysis.instructions, &analysis.labels, &analysis.functions),
+        None => Vec::new(),
+    }
+}
+
+/// The analyzed code sections of a file by name, or the whole file when it is
+/// not a PE image.
+fn code_sections(options: &Options, bytes: &[u8]) -> Result<Vec<(String, Analysis)>, String>
+{
+    if !options.pe
+    {
+        let file = Section { name: String::new(), origin: options.origin, bytes, virtual_size: 0, characteristics: 0 };
+        let analysis = analyze(options, &file, None, std::slice::from_ref(&file), &[], None, None);
+
+        return Ok(vec![(String::new(), analysis)]);
+    }
+
+    let image = Image::parse(bytes).map_err(|msg| format!("Failed to open PE executable: {}", msg))?;
+    let sections = image.code_sections().map(|section| {
+        let options = Options { origin: section.origin, ..*options };
+        let entry_point = section.offset_of(image.entry_point);
+        let analysis = analyze(&options, section, None, &image.sections, &image.relocations, entry_point, None);
+
+        (section.name.trim_end_matches('\0').to_string(), analysis)
+    });
+
+    Ok(sections.collect())
+}
+
+/// Maps a PE image at address 0, so that VM addresses are RVAs, and passes
+/// the mock system table to its entry point so CALLEX runs the mock services.
+fn load(options: &Options, image: &Image) -> Result<(Vm, Services), Error>
+{
+    let mut vm = Vm::new(&image.map(0)?).pointer_size(options.pointer_size);
+    let services = Services::new(&mut vm)?;
+    let mut vm = vm.arguments(&[services.image_handle, services.system_table]);
+    vm.ip = image.entry_point;
+
+    Ok((vm, services))
+}
+
+/// Runs the program to find out which instructions it executes.
+fn cover(options: &Options, mut vm: Vm, mut services: Option<Services>, steps: usize) -> Coverage
+{
+    let mut coverage = Coverage::default();
+
+    if let Err(error) = spore_disassembler::trace::trace(&mut vm, services.as_mut(), steps, |instruction| {
+        coverage.record(instruction.offset as u64);
+        Ok(())
+    })
+    {
+        eprintln!("{}", color_error(format!("Warning: {}", error), options));
+    }
+
+    coverage
+}
+
+/// Runs the program and prints each instruction as it is executed, addressed
+/// like the listing of the section it is in. Sections are paired with the
+/// address they were loaded at in the VM.
+fn trace(options: &Options, mut vm: Vm, mut services: Option<Services>, steps: usize, sections: &[(u64, &Section)])
+{
+    let mut stdout = std::io::stdout();
+    let result = spore_disassembler::trace::trace(&mut vm, services.as_mut(), steps, |mut instruction| {
+        let address = instruction.offset as u64;
+        let section = sections.iter().find(|(base, section)| {
+            address.checked_sub(*base).is_some_and(|offset| offset < section.bytes.len() as u64)
+        });
+
+        let origin = match section
+        {
+            Some((base, section)) =>
+            {
+                instruction.offset = (address - base) as usize;
+                section.origin
+            }
+
+            None => options.origin,
+        };
+
+        disassemble_instruction(&mut stdout, &Options { origin, ..*options }, &instruction)
+    });
+
+    let stop = match result
+    {
+        Ok(Step::Halt) => format!("; Program returned 0x{:X}", vm.registers[7]),
+        Ok(Step::Breakpoint) => String::from("; Stopped at BREAK 3"),
+        Ok(_) => format!("; Stopped after {} instructions", steps),
+        Err(error) => return failed(options, error),
+    };
+
+    if options.output == Output::Text
+    {
+        println!("{}", color_comment(stop, options));
+
+        if let Some(console) = services.filter(|services| !services.host.console.is_empty())
+        {
+            let lines = console.host.console.lines().map(|line| format!("; {}", line)).collect::<Vec<_>>();
+
+            println!("\n{}", color_comment(format!("; ---- console ----\n{}", lines.join("\n")), options));
+        }
+    }
+}
+
+/// Reads debugger commands from stdin until `quit` or the end of input.
+fn debug(mut debugger: Debugger)
+{
+    let mut line = String::new();
+
+    println!("{}", debugger.current());
+
+    loop
+    {
+        print!("(spore) ");
+        std::io::stdout().flush().unwrap();
+
+        line.clear();
+
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0
+        {
+            return println!();
+        }
+
+        match debugger.command(&line)
+        {
+            Some(output) if output.is_empty() => (),
+            Some(output) => println!("{}", output),
+            None => return,
+        }
+    }
+}
+
+/// Opens the interactive browser on the analyzed code sections.
+fn browse(options: &Options, listings: Vec<Listing>)
+{
+    if let Err(error) = Browser::new(listings, options).run()
+    {
+        println!("{}", color_error(format!("Terminal error: {}", error), options));
+    }
+}
+
+/// Writes `<prefix><function>.dot` for every function in the listing.
+fn write_graphs(
+    directory: &Path,
+    prefix: &str,
+    options: &Options,
+    instructions: &[Instruction],
+    labels: &Labels,
+    functions: &Functions,
+) -> std::io::Result<()>
+{
+    let graph = Graph::new(instructions, labels);
+
+    // The theme applies to the files even when stdout is not a terminal
+    colored::control::set_override(options.theme.is_some());
+
+    for function in functions.iter()
+    {
+        let path = directory.join(format!("{}{}.dot", prefix, function.name));
+        let written = std::fs::write(path, graph.dot(function.offset, instructions, labels, options) + "\n");
+
+        if written.is_err()
+        {
+            colored::control::unset_override();
+            return written;
+        }
+    }
+
+    colored::control::unset_override();
+    Ok(())
+}
+
+/// The bytes of a file, or of stdin when the filename is `-`.
+enum Input
+{
+    File(FileMap),
+    Stdin(Vec<u8>),
+}
+
+impl AsRef<[u8]> for Input
+{
+    fn as_ref(&self) -> &[u8]
+    {
+        match self
+        {
+            Self::File(file) => file.as_ref(),
+            Self::Stdin(bytes) => bytes,
+        }
+    }
+}
+
+fn read_input(filename: &str) -> std::io::Result<Input>
+{
+    if filename == "-"
+    {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes)?;
+
+        return Ok(Input::Stdin(bytes));
+    }
+
+    FileMap::open(filename).map(Input::File)
+}
+
+/// Parses a hexadecimal number with or without a `0x` prefix.
+fn parse_hex(value: &str) -> Option<u64>
+{
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+/// Decoding errors are reported as an object in JSON output and as a comment
+/// in assembler source so that the output can still be parsed.
+fn failed(options: &Options, error: Error)
+{
+    // Output that could not be written cannot carry its own error either
+    if let Error::Output(_) = error
+    {
+        return eprintln!("{}", color_error(error.to_string(), options));
+    }
+
+    match options.output
+    {
+        Output::Text => println!("{}", color_error(error.to_string(), options)),
+        Output::Json => println!("{}", json_object(&[("error", json_string(&error.to_string()))])),
+        Output::Asm => println!("; {}", error),
+    }
+}
+
+fn completed(options: &Options)
+{
+    if options.output == Output::Text
+    {
+        println!("Completed Disassembly");
     }
 }
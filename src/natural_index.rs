@@ -1,10 +1,11 @@
 use crate::bits::*;
+use crate::error::Error;
 use crate::options::Options;
 use crate::theme::*;
 
-const SIZE_OF_VOID_PTR: u16 = 8;
 const HEADER_SIZE: usize = 4;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NaturalIndex
 {
     pub value: u64,
@@ -17,8 +18,10 @@ pub struct NaturalIndex
 impl NaturalIndex
 {
     /// It is critical that the right method be selected per index size.
-    /// Do not use `from_u64()` for a 16 bit value.
-    pub fn from_u16(value: u16) -> Self
+    /// Do not use `from_u64()` for a 16 bit value. The offset is calculated
+    /// for a target with pointers that are `size_of_void_ptr` bytes wide.
+    /// Fails when the width bits give more natural bits than the index has.
+    pub fn from_u16(value: u16, size_of_void_ptr: u64) -> Result<Self, Error>
     {
         const ENCODING_SIZE: u16 = 2;
 
@@ -26,22 +29,30 @@ impl NaturalIndex
         let sign = if bits[0] { -1i64 } else { 1i64 };
         let width_base = bits_to_byte_u16(&bits[1 .. 4]);
         let actual_width = width_base * ENCODING_SIZE;
+
+        if actual_width as usize > bits.len() - HEADER_SIZE
+        {
+            return Err(Error::InvalidIndex(value as u64));
+        }
+
         let natural = bits_to_byte_u16(&bits[bits.len() - actual_width as usize ..]);
         let constant = bits_to_byte_u16(&bits[HEADER_SIZE .. bits.len() - actual_width as usize]);
-        let offset = sign * (constant + natural * SIZE_OF_VOID_PTR) as i64;
-
-        Self {
+        let index = Self {
             value: value as u64,
             sign: sign as i8,
             constant: constant as u64,
             natural: natural as u64,
-            offset: offset as i64,
-        }
+            offset: 0,
+        };
+
+        Ok(Self { offset: index.resolve(size_of_void_ptr), ..index })
     }
 
     /// It is critical that the right method be selected per index size.
-    /// Do not use `from_u64()` for a 16 bit value.
-    pub fn from_u32(value: u32) -> Self
+    /// Do not use `from_u64()` for a 16 bit value. The offset is calculated
+    /// for a target with pointers that are `size_of_void_ptr` bytes wide.
+    /// Fails when the width bits give more natural bits than the index has.
+    pub fn from_u32(value: u32, size_of_void_ptr: u64) -> Result<Self, Error>
     {
         const ENCODING_SIZE: u32 = 4;
 
@@ -49,22 +60,30 @@ impl NaturalIndex
         let sign = if bits[0] { -1i64 } else { 1i64 };
         let width_base = bits_to_byte_u32(&bits[1 .. 4]);
         let actual_width = width_base * ENCODING_SIZE;
+
+        if actual_width as usize > bits.len() - HEADER_SIZE
+        {
+            return Err(Error::InvalidIndex(value as u64));
+        }
+
         let natural = bits_to_byte_u32(&bits[bits.len() - actual_width as usize ..]);
         let constant = bits_to_byte_u32(&bits[HEADER_SIZE .. bits.len() - actual_width as usize]);
-        let offset = { sign * (constant + natural * SIZE_OF_VOID_PTR as u32) as i64 };
-
-        Self {
+        let index = Self {
             value: value as u64,
             sign: sign as i8,
             constant: constant as u64,
             natural: natural as u64,
-            offset: offset as i64,
-        }
+            offset: 0,
+        };
+
+        Ok(Self { offset: index.resolve(size_of_void_ptr), ..index })
     }
 
     /// It is critical that the right method be selected per index size.
-    /// Do not use `from_u64()` for a 16 bit value.
-    pub fn from_u64(value: u64) -> Self
+    /// Do not use `from_u64()` for a 16 bit value. The offset is calculated
+    /// for a target with pointers that are `size_of_void_ptr` bytes wide.
+    /// Fails when the width bits give more natural bits than the index has.
+    pub fn from_u64(value: u64, size_of_void_ptr: u64) -> Result<Self, Error>
     {
         const ENCODING_SIZE: u64 = 8;
 
@@ -72,11 +91,70 @@ impl NaturalIndex
         let sign = if bits[0] { -1i64 } else { 1i64 };
         let width_base = bits_to_byte_u64(&bits[1 .. 4]);
         let actual_width = width_base * ENCODING_SIZE;
+
+        if actual_width as usize > bits.len() - HEADER_SIZE
+        {
+            return Err(Error::InvalidIndex(value));
+        }
+
         let natural = bits_to_byte_u64(&bits[bits.len() - actual_width as usize ..]);
         let constant = bits_to_byte_u64(&bits[HEADER_SIZE .. bits.len() - actual_width as usize]);
-        let offset = { sign * (constant + natural * SIZE_OF_VOID_PTR as u64) as i64 };
+        let index = Self { value, sign: sign as i8, constant, natural, offset: 0 };
+
+        Ok(Self { offset: index.resolve(size_of_void_ptr), ..index })
+    }
+}
+
+impl NaturalIndex
+{
+    /// The byte offset on a target where pointers are `size_of_void_ptr` bytes.
+    pub fn resolve(&self, size_of_void_ptr: u64) -> i64
+    {
+        let offset = self.constant.wrapping_add(self.natural.wrapping_mul(size_of_void_ptr)) as i64;
+
+        offset.wrapping_mul(self.sign as i64)
+    }
+
+    /// Encodes a natural index in the smallest natural width that fits.
+    ///
+    /// Returns `None` if the natural and constant units do not fit in
+    /// `size_in_bits` together.
+    pub fn encode(negative: bool, natural: u64, constant: u64, size_in_bits: u32) -> Option<u64>
+    {
+        let encoding_size = size_in_bits / 8;
+        let fits = |value: u64, width: u32| width >= 64 || value < 1u64 << width;
+
+        for width_base in 0 .. 8
+        {
+            let natural_width = width_base * encoding_size;
+            let constant_width = (size_in_bits - HEADER_SIZE as u32).checked_sub(natural_width)?;
+
+            if fits(natural, natural_width) && fits(constant, constant_width)
+            {
+                let sign = if negative { 1u64 << (size_in_bits - 1) } else { 0 };
+                let width = (width_base as u64) << (size_in_bits - HEADER_SIZE as u32);
+                let constant = if constant_width == 0 { 0 } else { constant << natural_width };
 
-        Self { value: value, sign: sign as i8, constant: constant, natural: natural, offset: offset as i64 }
+                return Some(sign | width | constant | natural);
+            }
+        }
+
+        None
+    }
+
+    pub fn to_u16(&self) -> Option<u16>
+    {
+        Self::encode(self.sign < 0, self.natural, self.constant, 16).map(|value| value as u16)
+    }
+
+    pub fn to_u32(&self) -> Option<u32>
+    {
+        Self::encode(self.sign < 0, self.natural, self.constant, 32).map(|value| value as u32)
+    }
+
+    pub fn to_u64(&self) -> Option<u64>
+    {
+        Self::encode(self.sign < 0, self.natural, self.constant, 64)
     }
 }
 
@@ -102,45 +180,81 @@ mod tests
     #[test]
     pub fn test_natural_indexing()
     {
-        let index = NaturalIndex::from_u16(4161);
+        let index = NaturalIndex::from_u16(4161, 8).unwrap();
         assert_eq!(index.constant, 16u64);
         assert_eq!(index.natural, 1u64);
         assert_eq!(index.offset, 24i64);
 
-        let index = NaturalIndex::from_u16(4114);
+        let index = NaturalIndex::from_u16(4114, 8).unwrap();
         assert_eq!(index.constant, 4u64);
         assert_eq!(index.natural, 2u64);
         assert_eq!(index.offset, 20i64);
 
-        let index = NaturalIndex::from_u16(8581);
+        let index = NaturalIndex::from_u16(8581, 8).unwrap();
         assert_eq!(index.constant, 24u64);
         assert_eq!(index.natural, 5u64);
         assert_eq!(index.offset, 64i64);
 
-        let index = NaturalIndex::from_u32(805324752);
+        let index = NaturalIndex::from_u32(805324752, 8).unwrap();
         assert_eq!(index.constant, 4u64);
         assert_eq!(index.natural, 2000u64);
         assert_eq!(index.offset, 16004i64);
 
-        let index = NaturalIndex::from_u32(111111);
+        let index = NaturalIndex::from_u32(111111, 8).unwrap();
         assert_eq!(index.constant, 111111u64);
         assert_eq!(index.natural, 0u64);
         assert_eq!(index.offset, 111111i64);
 
-        let index = NaturalIndex::from_u64(2305843035428095952);
+        let index = NaturalIndex::from_u64(2305843035428095952, 8).unwrap();
         assert_eq!(index.constant, 400000u64);
         assert_eq!(index.natural, 2000u64);
         assert_eq!(index.offset, 416000i64);
 
-        let index = NaturalIndex::from_u32(591751049);
+        let index = NaturalIndex::from_u32(591751049, 8).unwrap();
         assert_eq!(index.constant, 214375u64);
         assert_eq!(index.natural, 137u64);
         assert_eq!(index.offset, 215471i64);
 
-        let index = NaturalIndex::from_u64(11529215072282871760);
+        let index = NaturalIndex::from_u64(11529215072282871760, 8).unwrap();
         assert_eq!(index.sign, -1i8);
         assert_eq!(index.constant, 400000u64);
         assert_eq!(index.natural, 2000u64);
         assert_eq!(index.offset, -416000i64);
     }
+
+    #[test]
+    pub fn test_natural_index_pointer_size()
+    {
+        let index = NaturalIndex::from_u16(4161, 4).unwrap();
+        assert_eq!(index.offset, 20i64);
+        assert_eq!(index.resolve(8), 24i64);
+
+        let index = NaturalIndex::from_u32(805324752, 4).unwrap();
+        assert_eq!(index.offset, 8004i64);
+
+        let index = NaturalIndex::from_u64(11529215072282871760, 4).unwrap();
+        assert_eq!(index.offset, -408000i64);
+    }
+
+    #[test]
+    pub fn test_natural_index_encoding()
+    {
+        for value in [4161u16, 4114, 8581, 36879]
+        {
+            assert_eq!(NaturalIndex::from_u16(value, 8).unwrap().to_u16(), Some(value));
+        }
+
+        for value in [805324752u32, 111111, 591751049, 2954019116]
+        {
+            assert_eq!(NaturalIndex::from_u32(value, 8).unwrap().to_u32(), Some(value));
+        }
+
+        for value in [2305843035428095952u64, 11529215072282871760]
+        {
+            assert_eq!(NaturalIndex::from_u64(value, 8).unwrap().to_u64(), Some(value));
+        }
+
+        let index = NaturalIndex::from_u16(4161, 8).unwrap();
+        assert_eq!(NaturalIndex { natural: 1 << 12, ..index }.to_u16(), None);
+    }
 }
@@ -1,9 +1,11 @@
 use crate::bits::*;
+use crate::error::Error;
 use crate::instruction::*;
+use crate::listing::disassemble_instruction;
 use crate::options::Options;
 use crate::theme::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode
 {
     ADD = 0x0C,
@@ -203,17 +205,11 @@ impl std::convert::TryFrom<u8> for OpCode
 
 impl OpCode
 {
+    /// Decodes the next instruction from the byte stream.
+    ///
+    /// Returns `Ok(None)` if only padding bytes were consumed.
     /// Bytes are read from left to right. Bits are read from right to left.
-    // pub fn disassemble<T: Iterator<Item=u8>, W: std::io::Write>(
-    //     options: &Options,
-    //     writer: &mut W,
-    //     bytes: &mut T
-    // ) -> Result<(), String>
-    pub fn disassemble<T: Iterator<Item = u8>, W: std::io::Write>(
-        options: &Options,
-        writer: &mut W,
-        bytes: &mut std::iter::Peekable<T>,
-    ) -> Result<(), String>
+    pub fn decode<T: Iterator<Item = u8>>(bytes: &mut std::iter::Peekable<T>) -> Result<Option<Instruction>, Error>
     {
         let byte0 = if let Some(byte) = bytes.next()
         {
@@ -221,7 +217,7 @@ impl OpCode
         }
         else
         {
-            return Err(String::from("Completed Disassembly"));
+            return Err(Error::Completed);
         };
 
         // * Using reverse number parsing to make indexing the individual bits
@@ -229,9 +225,9 @@ impl OpCode
 
         let byte0_bits = bits_rev(byte0);
         let op_value = bits_to_byte_rev(&byte0_bits[0 ..= 5]);
-        let op: OpCode = op_value.try_into().expect(format!("Invalid OpCode: {}", op_value).as_str());
+        let op: OpCode = op_value.try_into().map_err(|_| Error::InvalidOpCode(byte0))?;
 
-        match op
+        let instruction = match op
         {
             OpCode::BREAK =>
             {
@@ -240,44 +236,44 @@ impl OpCode
                     if *byte == 0
                     {
                         bytes.next(); // Skip this and the next zero
-                        Ok(())
+                        return Ok(None);
                     }
                     else if *byte > 6
                     // 1-6 are valid break codes
                     {
-                        Ok(()) // Only skip this zero
+                        return Ok(None); // Only skip this zero
                     }
                     else
                     {
                         // 2. INSTRUCTION ARGUMENT (BREAK)
-                        parse_instruction2(writer, options, bytes, byte0, byte0_bits, op)
+                        parse_instruction2(bytes, byte0, byte0_bits, op)
                     }
                 }
                 else
                 {
-                    Ok(())
+                    return Ok(None);
                 }
             }
 
             // 1. INSTRUCTION (RET)
-            OpCode::RET => parse_instruction1(writer, options, bytes, byte0, byte0_bits, op),
+            OpCode::RET => parse_instruction1(bytes, byte0, byte0_bits, op),
 
             OpCode::JMP8 =>
             {
                 // 2. INSTRUCTION ARGUMENT (BREAK)
-                parse_instruction2(writer, options, bytes, byte0, byte0_bits, op)
+                parse_instruction2(bytes, byte0, byte0_bits, op)
             }
 
             OpCode::CALL | OpCode::JMP | OpCode::PUSH | OpCode::PUSHn | OpCode::POP | OpCode::POPn =>
             {
                 // 3. INSTRUCTION OP1 ARGUMENT (CALL)
-                parse_instruction3(writer, options, bytes, byte0, byte0_bits, op)
+                parse_instruction3(bytes, byte0, byte0_bits, op)
             }
 
             OpCode::LOADSP | OpCode::STORESP =>
             {
                 // 4. INSTRUCTION OP1, OP2 (STORESP)
-                parse_instruction4(writer, options, bytes, byte0, byte0_bits, op)
+                parse_instruction4(bytes, byte0, byte0_bits, op)
             }
 
             OpCode::CMPIeq
@@ -290,7 +286,7 @@ impl OpCode
             | OpCode::MOVREL =>
             {
                 // 5. INSTRUCTION OP1 ARGUMENT, ARGUMENT (CMPI)
-                parse_instruction5(writer, options, bytes, byte0, byte0_bits, op)
+                parse_instruction5(bytes, byte0, byte0_bits, op)
             }
 
             OpCode::ADD
@@ -319,7 +315,7 @@ impl OpCode
             | OpCode::XOR =>
             {
                 // 6. INSTRUCTION OP1, OP2 ARGUMENT
-                parse_instruction6(writer, options, bytes, byte0, byte0_bits, op)
+                parse_instruction6(bytes, byte0, byte0_bits, op)
             }
 
             OpCode::MOVnw
@@ -337,9 +333,31 @@ impl OpCode
             | OpCode::MOVsnd =>
             {
                 // 7. INSTRUCTION OP1 ARGUMENT, OP2 ARGUMENT (MOV)
-                parse_instruction7(writer, options, bytes, byte0, byte0_bits, op)
+                parse_instruction7(bytes, byte0, byte0_bits, op)
             }
+        }?;
+
+        for argument in instruction.argument1.iter().chain(instruction.argument2.iter())
+        {
+            argument.validate()?;
         }
+
+        Ok(Some(instruction))
+    }
+
+    /// Decodes the next instruction and writes its disassembly to `writer`.
+    pub fn disassemble<T: Iterator<Item = u8>, W: std::io::Write>(
+        options: &Options,
+        writer: &mut W,
+        bytes: &mut std::iter::Peekable<T>,
+    ) -> Result<(), Error>
+    {
+        if let Some(instruction) = Self::decode(bytes)?
+        {
+            disassemble_instruction(writer, options, &instruction)?;
+        }
+
+        Ok(())
     }
 
     pub fn to(self) -> u8
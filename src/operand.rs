@@ -1,6 +1,8 @@
+use crate::error::Error;
 use crate::options::Options;
 use crate::theme::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operand
 {
     GeneralPurpose
@@ -16,18 +18,24 @@ pub enum Operand
 
 impl Operand
 {
-    pub fn new_general_purpose(register_index: u8, indirect: bool) -> Self
+    pub fn new_general_purpose(register_index: u8, indirect: bool) -> Result<Self, Error>
     {
-        assert!((0u8 ..= 7u8).contains(&register_index));
+        if !(0u8 ..= 7u8).contains(&register_index)
+        {
+            return Err(Error::InvalidGeneralPurposeRegister(register_index));
+        }
 
-        Self::GeneralPurpose { register_index, indirect }
+        Ok(Self::GeneralPurpose { register_index, indirect })
     }
 
-    pub fn new_dedicated(register_index: u8, indirect: bool) -> Self
+    pub fn new_dedicated(register_index: u8, indirect: bool) -> Result<Self, Error>
     {
-        assert!((0u8 ..= 1u8).contains(&register_index));
+        if !(0u8 ..= 1u8).contains(&register_index)
+        {
+            return Err(Error::InvalidDedicatedRegister(register_index));
+        }
 
-        Self::Dedicated { register_index, indirect }
+        Ok(Self::Dedicated { register_index, indirect })
     }
 }
 
@@ -41,8 +49,6 @@ impl Emit for Operand
             {
                 let at_sym = if *at { color_indirect("@".to_string(), options) } else { "".to_string() };
 
-                assert!((0u8 ..= 7u8).contains(&index));
-
                 color_operand(format!("{}R{}", at_sym, index), options)
             }
 
@@ -50,9 +56,12 @@ impl Emit for Operand
             {
                 let at_sym = if *at { color_indirect("@".to_string(), options) } else { "".to_string() };
 
-                assert!((0u8 ..= 1u8).contains(&index));
-
-                let result = if *index == 0 { format!("{}FLAGS", at_sym) } else { format!("{}IP", at_sym) };
+                let result = match index
+                {
+                    0 => format!("{}FLAGS", at_sym),
+                    1 => format!("{}IP", at_sym),
+                    _ => format!("{}DR{}", at_sym, index), // Reserved dedicated register
+                };
 
                 color_operand(result, options)
             }
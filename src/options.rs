@@ -1,9 +1,58 @@
-use crate::theme::Theme;
+use crate::address::{Address, Origin};
+use crate::theme::*;
+
+/// How the listing is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output
+{
+    Text, // Assembly rendered through `Emit`
+    Json, // One JSON object per instruction rendered through `Json`
+    Asm,  // fasmg-ebc source that reassembles into the original bytes
+}
+
+/// How the bytes of a code section are turned into instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Traversal
+{
+    Linear,    // Decode every byte from start to end
+    Recursive, // Only decode bytes that control can reach from the entry point
+}
 
 pub struct Options
 {
-    pub pad_output: bool,     // Padding is great for output but not for testing
-    pub theme: Option<Theme>, // Colorize assembly output (optional for pipes)
-    pub bytecode: bool,       // Output bytecode in hex notation beside assembly
-    pub pe: bool,             // Load a Windows PE file rather than a binary file
+    pub pad_output: bool,         // Padding is great for output but not for testing
+    pub theme: Option<Theme>,     // Colorize assembly output (optional for pipes)
+    pub bytecode: bool,           // Output bytecode in hex notation beside assembly
+    pub pe: bool,                 // Load a Windows PE file rather than a binary file
+    pub address: Option<Address>, // Print the address of each instruction (optional)
+    pub origin: Origin,           // Location of the bytecode within the file/image
+    pub resilient: bool,          // Emit undecodable bytes as DB instead of stopping
+    pub pointer_size: u64,        // Size of a natural unit in bytes (4 or 8)
+    pub offsets: bool,            // Show natural index byte offsets for 32 and 64 bit
+    pub force: bool,              // Disassemble PE files that are not EBC images
+    pub output: Output,           // Text, JSON or fasmg-ebc source
+    pub traversal: Traversal,     // Linear sweep or recursive descent
+    pub xrefs: bool,              // Print the instructions that reference each target
+}
+
+impl Default for Options
+{
+    fn default() -> Self
+    {
+        Self {
+            pad_output: true,
+            theme: Some(SPORE),
+            bytecode: true,
+            pe: true,
+            address: None,
+            origin: Origin::default(),
+            resilient: true,
+            pointer_size: 8,
+            offsets: false,
+            force: false,
+            output: Output::Text,
+            traversal: Traversal::Linear,
+            xrefs: false,
+        }
+    }
 }
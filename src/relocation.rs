@@ -0,0 +1,121 @@
+use pelite::image::{IMAGE_REL_BASED_DIR64, IMAGE_REL_BASED_HIGH, IMAGE_REL_BASED_HIGHLOW, IMAGE_REL_BASED_LOW};
+
+use crate::instruction::{Decoder, Instruction};
+
+/// A location that the loader patches when the image is not loaded at its
+/// preferred base, from the `.reloc` directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation
+{
+    pub rva: u64, // Address of the patched bytes
+    pub kind: u8, // IMAGE_REL_BASED_* type
+}
+
+impl Relocation
+{
+    /// Number of bytes that are patched.
+    pub fn size(&self) -> usize
+    {
+        match self.kind
+        {
+            IMAGE_REL_BASED_DIR64 => 8,
+            IMAGE_REL_BASED_HIGHLOW => 4,
+            IMAGE_REL_BASED_HIGH | IMAGE_REL_BASED_LOW => 2,
+            _ => 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match self.kind
+        {
+            IMAGE_REL_BASED_DIR64 => "DIR64",
+            IMAGE_REL_BASED_HIGHLOW => "HIGHLOW",
+            IMAGE_REL_BASED_HIGH => "HIGH",
+            IMAGE_REL_BASED_LOW => "LOW",
+            _ => "UNKNOWN",
+        }
+    }
+
+    /// Adds `delta` to the patched bytes like the loader does.
+    pub fn apply(&self, bytes: &mut [u8], delta: u64)
+    {
+        match self.kind
+        {
+            IMAGE_REL_BASED_DIR64 =>
+            {
+                let value = u64::from_le_bytes(bytes[.. 8].try_into().unwrap());
+                bytes[.. 8].copy_from_slice(&value.wrapping_add(delta).to_le_bytes());
+            }
+            IMAGE_REL_BASED_HIGHLOW =>
+            {
+                let value = u32::from_le_bytes(bytes[.. 4].try_into().unwrap());
+                bytes[.. 4].copy_from_slice(&value.wrapping_add(delta as u32).to_le_bytes());
+            }
+            IMAGE_REL_BASED_HIGH =>
+            {
+                let value = u16::from_le_bytes(bytes[.. 2].try_into().unwrap());
+                bytes[.. 2].copy_from_slice(&value.wrapping_add((delta >> 16) as u16).to_le_bytes());
+            }
+            IMAGE_REL_BASED_LOW =>
+            {
+                let value = u16::from_le_bytes(bytes[.. 2].try_into().unwrap());
+                bytes[.. 2].copy_from_slice(&value.wrapping_add(delta as u16).to_le_bytes());
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Marks every instruction with bytes covered by a relocation, such as the
+/// immediate of `MOVIqq`, `JMP64` or `CALL64`.
+///
+/// `rva` is the address of the decoded bytes and `delta` is the difference
+/// between the load base and the preferred base. When it is not zero, the
+/// relocations are applied and the instruction decoded again so that it shows
+/// the values seen in a debugger.
+pub fn annotate_relocations(instructions: &mut [Instruction], rva: u64, relocations: &[Relocation], delta: u64)
+{
+    for instruction in instructions.iter_mut()
+    {
+        let start = rva + instruction.offset as u64;
+        let covered = relocations
+            .iter()
+            .filter(|relocation| relocation.size() > 0)
+            .filter(|relocation| {
+                relocation.rva >= start
+                    && relocation.rva + relocation.size() as u64 <= start + instruction.bytecode.len() as u64
+            })
+            .collect::<Vec<_>>();
+
+        if covered.is_empty()
+        {
+            continue;
+        }
+
+        if delta != 0
+        {
+            let mut bytecode = instruction.bytecode.clone();
+
+            for relocation in covered.iter()
+            {
+                relocation.apply(&mut bytecode[(relocation.rva - start) as usize ..], delta);
+            }
+
+            if let Some(Ok(relocated)) = Decoder::new(&bytecode).next()
+            {
+                *instruction =
+                    Instruction { offset: instruction.offset, comment: instruction.comment.take(), ..relocated };
+            }
+        }
+
+        let names = covered.iter().map(|relocation| format!("reloc {}", relocation.name())).collect::<Vec<_>>();
+        let comment = match instruction.comment.take()
+        {
+            Some(comment) => format!("{}, {}", comment, names.join(", ")),
+            None => names.join(", "),
+        };
+
+        instruction.comment = Some(comment);
+    }
+}
@@ -0,0 +1,339 @@
+/*
+Mock UEFI services for running images in the VM.
+
+`Services::new()` lays out an `EFI_SYSTEM_TABLE` in VM memory using the table
+layouts from `efi`, with ConIn, ConOut, StdErr, RuntimeServices and
+BootServices filled in. Every function member points at its own fake native
+address, so when the program uses CALLEX on one of them, `Services::call()`
+knows which service it is and runs the Rust closure registered under its name.
+The closure reads the arguments the program pushed with `Vm::argument()` and
+its result is returned to the program in R7.
+
+Services without a closure return EFI_UNSUPPORTED. Any of them can be given a
+closure, or have theirs replaced, with `on()`.
+*/
+
+use std::collections::BTreeMap;
+
+use crate::efi::{Table, BOOT_SERVICES, RUNTIME_SERVICES, SYSTEM_TABLE};
+use crate::error::Error;
+use crate::vm::{Step, Vm};
+
+const SERVICE_BASE: u64 = 0xEBC0_0000; // Fake native addresses, below 4GB so they also work for 32-bit images
+const SERVICE_STRIDE: u64 = 0x10;
+const REVISION: u64 = 2 << 16 | 70; // UEFI 2.7
+const HANDLE_SIZE: u64 = 16;
+
+/// Members that are not functions, which are left as 0.
+const DATA_MEMBERS: &[&str] = &[
+    "FirmwareVendor",
+    "FirmwareRevision",
+    "ConsoleInHandle",
+    "ConsoleOutHandle",
+    "StandardErrorHandle",
+    "NumberOfTableEntries",
+    "ConfigurationTable",
+    "WaitForKey",
+    "Mode",
+];
+
+pub const EFI_SUCCESS: u64 = 0;
+pub const EFI_INVALID_PARAMETER: u64 = 2;
+pub const EFI_UNSUPPORTED: u64 = 3;
+pub const EFI_OUT_OF_RESOURCES: u64 = 9;
+pub const EFI_NOT_FOUND: u64 = 14;
+
+/// An error status, which has the highest bit of a natural unit set.
+pub fn error_status(code: u64, pointer_size: u64) -> u64
+{
+    code | 1 << (pointer_size * 8 - 1)
+}
+
+/// What the services have done, for closures to update and the host to inspect.
+#[derive(Debug, Default)]
+pub struct Host
+{
+    pub console: String,                    // Everything written to ConOut and StdErr
+    pub protocols: BTreeMap<[u8; 16], u64>, // Interface of each installed protocol GUID
+    pub pool: BTreeMap<u64, u64>,           // Address and size of each pool allocation not freed yet
+    pub calls: Vec<&'static str>,           // Name of each service called, in order
+}
+
+/// Emulates a service, returning its status.
+pub type Handler = Box<dyn FnMut(&mut Vm, &mut Host) -> Result<u64, Error>>;
+
+pub struct Services
+{
+    pub host: Host,
+    pub image_handle: u64,
+    pub system_table: u64,
+    names: BTreeMap<u64, &'static str>, // Name of the service at each fake native address
+    handlers: BTreeMap<&'static str, Handler>,
+}
+
+impl Services
+{
+    /// Builds the system table and the image handle in `vm`, which has to
+    /// have its pointer size set already. They are the arguments of the entry
+    /// point, e.g. `vm.arguments(&[services.image_handle, services.system_table])`.
+    pub fn new(vm: &mut Vm) -> Result<Self, Error>
+    {
+        let mut services = Self {
+            host: Host::default(),
+            image_handle: vm.allocate(HANDLE_SIZE)?,
+            system_table: 0,
+            names: BTreeMap::new(),
+            handlers: BTreeMap::new(),
+        };
+
+        services.system_table = services.table(vm, &SYSTEM_TABLE)?;
+
+        for name in
+            ["Reset", "SetAttribute", "ClearScreen", "SetCursorPosition", "EnableCursor", "Stall", "SetWatchdogTimer"]
+        {
+            services = services.on(name, |_, _| Ok(EFI_SUCCESS));
+        }
+
+        Ok(services
+            .on("OutputString", output_string)
+            .on("AllocatePool", allocate_pool)
+            .on("FreePool", free_pool)
+            .on("InstallProtocolInterface", install_protocol_interface)
+            .on("HandleProtocol", |vm, host| find_protocol(vm, host, 1))
+            .on("LocateProtocol", |vm, host| find_protocol(vm, host, 0))
+            .on("CopyMem", copy_mem)
+            .on("SetMem", set_mem))
+    }
+
+    /// Runs `handler` whenever the program calls the service `name`.
+    pub fn on(
+        mut self,
+        name: &'static str,
+        handler: impl FnMut(&mut Vm, &mut Host) -> Result<u64, Error> + 'static,
+    ) -> Self
+    {
+        self.handlers.insert(name, Box::new(handler));
+        self
+    }
+
+    /// The fake native address of the service `name`.
+    pub fn address(&self, name: &str) -> Option<u64>
+    {
+        self.names.iter().find(|(_, service)| **service == name).map(|(address, _)| *address)
+    }
+
+    /// Runs the service at the address of a `Step::Native`, setting R7 to its
+    /// status and returning its name.
+    pub fn call(&mut self, vm: &mut Vm, address: u64) -> Result<&'static str, Error>
+    {
+        let name = *self.names.get(&address).ok_or(Error::InvalidAddress(address))?;
+        let status = match self.handlers.get_mut(name)
+        {
+            Some(handler) => handler(vm, &mut self.host)?,
+            None => error_status(EFI_UNSUPPORTED, vm.pointer_size),
+        };
+
+        self.host.calls.push(name);
+        vm.registers[7] = status;
+
+        Ok(name)
+    }
+
+    /// Like `Vm::step()`, but runs services instead of returning `Step::Native`.
+    pub fn step(&mut self, vm: &mut Vm) -> Result<Step, Error>
+    {
+        match vm.step()?
+        {
+            Step::Native(address) => self.call(vm, address).map(|_| Step::Continue),
+            step => Ok(step),
+        }
+    }
+
+    /// Steps until the program returns or reaches a `BREAK 3`.
+    pub fn run(&mut self, vm: &mut Vm) -> Result<Step, Error>
+    {
+        loop
+        {
+            match self.step(vm)?
+            {
+                Step::Continue => (),
+                step => return Ok(step),
+            }
+        }
+    }
+
+    /// Lays out `table` and the tables it points to, returning its address.
+    fn table(&mut self, vm: &mut Vm, table: &'static Table) -> Result<u64, Error>
+    {
+        let natural = vm.pointer_size;
+        let size = table.header + table.members.len() as u64 * natural;
+        let address = vm.allocate(size)?;
+
+        if table.header > 0
+        {
+            vm.write(address, 8, signature(table))?;
+            vm.write(address + 8, 4, REVISION)?;
+            vm.write(address + 12, 4, size)?;
+        }
+
+        for (i, (name, nested)) in table.members.iter().enumerate()
+        {
+            let value = match nested
+            {
+                Some(nested) => self.table(vm, nested)?,
+                None if DATA_MEMBERS.contains(name) => continue,
+                None =>
+                {
+                    let service = SERVICE_BASE + self.names.len() as u64 * SERVICE_STRIDE;
+
+                    self.names.insert(service, name);
+                    service
+                }
+            };
+
+            vm.write(address + table.header + i as u64 * natural, natural as usize, value)?;
+        }
+
+        Ok(address)
+    }
+}
+
+/// `EFI_TABLE_HEADER.Signature` of a table with a header.
+fn signature(table: &Table) -> u64
+{
+    let signature: &[u8; 8] = match table.name
+    {
+        name if name == SYSTEM_TABLE.name => b"IBI SYST",
+        name if name == BOOT_SERVICES.name => b"BOOTSERV",
+        name if name == RUNTIME_SERVICES.name => b"RUNTSERV",
+        _ => &[0; 8],
+    };
+
+    u64::from_le_bytes(*signature)
+}
+
+/// OutputString(This, String)
+fn output_string(vm: &mut Vm, host: &mut Host) -> Result<u64, Error>
+{
+    let address = vm.argument(1)?;
+    let mut units = Vec::new();
+
+    loop
+    {
+        match vm.read(address + units.len() as u64 * 2, 2)? as u16
+        {
+            0 => break,
+            unit => units.push(unit),
+        }
+    }
+
+    host.console.extend(char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+
+    Ok(EFI_SUCCESS)
+}
+
+/// AllocatePool(PoolType, Size, Buffer)
+fn allocate_pool(vm: &mut Vm, host: &mut Host) -> Result<u64, Error>
+{
+    let size = vm.argument(1)?;
+    let buffer = vm.argument(2)?;
+    let address = match vm.allocate(size)
+    {
+        Ok(address) => address,
+        Err(_) => return Ok(error_status(EFI_OUT_OF_RESOURCES, vm.pointer_size)),
+    };
+
+    vm.write(buffer, vm.pointer_size as usize, address)?;
+    host.pool.insert(address, size);
+
+    Ok(EFI_SUCCESS)
+}
+
+/// FreePool(Buffer)
+fn free_pool(vm: &mut Vm, host: &mut Host) -> Result<u64, Error>
+{
+    match host.pool.remove(&vm.argument(0)?)
+    {
+        Some(_) => Ok(EFI_SUCCESS),
+        None => Ok(error_status(EFI_INVALID_PARAMETER, vm.pointer_size)),
+    }
+}
+
+/// InstallProtocolInterface(Handle, Protocol, InterfaceType, Interface)
+fn install_protocol_interface(vm: &mut Vm, host: &mut Host) -> Result<u64, Error>
+{
+    let guid = guid(vm, vm.argument(1)?)?;
+
+    host.protocols.insert(guid, vm.argument(3)?);
+
+    Ok(EFI_SUCCESS)
+}
+
+/// HandleProtocol(Handle, Protocol, Interface) and
+/// LocateProtocol(Protocol, Registration, Interface), which only differ in
+/// which argument the protocol is.
+fn find_protocol(vm: &mut Vm, host: &mut Host, protocol: u64) -> Result<u64, Error>
+{
+    let guid = guid(vm, vm.argument(protocol)?)?;
+    let interface = vm.argument(2)?;
+
+    match host.protocols.get(&guid)
+    {
+        Some(protocol) =>
+        {
+            vm.write(interface, vm.pointer_size as usize, *protocol)?;
+            Ok(EFI_SUCCESS)
+        }
+
+        None => Ok(error_status(EFI_NOT_FOUND, vm.pointer_size)),
+    }
+}
+
+/// CopyMem(Destination, Source, Length)
+fn copy_mem(vm: &mut Vm, _host: &mut Host) -> Result<u64, Error>
+{
+    let (destination, source, length) = (vm.argument(0)?, vm.argument(1)?, vm.argument(2)?);
+
+    // Copied through a buffer since the ranges may overlap
+    let bytes = match usize::try_from(length).map(|length| vm.bytes(source, length))
+    {
+        Ok(Ok(bytes)) => bytes.to_vec(),
+        _ => return Ok(error_status(EFI_INVALID_PARAMETER, vm.pointer_size)),
+    };
+
+    match vm.bytes_mut(destination, bytes.len())
+    {
+        Ok(destination) =>
+        {
+            destination.copy_from_slice(&bytes);
+            Ok(EFI_SUCCESS)
+        }
+
+        Err(_) => Ok(error_status(EFI_INVALID_PARAMETER, vm.pointer_size)),
+    }
+}
+
+/// SetMem(Buffer, Size, Value)
+fn set_mem(vm: &mut Vm, _host: &mut Host) -> Result<u64, Error>
+{
+    let (buffer, size, value) = (vm.argument(0)?, vm.argument(1)?, vm.argument(2)? as u8);
+
+    match usize::try_from(size).map(|size| vm.bytes_mut(buffer, size))
+    {
+        Ok(Ok(bytes)) =>
+        {
+            bytes.fill(value);
+            Ok(EFI_SUCCESS)
+        }
+
+        _ => Ok(error_status(EFI_INVALID_PARAMETER, vm.pointer_size)),
+    }
+}
+
+fn guid(vm: &Vm, address: u64) -> Result<[u8; 16], Error>
+{
+    let mut guid = [0; 16];
+    guid.copy_from_slice(vm.bytes(address, 16)?);
+
+    Ok(guid)
+}
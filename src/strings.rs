@@ -0,0 +1,185 @@
+use crate::image::Section;
+use crate::instruction::{Instruction, Reference};
+use crate::options::Options;
+use crate::theme::*;
+
+/// How the characters of a string are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding
+{
+    Ascii, // One byte per character
+    Utf16, // Two bytes per character, as used by UEFI (CHAR16)
+}
+
+impl Encoding
+{
+    pub fn width(&self) -> usize
+    {
+        match self
+        {
+            Self::Ascii => 1,
+            Self::Utf16 => 2,
+        }
+    }
+}
+
+/// A NUL terminated string of printable characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringLiteral
+{
+    pub text: String, // Without the terminator
+    pub encoding: Encoding,
+}
+
+impl StringLiteral
+{
+    /// Number of bytes the string occupies, including the terminator.
+    pub fn length(&self) -> usize
+    {
+        (self.text.len() + 1) * self.encoding.width()
+    }
+}
+
+/// Rendered like a C literal, with an `L` prefix for UTF-16.
+impl Emit for StringLiteral
+{
+    fn emit(&self, _options: &Options) -> String
+    {
+        let prefix = if self.encoding == Encoding::Utf16 { "L" } else { "" };
+
+        format!("{}{:?}", prefix, self.text)
+    }
+}
+
+/// The string at the start of `bytes`, if there is one of at least
+/// `minimum_length` characters. UTF-16 is tried first since the first
+/// character of a UTF-16 string is also a one character ASCII string.
+pub fn string_at(bytes: &[u8], minimum_length: usize) -> Option<StringLiteral>
+{
+    [Encoding::Utf16, Encoding::Ascii].into_iter().find_map(|encoding| {
+        let mut text = String::new();
+
+        for unit in bytes.chunks_exact(encoding.width())
+        {
+            let value = unit.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32);
+
+            match char::from_u32(value)?
+            {
+                '\0' if text.len() >= minimum_length => return Some(StringLiteral { text, encoding }),
+                character @ (' ' ..= '~' | '\t' | '\n' | '\r') => text.push(character),
+                _ => return None,
+            }
+        }
+
+        None
+    })
+}
+
+/// A `MOVREL` that loads the address of a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringReference
+{
+    pub offset: usize,   // Offset of the MOVREL within its section
+    pub section: String, // Name of the section containing the string
+    pub rva: u64,        // Address of the string
+    pub string: StringLiteral,
+}
+
+/// Every string referenced by the code of a section.
+#[derive(Debug, Default)]
+pub struct Strings
+{
+    references: Vec<StringReference>,
+}
+
+impl Strings
+{
+    /// Resolves the target of every `MOVREL` to a section. Targets in other
+    /// sections are described by section and RVA instead of offset, and
+    /// strings are appended to the comment.
+    ///
+    /// `rva` is the address of the decoded bytes, which is zero for files
+    /// that are not PE images.
+    pub fn annotate(instructions: &mut [Instruction], rva: u64, sections: &[Section], options: &Options) -> Self
+    {
+        let mut references = Vec::new();
+
+        for instruction in instructions.iter_mut()
+        {
+            let target = match instruction.reference()
+            {
+                Some((Reference::Load, target)) => (rva as i64).checked_add(target).and_then(|t| u64::try_from(t).ok()),
+                _ => None,
+            };
+
+            let (target, section) = match target.and_then(|t| Some((t, section_at(sections, t)?)))
+            {
+                Some(found) => found,
+                None => continue,
+            };
+
+            let offset = (target - section.origin.rva) as usize;
+            let string = string_at(&section.contents()[offset ..], 1);
+            let mut comment = match section.origin.rva == rva
+            {
+                true => instruction.comment.take().unwrap_or_default(),
+                false => format!("-> {}:{:08X}", section.name, target),
+            };
+
+            if let Some(string) = string
+            {
+                comment = format!("{} {}", comment, string.emit(options)).trim_start().to_string();
+
+                references.push(StringReference {
+                    offset: instruction.offset,
+                    section: section.name.clone(),
+                    rva: target,
+                    string,
+                });
+            }
+
+            instruction.comment = Some(comment);
+        }
+
+        Self { references }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StringReference>
+    {
+        self.references.iter()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.references.is_empty()
+    }
+}
+
+/// The string cross-reference list printed after the listing.
+impl Emit for Strings
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        let mut lines = vec![String::from("; ---- strings ----")];
+
+        for reference in self.iter()
+        {
+            lines.push(format!(
+                "; {:04X}  -> {}:{:08X}  {}",
+                reference.offset,
+                reference.section,
+                reference.rva,
+                reference.string.emit(options)
+            ));
+        }
+
+        color_comment(lines.join("\n"), options)
+    }
+}
+
+fn section_at<'a, 'b>(sections: &'a [Section<'b>], rva: u64) -> Option<&'a Section<'b>>
+{
+    sections
+        .iter()
+        .find(|section| (section.origin.rva .. section.origin.rva + section.contents().len() as u64).contains(&rva))
+}
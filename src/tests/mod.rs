@@ -1,15 +1,40 @@
 use std::io::Cursor;
 
 use super::*;
+use crate::address::{Address, Origin};
+use crate::argument::Argument;
+use crate::assembler::{assemble, assemble_instruction, parse_operand_offset};
+use crate::browser::{Browser, Key, Listing};
+use crate::debugger::Debugger;
+use crate::diff::{units, Change, Diff, Unit};
+use crate::efi::{annotate_services, SYSTEM_TABLE};
+use crate::fasm::{data_directives, write_binary, write_image};
+use crate::function::Functions;
+use crate::graph::{Edge, Graph};
+use crate::image::{Image, Section};
+use crate::instruction::{decode_at, Reference};
+use crate::json::{json_string, Json};
+use crate::label::{Label, Labels};
+use crate::listing::disassemble_listing;
+use crate::natural_index::NaturalIndex;
+use crate::operand::Operand;
+use crate::options::{Output, Traversal};
+use crate::relocation::{annotate_relocations, Relocation};
+use crate::services::{
+    error_status, Services, EFI_INVALID_PARAMETER, EFI_NOT_FOUND, EFI_OUT_OF_RESOURCES, EFI_SUCCESS, EFI_UNSUPPORTED,
+};
+use crate::strings::{string_at, Encoding, Strings};
+use crate::theme::Emit;
+use crate::trace::{trace, Coverage, FunctionCoverage};
+use crate::traversal::decode_recursive;
+use crate::vm::{Step, Vm};
+use crate::xref::Xrefs;
 
 fn dis(options: &Options, cursor: &mut Cursor<Vec<u8>>, bytecode: &[u8]) -> String
 {
-    let result = OpCode::disassemble(&options, cursor, &mut bytecode.into_iter().cloned().peekable());
-
-    match result
+    if let Err(msg) = OpCode::disassemble(options, cursor, &mut bytecode.iter().cloned().peekable())
     {
-        Ok(_) => assert!(true),
-        Err(msg) => assert!(false, "{}", msg.to_string()),
+        panic!("{}", msg);
     }
 
     let disassembly = String::from_utf8(cursor.get_ref().clone()).unwrap();
@@ -19,7 +44,51 @@ fn dis(options: &Options, cursor: &mut Cursor<Vec<u8>>, bytecode: &[u8]) -> Stri
     // Remove last character since that will always be a "\n"
     let mut chars = disassembly.chars();
     chars.next_back();
-    chars.as_str().to_string()
+    let disassembly = chars.as_str().to_string();
+
+    // Reassembling the disassembly must produce identical bytes
+    match assemble_instruction(&disassembly)
+    {
+        Ok(reassembled) => assert_eq!(canonical(&reassembled), canonical(bytecode), "{}", disassembly),
+        Err(msg) => panic!("{}: {}", disassembly, msg),
+    }
+
+    disassembly
+}
+
+/// Some bits are ignored by the decoder and can't be recovered from the text:
+/// the condition bit of unconditional jumps, and the operand bits of JMP64 and
+/// CALL64 which always read 64 bits of immediate data. CALL64 is also always
+/// decoded as an absolute address.
+fn canonical(bytecode: &[u8]) -> Vec<u8>
+{
+    let mut bytecode = bytecode.to_vec();
+    let op = bytecode[0] & 0b00111111;
+    let is_64_bit = bytecode[0] & 0b01000000 != 0;
+
+    if op == OpCode::JMP8.to() && bytecode[0] & 0b10000000 == 0
+    {
+        bytecode[0] &= 0b00111111;
+    }
+
+    if op == OpCode::JMP.to() && bytecode[1] & 0b10000000 == 0
+    {
+        bytecode[1] &= 0b00111111;
+    }
+
+    if is_64_bit && op == OpCode::JMP.to()
+    {
+        bytecode[0] |= 0b10000000;
+        bytecode[1] &= 0b11110000;
+    }
+
+    if is_64_bit && op == OpCode::CALL.to()
+    {
+        bytecode[0] |= 0b10000000;
+        bytecode[1] &= 0b11100000;
+    }
+
+    bytecode
 }
 
 fn byte(bit8: u8, bit7: u8, op: OpCode) -> u8
@@ -36,10 +105,75 @@ fn byte(bit8: u8, bit7: u8, op: OpCode) -> u8
     byte
 }
 
+/// A minimal EBC application with a single `.text` section at RVA 0x1000.
+fn pe_image(pe32: bool, machine: u16, code: &[u8], entry_point: u32) -> Vec<u8>
+{
+    const FILE_ALIGNMENT: u32 = 0x200;
+    const SECTION_ALIGNMENT: u32 = 0x1000;
+    const IMAGE_BASE: u64 = 0x10000000;
+
+    let raw_size = (code.len() as u32).div_ceil(FILE_ALIGNMENT).max(1) * FILE_ALIGNMENT;
+    let optional_header_size: u16 = if pe32 { 0xE0 } else { 0xF0 };
+    let mut image = vec![0u8; FILE_ALIGNMENT as usize];
+
+    image[0 .. 2].copy_from_slice(b"MZ");
+    image[0x3C .. 0x40].copy_from_slice(&0x40u32.to_le_bytes());
+
+    let mut headers = b"PE\0\0".to_vec();
+    headers.extend(machine.to_le_bytes());
+    headers.extend(1u16.to_le_bytes()); // NumberOfSections
+    headers.extend([0; 12]);
+    headers.extend(optional_header_size.to_le_bytes());
+    headers.extend(0x0102u16.to_le_bytes()); // Characteristics
+
+    headers.extend((if pe32 { 0x10Bu16 } else { 0x20Bu16 }).to_le_bytes());
+    headers.extend([0; 14]);
+    headers.extend(entry_point.to_le_bytes());
+    headers.extend(SECTION_ALIGNMENT.to_le_bytes()); // BaseOfCode
+
+    if pe32
+    {
+        headers.extend(0u32.to_le_bytes()); // BaseOfData
+        headers.extend((IMAGE_BASE as u32).to_le_bytes());
+    }
+    else
+    {
+        headers.extend(IMAGE_BASE.to_le_bytes());
+    }
+
+    headers.extend(SECTION_ALIGNMENT.to_le_bytes());
+    headers.extend(FILE_ALIGNMENT.to_le_bytes());
+    headers.extend([0; 16]);
+    headers.extend((SECTION_ALIGNMENT * 2).to_le_bytes()); // SizeOfImage
+    headers.extend(FILE_ALIGNMENT.to_le_bytes()); // SizeOfHeaders
+    headers.extend(0u32.to_le_bytes());
+    headers.extend(10u16.to_le_bytes()); // Subsystem: EFI application
+    headers.extend(0u16.to_le_bytes());
+    headers.extend(vec![0; if pe32 { 16 } else { 32 }]); // Stack and heap sizes
+    headers.extend(0u32.to_le_bytes());
+    headers.extend(16u32.to_le_bytes()); // NumberOfRvaAndSizes
+    headers.extend([0; 16 * 8]);
+
+    headers.extend(b".text\0\0\0");
+    headers.extend((code.len() as u32).to_le_bytes());
+    headers.extend(SECTION_ALIGNMENT.to_le_bytes());
+    headers.extend(raw_size.to_le_bytes());
+    headers.extend(FILE_ALIGNMENT.to_le_bytes());
+    headers.extend([0; 12]);
+    headers.extend(0x60000020u32.to_le_bytes()); // Code, execute and read
+
+    image[0x40 .. 0x40 + headers.len()].copy_from_slice(&headers);
+    image.extend(code);
+    image.resize((FILE_ALIGNMENT + raw_size) as usize, 0);
+
+    image
+}
+
 #[test]
 pub fn test_instruction_disassembly()
 {
-    let opts = &Options { theme: None, bytecode: false, pad_output: false, pe: false };
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
 
     let cur = &mut Cursor::new(Vec::with_capacity(50));
 
@@ -2391,3 +2525,1143 @@ pub fn test_instruction_disassembly()
         dis(opts, cur, &[&[byte(1, 1, OpCode::OR), 0b10101001][..], &(36879u16).to_le_bytes()[..],].concat())
     );
 }
+
+#[test]
+pub fn test_instruction_decoding()
+{
+    let bytecode = [
+        &[OpCode::MOVnw.to() | 0b10000000, 0b00010001][..],
+        &(4161u16).to_le_bytes()[..],
+        &[0, 0][..], // Padding
+        &[OpCode::RET.to()][..],
+    ]
+    .concat();
+
+    let instructions = Decoder::new(&bytecode).collect::<Result<Vec<_>, _>>().unwrap();
+
+    assert_eq!(instructions.len(), 2);
+
+    let movnw = &instructions[0];
+    assert_eq!(movnw.op, Some(OpCode::MOVnw));
+    assert_eq!(movnw.offset, 0);
+    assert_eq!(movnw.length(), 4);
+    assert_eq!(movnw.mnemonic(), "MOVnw");
+    assert_eq!(movnw.operand1, Some(Operand::GeneralPurpose { register_index: 1, indirect: false }));
+    assert_eq!(movnw.argument1, Some(Argument::Index16(4161)));
+    assert_eq!(movnw.operand2, Some(Operand::GeneralPurpose { register_index: 1, indirect: false }));
+    assert_eq!(movnw.argument2, None);
+
+    let ret = &instructions[1];
+    assert_eq!(ret.op, Some(OpCode::RET));
+    assert_eq!(ret.offset, 6);
+    assert_eq!(ret.bytecode.as_slice(), &[OpCode::RET.to()]);
+}
+
+#[test]
+pub fn test_address_column()
+{
+    let origin = Origin { file_offset: 0x400, rva: 0x1000, image_base: 0x10000000 };
+    let mut opts = Options {
+        theme: None,
+        bytecode: false,
+        pad_output: false,
+        pe: true,
+        address: None,
+        origin,
+        ..Default::default()
+    };
+    let bytecode = [OpCode::PUSHn.to(), 0b00000010, OpCode::RET.to()];
+    let lines = |options: &Options| {
+        Decoder::new(&bytecode).map(|instruction| instruction.unwrap().emit(options)).collect::<Vec<_>>()
+    };
+
+    assert_eq!(lines(&opts), ["PUSHn R2", "RET"]);
+
+    opts.address = Some(Address::Offset);
+    assert_eq!(lines(&opts), ["0000  00000400  PUSHn R2", "0002  00000402  RET"]);
+
+    opts.address = Some(Address::Rva);
+    assert_eq!(lines(&opts), ["0000  00001000  PUSHn R2", "0002  00001002  RET"]);
+
+    opts.address = Some(Address::Va);
+    assert_eq!(lines(&opts), ["0000  0000000010001000  PUSHn R2", "0002  0000000010001002  RET"]);
+}
+
+#[test]
+pub fn test_relative_targets()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+    let bytecode = [
+        &[OpCode::RET.to()][..],
+        &[byte(1, 1, OpCode::JMP8)][..],
+        &(-2i8).to_le_bytes()[..],
+        &[byte(1, 0, OpCode::CALL), 0b00010000][..],
+        &(1i32).to_le_bytes()[..],
+        &[OpCode::RET.to()][..],
+        &[OpCode::RET.to()][..],
+        &[byte(1, 0, OpCode::JMP), 0b00010001][..], // Depends on R1
+        &(-16i32).to_le_bytes()[..],
+        &[byte(0, 1, OpCode::MOVREL), 0b00000010][..],
+        &(0x100i16).to_le_bytes()[..],
+    ]
+    .concat();
+
+    let mut instructions = Decoder::new(&bytecode).collect::<Result<Vec<_>, _>>().unwrap();
+
+    assert_eq!(instructions[1].reference(), Some((Reference::Jump, -1)));
+    assert_eq!(instructions[2].reference(), Some((Reference::Call, 10)));
+    assert_eq!(instructions[5].reference(), None);
+    assert_eq!(instructions[6].reference(), Some((Reference::Load, 0x115)));
+
+    let labels = Labels::new(&instructions);
+    assert_eq!(labels.iter().collect::<Vec<_>>(), [&Label::Subroutine(10)]);
+
+    labels.annotate(&mut instructions, opts);
+    let lines = instructions.iter().map(|instruction| instruction.emit(opts)).collect::<Vec<_>>();
+
+    assert_eq!(lines[1], "JMP8cs -2  ;; -> -0001");
+    assert_eq!(lines[2], "CALL32 R0 1  ;; -> sub_000A");
+    assert_eq!(lines[5], "JMP32 R1 -16  ;; Relative Address");
+    assert_eq!(lines[6], "MOVRELw R2, 256  ;; -> 0115");
+}
+
+#[test]
+pub fn test_invalid_bytes()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+    let bytecode = [OpCode::RET.to(), 0xFF, OpCode::STORESP.to(), 0b01110001, OpCode::PUSHn.to()];
+
+    let mut strict = Decoder::new(&bytecode);
+    assert_eq!(strict.next().unwrap().unwrap().op, Some(OpCode::RET));
+    assert_eq!(strict.next(), Some(Err(Error::InvalidOpCode(0xFF))));
+    assert_eq!(strict.next(), None);
+
+    let lines =
+        Decoder::new(&bytecode).resilient(true).map(|instruction| instruction.unwrap().emit(opts)).collect::<Vec<_>>();
+
+    assert_eq!(lines, [
+        "RET",
+        "DB 0xFF  ;; invalid opcode",
+        "DB 0x2A  ;; Invalid dedicated register: 7",
+        "DB 0x71  ;; Unexpected end of byte stream",
+        "DB 0x35  ;; Unexpected end of byte stream",
+    ]);
+
+    // A 16 bit index cannot have 14 natural bits
+    let index = [0xB2, 0xA1, 0x00, 0x70];
+    assert_eq!(Decoder::new(&index).next(), Some(Err(Error::InvalidIndex(0x7000))));
+    assert_eq!(
+        Decoder::new(&index).resilient(true).next().unwrap().unwrap().emit(opts),
+        "DB 0xB2  ;; Invalid natural index: 0x7000"
+    );
+    assert_eq!(NaturalIndex::from_u16(0x7000, 8), Err(Error::InvalidIndex(0x7000)));
+    assert_eq!(Vm::new(&index).step(), Err(Error::InvalidIndex(0x7000)));
+
+    assert_eq!(Operand::new_general_purpose(8, false), Err(Error::InvalidGeneralPurposeRegister(8)));
+    assert_eq!(Operand::new_dedicated(2, false), Err(Error::InvalidDedicatedRegister(2)));
+}
+
+#[test]
+pub fn test_assemble_listing()
+{
+    let listing = "
+        loc_0000:
+            MOVnw R1, @R0(+1, +16)
+            MOVnw R1, @R1(+5, +24)
+            MOVRELw R2, 4084  ;; -> 0FFE
+            PUSHn R2
+            PUSHn R1
+            CALL32EXa @R1(+1, +0)
+            MOVqw R0, R0(+2, +0)
+            JMP8 -14  ;; -> loc_0000
+            RET
+    ";
+
+    assert_eq!(
+        assemble(listing),
+        Ok(vec![
+            0x72, 0x81, 0x41, 0x10, 0x72, 0x91, 0x85, 0x21, 0x79, 0x02, 0xF4, 0x0F, 0x35, 0x02, 0x35, 0x01, 0x83, 0x29,
+            0x01, 0x00, 0x00, 0x10, 0x60, 0x00, 0x02, 0x10, 0x02, 0xF2, 0x04,
+        ])
+    );
+
+    assert_eq!(assemble("RET\nPUSHn R9"), Err(Error::AtLine(2, Box::new(Error::InvalidGeneralPurposeRegister(9)))));
+    assert!(matches!(assemble("FOO R1"), Err(Error::AtLine(1, _))));
+    assert_eq!(assemble_instruction("AD€ R1, R2"), Err(Error::Syntax(String::from("Unknown instruction: AD€"))));
+}
+
+#[test]
+pub fn test_vm_execution()
+{
+    let program = assemble(
+        "
+        MOVIqw R1, 7
+        MOVIqw R2, 5
+        MUL64 R1, R2
+        ADD64 R1, R2 -10
+        MOVIqw R3, -4
+        DIV64 R1, R3
+        MOVIqw R4, 100
+        MODU64 R4, R2 2
+        NEG32 R5, R3
+        PUSH64 R1
+        PUSHn R4
+        POPn R6
+        POP64 R7
+        CMPI64weq R6, 2
+        JMP8cs 2
+        MOVIqw R6, 0
+        RET
+        ",
+    )
+    .unwrap();
+
+    let mut vm = Vm::new(&program);
+    let stack_top = vm.registers[0];
+
+    assert_eq!(vm.run(), Ok(Step::Halt));
+    assert_eq!(vm.registers[1] as i64, -7);
+    assert_eq!(vm.registers[4], 2);
+    assert_eq!(vm.registers[5], 4);
+    assert_eq!(vm.registers[6], 2);
+    assert_eq!(vm.registers[7] as i64, -7);
+    assert_eq!(vm.registers[0], stack_top);
+
+    // Relative call to a subroutine that returns to the caller
+    let program = assemble("CALL32 R0 5\nMOVIqw R1, 1\nRET\nMOVIqw R2, 2\nRET").unwrap();
+    let mut vm = Vm::new(&program);
+
+    assert_eq!(vm.step(), Ok(Step::Continue));
+    assert_eq!(vm.ip, 11);
+    assert_eq!(vm.run(), Ok(Step::Halt));
+    assert_eq!(vm.registers[1 ..= 2], [1, 2]);
+
+    // Natural indexes depend on the size of a pointer
+    let program = assemble("MOVInw R1, (+2, +4)\nMOVqw @R0(-2, +0), R1\nMOVdw R2, @R0(-2, +0)\nRET").unwrap();
+    let mut vm = Vm::new(&program).pointer_size(4);
+
+    assert_eq!(vm.run(), Ok(Step::Halt));
+    assert_eq!(vm.registers[1 ..= 2], [12, 12]);
+
+    let mut vm = Vm::new(&program);
+
+    assert_eq!(vm.run(), Ok(Step::Halt));
+    assert_eq!(vm.registers[1 ..= 2], [20, 20]);
+
+    // STORESP reads IP as the address of the next instruction
+    let program = assemble("MOVIqw R1, 1\nSTORESP R2, IP\nRET").unwrap();
+    let mut vm = Vm::new(&program);
+
+    assert_eq!(vm.run(), Ok(Step::Halt));
+    assert_eq!(vm.registers[2], 6);
+
+    // Native calls and breakpoints are handed back to the host
+    let program = assemble("MOVIqw R1, 4660\nCALL32EXa R1\nBREAK 3\nDIVU64 R1, R2").unwrap();
+    let mut vm = Vm::new(&program);
+
+    assert_eq!(vm.run(), Ok(Step::Native(0x1234)));
+    assert_eq!(vm.run(), Ok(Step::Breakpoint));
+    assert_eq!(vm.run(), Err(Error::DivideByZero));
+}
+
+#[test]
+pub fn test_natural_index_offsets()
+{
+    let opts = &Options {
+        theme: None,
+        bytecode: false,
+        pad_output: false,
+        pe: false,
+        address: None,
+        offsets: true,
+        ..Default::default()
+    };
+
+    let bytecode = [0x72, 0x81, 0x41, 0x10, 0x32, 0x91, 0x35, 0x02, 0x81, 0x09, 0x01, 0x01, 0x00, 0x90];
+    let lines = Decoder::new(&bytecode).map(|instruction| instruction.unwrap().emit(opts)).collect::<Vec<_>>();
+
+    assert_eq!(lines, [
+        "MOVnw R1, @R0(+1, +16)  ;; +20 / +24 (32 / 64-bit)",
+        "MOVn R1, @R1",
+        "PUSHn R2",
+        "JMP32 @R1(-1, -16)  ;; Absolute Address, -20 / -24 (32 / 64-bit)",
+    ]);
+
+    let movnw = Decoder::new(&bytecode).next().unwrap().unwrap();
+    assert_eq!(movnw.argument2.unwrap().natural_index(4).unwrap().offset, 20);
+    assert_eq!(movnw.argument2.unwrap().natural_index(8).unwrap().offset, 24);
+}
+
+#[test]
+pub fn test_entry_point_label()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+    let bytecode = [OpCode::RET.to(), OpCode::JMP8.to(), 0xFF];
+    let section = Section {
+        name: String::from(".text"),
+        origin: Origin { file_offset: 0x400, rva: 0x2000, image_base: 0 },
+        bytes: &bytecode,
+        virtual_size: 3,
+        characteristics: 0x60000020,
+    };
+
+    assert_eq!(section.offset_of(0x2001), Some(1));
+    assert_eq!(section.offset_of(0x2003), None);
+    assert_eq!(section.offset_of(0x1000), None);
+    assert_eq!(section.emit(opts), "; ---- section .text (RVA 00002000, 3 bytes) ----");
+
+    let mut instructions = Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let mut labels = Labels::new(&instructions);
+    assert_eq!(labels.get(1), Some(&Label::Location(1)));
+
+    labels.insert(Label::Entry(1));
+    labels.annotate(&mut instructions, opts);
+
+    assert_eq!(labels.get(1), Some(&Label::Entry(1)));
+    assert_eq!(instructions[1].emit(opts), "JMP8 -1  ;; -> entry");
+}
+
+#[test]
+pub fn test_pe_images()
+{
+    let code = [OpCode::RET.to(), OpCode::JMP8.to(), 0xFF];
+
+    for pe32 in [false, true]
+    {
+        let bytes = pe_image(pe32, 0x0EBC, &code, 0x1001);
+        let image = Image::parse(&bytes).unwrap();
+
+        assert_eq!(image.entry_point, 0x1001);
+        assert_eq!(image.image_base, 0x10000000);
+        assert_eq!(image.sections.len(), 1);
+        assert_eq!(image.sections[0].name, ".text");
+        assert_eq!(image.sections[0].origin, Origin { file_offset: 0x200, rva: 0x1000, image_base: 0x10000000 });
+        assert_eq!(image.sections[0].bytes[.. 3], code);
+        assert_eq!(image.sections[0].offset_of(image.entry_point), Some(1));
+        assert_eq!(image.pe32, pe32);
+        assert_eq!(image.validate(), []);
+    }
+
+    let opts = &Options { theme: None, ..Default::default() };
+    let bytes = pe_image(false, 0x8664, &code, 0x1000);
+    let image = Image::parse(&bytes).unwrap();
+
+    assert_eq!(image.validate(), [Error::NotEbc(0x8664)]);
+    assert!(image.relocations.is_empty());
+    assert_eq!(Image { subsystem: 3, ..image.clone() }.validate(), [Error::NotEbc(0x8664), Error::NotEfiSubsystem(3)]);
+    assert_eq!(image.emit(opts).lines().nth(1), Some("; Machine:     0x8664 (x64)"));
+
+    // Sizes from the headers are checked before anything is allocated
+    let mut huge = image.clone();
+    assert_eq!(huge.map(0).unwrap()[0x1000 .. 0x1003], code);
+    huge.sections[0].virtual_size = 0xFFFF_FFFF;
+    assert_eq!(huge.map(0), Err(Error::ImageTooLarge(0x1_0000_0FFF)));
+
+    assert!(Image::parse(&code).is_err());
+}
+
+#[test]
+pub fn test_json_output()
+{
+    let opts = &Options { output: Output::Json, address: None, ..Default::default() };
+    let bytecode = [0x72, 0x81, 0x41, 0x10, 0x02, 0xFD];
+    let mut instructions = Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let labels = Labels::new(&instructions);
+    labels.annotate(&mut instructions, opts);
+
+    assert_eq!(
+        instructions[0].json(opts),
+        [
+            r#"{"offset": 0, "address": null, "label": null, "bytes": [114, 129, 65, 16], "mnemonic": "MOVnw", "#,
+            r#""opcode": "MOVnw", "name": "MOVn", "postfixes": ["w"], "widths": ["w"], "#,
+            r#""operand1": {"register": "R1", "indirect": false}, "argument1": null, "#,
+            r#""operand2": {"register": "R0", "indirect": true}, "argument2": {"kind": "index16", "value": 4161, "#,
+            r#""index": {"sign": 1, "constant": 16, "natural": 1, "offset": 24}}, "comment": null}"#,
+        ]
+        .concat()
+    );
+
+    let mut cursor = Cursor::new(Vec::new());
+    disassemble_listing(&mut cursor, opts, &instructions, &labels, &Functions::default(), &Xrefs::default()).unwrap();
+    let lines = String::from_utf8(cursor.into_inner()).unwrap();
+    let lines = lines.lines().collect::<Vec<_>>();
+
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains(r#""label": "loc_0000""#));
+    assert!(lines[1].contains(r#""mnemonic": "JMP8", "opcode": "JMP8""#));
+    assert!(lines[1].ends_with(r#""argument1": {"kind": "immediate16", "value": -3}, "operand2": null, "argument2": null, "comment": "-> loc_0000"}"#));
+
+    // A closed pipe or full disk is an error rather than a panic
+    let xrefs = Xrefs::default();
+    let result =
+        disassemble_listing(&mut &mut [0; 64][..], opts, &instructions, &labels, &Functions::default(), &xrefs);
+    assert!(matches!(result, Err(Error::Output(_))));
+
+    assert_eq!(json_string("\"a\\b\n\u{1}"), r#""\"a\\b\n\u0001""#);
+}
+
+#[test]
+pub fn test_asm_output()
+{
+    let opts = &Options { output: Output::Asm, ..Default::default() };
+    let code = [0x72, 0x81, 0x41, 0x10, OpCode::RET.to(), OpCode::RET.to(), 0x02, 0xFC];
+    let bytes = pe_image(false, 0x0EBC, &code, 0x1000);
+    let image = Image::parse(&bytes).unwrap();
+
+    let mut cursor = Cursor::new(Vec::new());
+    write_image(&mut cursor, opts, &image).unwrap();
+    let source = String::from_utf8(cursor.into_inner()).unwrap();
+
+    assert_eq!(
+        source.split_once("\n\n").unwrap().1,
+        [
+            "include 'ebc.inc'",
+            "include 'format.inc'",
+            "",
+            "format peebc efi",
+            "entry efi_main",
+            "",
+            "section '.text' code readable executable",
+            "",
+            "efi_main:",
+            "    MOVnw R1, @R0(+1,+16)",
+            "    RET",
+            "    RET",
+            "    JMP8 efi_main",
+            "",
+        ]
+        .join("\n")
+    );
+
+    let mut cursor = Cursor::new(Vec::new());
+    write_binary(&mut cursor, opts, &[0x02, 0x00, 0xFF]).unwrap();
+    let source = String::from_utf8(cursor.into_inner()).unwrap();
+
+    assert_eq!(source, "include 'ebc.inc'\n    JMP8 loc_0002\n\nloc_0002:\n    db 0xFF  ;; invalid opcode\n");
+
+    // The relocation directory is kept since fasmg would only generate fixups
+    // for labels, and is decoded back into the same relocations
+    let mut image = Image::parse(&bytes).unwrap();
+    let reloc = [0u8; 16];
+    image.relocations = vec![Relocation { rva: 0x1002, kind: 10 }, Relocation { rva: 0x100C, kind: 10 }, Relocation {
+        rva: 0x2010,
+        kind: 3,
+    }];
+    image.sections.push(Section {
+        name: String::from(".reloc"),
+        origin: Origin { file_offset: 0x400, rva: 0x2000, image_base: 0x10000000 },
+        bytes: &reloc,
+        virtual_size: 0,
+        characteristics: 0x42000040,
+    });
+
+    let mut cursor = Cursor::new(Vec::new());
+    write_image(&mut cursor, opts, &image).unwrap();
+    let source = String::from_utf8(cursor.into_inner()).unwrap();
+    let directory = source.split_once("section '.reloc' data readable discardable\n    data 5\n").unwrap().1;
+    let directory = directory.strip_suffix("    end data\n").unwrap();
+    let number = |text: &str| u64::from_str_radix(text.trim_start_matches("0x"), 16).unwrap();
+    let mut relocations = Vec::new();
+
+    for block in directory.lines().collect::<Vec<_>>().chunks(2)
+    {
+        let page = number(block[0].trim_start_matches("    dd ").split_once(", ").unwrap().0);
+
+        for entry in block[1].trim_start_matches("    dw ").split(", ").map(number).filter(|&entry| entry != 0)
+        {
+            relocations.push(Relocation { rva: page + (entry & 0xFFF), kind: (entry >> 12) as u8 });
+        }
+    }
+
+    assert_eq!(relocations, image.relocations);
+    assert!(directory.contains("    dd 0x00002000, 12\n    dw 0x3010, 0x0000\n"));
+
+    // Running out of room for the output is an error rather than a panic
+    let mut full = [0; 32];
+    assert!(matches!(write_image(&mut &mut full[..], opts, &image), Err(Error::Output(_))));
+    assert!(matches!(write_binary(&mut &mut full[..], opts, &[0x02, 0x00, 0xFF]), Err(Error::Output(_))));
+
+    assert_eq!(data_directives(b"H\0i\0!\0\n\0\0\0"), ["du 'Hi!', 0x0A, 0"]);
+    assert_eq!(data_directives(b"\x01It's\0\x02"), ["db 0x01", "db 'It''s', 0", "db 0x02"]);
+    assert_eq!(data_directives(b"Hi\0"), ["db 0x48, 0x69, 0x00"]);
+}
+
+#[test]
+pub fn test_control_flow_graph()
+{
+    let opts = &Options { theme: None, ..Default::default() };
+    let bytecode = [0xC2, 0x01, OpCode::RET.to(), OpCode::RET.to(), 0x02, 0xFD];
+    let instructions = Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let labels = Labels::new(&instructions);
+    let graph = Graph::new(&instructions, &labels);
+
+    let blocks = graph.iter().map(|block| (block.offset, block.instructions.clone())).collect::<Vec<_>>();
+    assert_eq!(blocks, [(0, 0 .. 1), (2, 1 .. 2), (3, 2 .. 3), (4, 3 .. 4)]);
+    assert_eq!(graph.get(0).unwrap().edges, [Edge::Taken(4), Edge::NotTaken(2)]);
+    assert_eq!(graph.get(2).unwrap().edges, []);
+    assert_eq!(graph.get(4).unwrap().edges, [Edge::Jump(0)]);
+
+    let reachable = graph.reachable(0).iter().map(|block| block.offset).collect::<Vec<_>>();
+    assert_eq!(reachable, [0, 2, 4]);
+
+    assert_eq!(
+        graph.dot(0, &instructions, &labels, opts),
+        [
+            r#"digraph "loc_0000" {"#,
+            r#"    node [shape=box, fontname="monospace"];"#,
+            r#"    "loc_0000" [label=<loc_0000:<br align="left"/>JMP8cs 1<br align="left"/>>];"#,
+            r#"    "loc_0002" [label=<RET<br align="left"/>>];"#,
+            r#"    "loc_0004" [label=<loc_0004:<br align="left"/>JMP8 -3<br align="left"/>>];"#,
+            r#"    "loc_0000" -> "loc_0004" [color="green"];"#,
+            r#"    "loc_0000" -> "loc_0002" [color="red"];"#,
+            r#"    "loc_0004" -> "loc_0000";"#,
+            "}",
+        ]
+        .join("\n")
+    );
+}
+
+#[test]
+pub fn test_function_discovery()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+    let call32 = [0x83, 0x10, 0x01, 0x00, 0x00, 0x00];
+    let callex = [0x83, 0x29, 0x01, 0x00, 0x00, 0x10];
+    let bytecode = [&call32[..], &[OpCode::RET.to()], &callex, &[OpCode::RET.to()]].concat();
+    let mut instructions = Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let mut labels = Labels::new(&instructions);
+    labels.insert(Label::Entry(0));
+    labels.annotate(&mut instructions, opts);
+
+    let functions = Functions::discover(&instructions, &labels, Some(0));
+    let entry = functions.get(0).unwrap();
+    let subroutine = functions.get(7).unwrap();
+
+    assert_eq!(functions.iter().count(), 2);
+    assert_eq!((entry.name.as_str(), entry.end, entry.instructions), ("entry", 7, 2));
+    assert_eq!((&entry.calls[..], &entry.external_calls[..]), (&[7][..], &[][..]));
+    assert_eq!((subroutine.name.as_str(), subroutine.end), ("sub_0007", 14));
+    assert_eq!((&subroutine.calls[..], &subroutine.external_calls[..]), (&[][..], &[7][..]));
+
+    let mut cursor = Cursor::new(Vec::new());
+    disassemble_listing(&mut cursor, opts, &instructions, &labels, &functions, &Xrefs::default()).unwrap();
+    let listing = String::from_utf8(cursor.into_inner()).unwrap();
+
+    assert_eq!(listing.lines().collect::<Vec<_>>(), [
+        "; ---- function entry ----",
+        "entry:",
+        "CALL32 R0 1  ;; -> sub_0007",
+        "RET",
+        "",
+        "; ---- function sub_0007 ----",
+        "sub_0007:",
+        "CALL32EXa @R1(+1, +0)",
+        "RET",
+    ]);
+
+    assert_eq!(functions.emit(opts).lines().collect::<Vec<_>>(), [
+        "; ---- functions ----",
+        "; Function  Start  End    Instructions  Calls  External",
+        "; entry     0000   0007   2             1      0  -> sub_0007",
+        "; sub_0007  0007   000E   2             0      1",
+    ]);
+}
+
+#[test]
+pub fn test_recursive_traversal()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+    let call32 = [0x83, 0x10, 0x03, 0x00, 0x00, 0x00];
+    let bytecode = [&[0x02, 0x01, b'H', b'i'], &call32[..], &[OpCode::RET.to(), 0xFF, 0xFF, OpCode::RET.to()]].concat();
+    let instructions = decode_recursive(&bytecode, &[0]);
+    let lines = instructions.iter().map(|instruction| instruction.emit(opts)).collect::<Vec<_>>();
+
+    assert_eq!(lines, ["JMP8 1", "DB 0x48, 0x69  ;; Hi", "CALL32 R0 3", "RET", "DB 0xFF, 0xFF  ;; ..", "RET"]);
+    assert_eq!(instructions.iter().map(|instruction| instruction.offset).collect::<Vec<_>>(), [0, 2, 4, 10, 11, 13]);
+
+    // Zeros at a jump target end the path instead of being skipped as padding
+    let bytecode = [OpCode::JMP8.to(), 0x00, 0x00, 0x00, 0x02, 0x01];
+    let lines = decode_recursive(&bytecode, &[0]).iter().map(|instruction| instruction.emit(opts)).collect::<Vec<_>>();
+
+    assert_eq!(lines, ["JMP8 0", "DB 0x00, 0x00, 0x02, 0x01  ;; ...."]);
+    assert_eq!(decode_at(&bytecode, 2), Ok(None));
+    assert_eq!(decode_at(&bytecode, 4).unwrap().unwrap().offset, 4);
+
+    // Linear sweep decodes the string as instructions
+    assert_ne!(Decoder::new(&bytecode).nth(1).unwrap().unwrap().name, "DB");
+    assert_eq!(Options::default().traversal, Traversal::Linear);
+
+    assert_eq!(assemble("DB 0x48, 0x69  ;; Hi").unwrap(), [0x48, 0x69]);
+    assert!(assemble("DB").is_err());
+}
+
+#[test]
+pub fn test_efi_service_annotations()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+    let annotated = |bytecode: &[u8]| {
+        let mut instructions = Decoder::new(bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+        let labels = Labels::new(&instructions);
+        annotate_services(&mut instructions, &labels, Some(0), opts);
+        instructions.iter().map(|instruction| instruction.emit(opts)).collect::<Vec<_>>()
+    };
+
+    // The hello world example from the README
+    let hello = [
+        0x72, 0x81, 0x41, 0x10, 0x72, 0x91, 0x85, 0x21, 0x79, 0x02, 0xF4, 0x0F, 0x35, 0x02, 0x35, 0x01, 0x83, 0x29,
+        0x01, 0x00, 0x00, 0x10,
+    ];
+    assert_eq!(annotated(&hello)[5], "CALL32EXa @R1(+1, +0)  ;; ConOut->OutputString");
+
+    // Tables and services are followed through other registers
+    let boot_services = [0x72, 0x82, 0x41, 0x10, 0x72, 0xA3, 0x89, 0x21, 0x72, 0xB4, 0x85, 0x21, 0x03, 0x24];
+    let lines = annotated(&boot_services);
+    assert_eq!(lines[1], "MOVnw R3, @R2(+9, +24)");
+    assert_eq!(lines[3], "CALL32EXa R4  ;; BootServices->AllocatePool");
+
+    // STORESP overwrites its register with FLAGS or IP
+    let stored = [&boot_services[.. 12], &[OpCode::STORESP.to(), 0x14], &boot_services[12 ..]].concat();
+    let lines = annotated(&stored);
+    assert_eq!(lines[3], "STORESP R4, IP");
+    assert_eq!(lines[4], "CALL32EXa R4");
+
+    // Nothing is known about R1 outside of the entry point
+    let mut instructions = Decoder::new(&hello).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let labels = Labels::new(&instructions);
+    annotate_services(&mut instructions, &labels, None, opts);
+    assert_eq!(instructions[5].comment, None);
+
+    assert_eq!(SYSTEM_TABLE.member(5, 24, 8).map(|member| member.0), Some("ConOut"));
+    assert_eq!(SYSTEM_TABLE.member(0, 24 + 9 * 4, 4).map(|member| member.0), Some("BootServices"));
+    assert!(SYSTEM_TABLE.member(0, 20, 8).is_none());
+}
+
+#[test]
+pub fn test_string_references()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+
+    assert_eq!(string_at(b"Hi\0", 1).map(|string| string.encoding), Some(Encoding::Ascii));
+    assert_eq!(string_at(b"H\0i\0\0\0", 1).map(|string| string.text), Some(String::from("Hi")));
+    assert_eq!(string_at(b"H\0i\0\0\0", 1).map(|string| string.length()), Some(6));
+    assert_eq!(string_at(b"Hi\0", 3), None);
+    assert_eq!(string_at(b"Hi", 1), None);
+    assert_eq!(string_at(b"\x01\0", 1), None);
+
+    // The hello world example from the README, with the string in .data
+    let hello = [
+        0x72, 0x81, 0x41, 0x10, 0x72, 0x91, 0x85, 0x21, 0x79, 0x02, 0xF4, 0x0F, 0x35, 0x02, 0x35, 0x01, 0x83, 0x29,
+        0x01, 0x00, 0x00, 0x10,
+    ];
+    let data = "Hello World!\n\0".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+    let section = |name: &str, rva: u64, bytes| Section {
+        name: String::from(name),
+        origin: Origin { file_offset: 0, rva, image_base: 0 },
+        bytes,
+        virtual_size: 0,
+        characteristics: 0,
+    };
+    let sections = [section(".text", 0x1000, &hello[..]), section(".data", 0x2000, &data[..])];
+
+    let mut instructions = Decoder::new(&hello).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let strings = Strings::annotate(&mut instructions, 0x1000, &sections, opts);
+
+    assert_eq!(instructions[2].emit(opts), "MOVRELw R2, 4084  ;; -> .data:00002000 L\"Hello World!\\n\"");
+    assert_eq!(strings.iter().count(), 1);
+    assert_eq!(strings.emit(opts), "; ---- strings ----\n; 0008  -> .data:00002000  L\"Hello World!\\n\"");
+
+    // Targets outside of every section are left alone
+    let mut instructions = Decoder::new(&hello).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let strings = Strings::annotate(&mut instructions, 0x1000, &sections[.. 1], opts);
+
+    assert!(strings.is_empty());
+    assert_eq!(instructions[2].comment, None);
+}
+
+#[test]
+pub fn test_xrefs()
+{
+    let opts = &Options {
+        theme: None,
+        bytecode: false,
+        pad_output: false,
+        pe: false,
+        address: None,
+        xrefs: true,
+        ..Default::default()
+    };
+    let bytecode = [
+        0xC2, 0x03, // JMP8cs +3
+        0x83, 0x10, 0x04, 0x00, 0x00, 0x00, // CALL32 +4
+        0x79, 0x01, 0x00, 0x00, // MOVRELw R1, 0
+        0xC1, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // JMP64a 0x08
+        0xC3, 0x00, 0x0C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // CALL64a 0x0C
+        0x04, // RET
+    ];
+    let instructions = Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let xrefs = Xrefs::new(&instructions, &Origin::default());
+
+    let from = |target| xrefs.to(target).iter().map(|xref| (xref.from, xref.reference)).collect::<Vec<_>>();
+    assert_eq!(from(0x08), vec![(0x00, Reference::Jump), (0x0C, Reference::Jump)]);
+    assert_eq!(from(0x0C), vec![(0x02, Reference::Call), (0x08, Reference::Load), (0x16, Reference::Call)]);
+    assert_eq!(xrefs.iter().count(), 2);
+
+    let labels = Labels::new(&instructions);
+    let mut cursor = Cursor::new(Vec::new());
+    disassemble_listing(&mut cursor, opts, &instructions, &labels, &Functions::default(), &xrefs).unwrap();
+    let listing = String::from_utf8(cursor.into_inner()).unwrap();
+
+    assert!(listing.contains("; XREF from 0x0000 (JMP8cs)\n; XREF from 0x000C (JMP64)\nloc_0008:\n"), "{}", listing);
+    assert!(listing.contains("; XREF from 0x0002 (CALL32)\n; XREF from 0x0008 (MOVRELw)\n"), "{}", listing);
+
+    // Absolute addresses are virtual addresses within a PE image
+    let origin = Origin { file_offset: 0x400, rva: 0x1000, image_base: 0x10000000 };
+    let mut bytecode = bytecode;
+    bytecode[14 .. 22].copy_from_slice(&0x10001008u64.to_le_bytes());
+    let instructions = Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let xrefs = Xrefs::new(&instructions, &origin);
+
+    assert_eq!(xrefs.to(0x08).len(), 2);
+    assert!(xrefs.to(0x0C).iter().all(|xref| xref.from != 0x16));
+
+    // Nothing is printed unless asked for
+    let mut cursor = Cursor::new(Vec::new());
+    disassemble_listing(
+        &mut cursor,
+        &Options { xrefs: false, ..*opts },
+        &instructions,
+        &labels,
+        &Functions::default(),
+        &xrefs,
+    )
+    .unwrap();
+    assert!(!String::from_utf8(cursor.into_inner()).unwrap().contains("XREF"));
+}
+
+#[test]
+pub fn test_relocations()
+{
+    let opts =
+        &Options { theme: None, bytecode: false, pad_output: false, pe: false, address: None, ..Default::default() };
+    let mut bytecode = vec![0xF7, 0x31]; // MOVIqq R1
+    bytecode.extend(0x10002000u64.to_le_bytes());
+    bytecode.extend([0xC1, 0x00]); // JMP64
+    bytecode.extend(0x10001000u64.to_le_bytes());
+    bytecode.push(0x04); // RET
+
+    let relocations = [Relocation { rva: 0x1002, kind: 10 }, Relocation { rva: 0x100C, kind: 10 }];
+    let decoded = || Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+
+    let mut instructions = decoded();
+    annotate_relocations(&mut instructions, 0x1000, &relocations, 0);
+    let lines = instructions.iter().map(|instruction| instruction.emit(opts)).collect::<Vec<_>>();
+
+    assert_eq!(lines[0], "MOVIqq R1, 268443648  ;; reloc DIR64");
+    assert_eq!(lines[1], "JMP64 268439552  ;; Absolute Address, reloc DIR64");
+    assert_eq!(lines[2], "RET");
+
+    // Loaded at 0x7E000000 rather than 0x10000000
+    let mut instructions = decoded();
+    annotate_relocations(&mut instructions, 0x1000, &relocations, 0x6E000000);
+
+    assert_eq!(instructions[0].emit(opts), "MOVIqq R1, 2113937408  ;; reloc DIR64");
+    assert_eq!(instructions[1].offset, 0x0A);
+    assert_eq!(instructions[1].absolute_reference(), Some((Reference::Jump, 0x7E001000)));
+
+    // Relocations that only partly cover an instruction are not its own
+    let mut instructions = decoded();
+    annotate_relocations(&mut instructions, 0x1000, &[Relocation { rva: 0x1010, kind: 10 }], 0);
+    assert!(instructions.iter().all(|instruction| instruction.comment.as_deref() != Some("reloc DIR64")));
+
+    assert_eq!(Relocation { rva: 0, kind: 3 }.name(), "HIGHLOW");
+    assert_eq!(Relocation { rva: 0, kind: 3 }.size(), 4);
+}
+
+#[test]
+pub fn test_section_slice()
+{
+    let bytes = (0 .. 0x20).collect::<Vec<u8>>();
+    let section = Section {
+        name: String::from(".text"),
+        origin: Origin { file_offset: 0x400, rva: 0x1000, image_base: 0x10000000 },
+        bytes: &bytes,
+        virtual_size: 0x18,
+        characteristics: 0x60000020,
+    };
+
+    assert_eq!(section.offsets(&(0x1004 .. 0x1008)), Some(4 .. 8));
+    assert_eq!(section.offsets(&(0x1018 .. 0x1020)), None);
+
+    let slice = section.slice(&(0x1004 .. 0x1008)).unwrap();
+    assert_eq!(slice.bytes, &[4, 5, 6, 7]);
+    assert_eq!(slice.origin, Origin { file_offset: 0x404, rva: 0x1004, image_base: 0x10000000 });
+    assert_eq!(slice.contents(), slice.bytes);
+    assert!(slice.is_executable());
+
+    // Ranges are clipped to the contents, which exclude the padding
+    assert_eq!(section.slice(&(0x0800 .. 0x1002)).unwrap().bytes, &[0, 1]);
+    assert_eq!(section.slice(&(0x1010 .. u64::MAX)).unwrap().bytes.len(), 8);
+    assert!(section.slice(&(0x1018 .. 0x1020)).is_none());
+    assert!(section.slice(&(0 .. 0x1000)).is_none());
+}
+
+#[test]
+pub fn test_browser()
+{
+    let opts = &Options { theme: None, pe: false, ..Default::default() };
+    let bytecode = [
+        0x02, 0x02, // JMP8 +2
+        0x35, 0x01, // PUSHn R1
+        0x35, 0x02, // PUSHn R2
+        0x83, 0x10, 0xF4, 0xFF, 0xFF, 0xFF, // CALL32 -12
+        0x79, 0x01, 0x00, 0x10, // MOVRELw R1, 4096
+        0x04, // RET
+    ];
+    let instructions = Decoder::new(&bytecode).map(|instruction| instruction.unwrap()).collect::<Vec<_>>();
+    let mut labels = Labels::new(&instructions);
+    labels.insert(Label::Entry(0));
+    let functions = Functions::discover(&instructions, &labels, Some(0));
+    let section = Section {
+        name: String::from(".text"),
+        origin: Origin::default(),
+        bytes: &bytecode,
+        virtual_size: 0,
+        characteristics: 0,
+    };
+
+    let mut browser = Browser::new(vec![Listing { section, instructions, labels, functions }], opts);
+    browser.resize(80, 16);
+    let offset = |browser: &Browser| browser.selected().map(|instruction| instruction.offset);
+
+    let screen = browser.render();
+    assert_eq!(screen.len(), 16);
+    assert!(screen.iter().all(|line| line.replace("\x1B[7m", "").replace("\x1B[0m", "").chars().count() == 80));
+    assert!(screen[0].starts_with(".text "));
+    assert!(screen.iter().any(|line| line.contains("\x1B[7m02\x1B[0m 35 01 35 02 83")));
+    assert_eq!(offset(&browser), Some(0));
+
+    // Follow the jump, then the call back to the entry point
+    browser.handle(Key::Follow);
+    assert_eq!(offset(&browser), Some(6));
+    browser.handle(Key::Follow);
+    assert_eq!(offset(&browser), Some(0));
+
+    browser.handle(Key::Back);
+    assert_eq!(offset(&browser), Some(6));
+    browser.handle(Key::Back);
+    assert_eq!(offset(&browser), Some(0));
+    browser.handle(Key::Forward);
+    assert_eq!(offset(&browser), Some(6));
+
+    // Data outside of the code cannot be followed
+    browser.handle(Key::Down);
+    assert_eq!(offset(&browser), Some(12));
+    browser.handle(Key::Follow);
+    assert_eq!(offset(&browser), Some(12));
+    assert!(browser.render().last().unwrap().contains("No code at RVA 00001010"));
+
+    // The sidebar lists the section and then its functions
+    browser.handle(Key::Switch);
+    browser.handle(Key::End);
+    browser.handle(Key::Follow);
+    assert_eq!(offset(&browser), Some(0));
+
+    assert!(!browser.handle(Key::Quit));
+
+    browser.resize(10, 3);
+    assert_eq!(browser.render(), ["Terminal i", "          ", "          "]);
+}
+
+#[test]
+pub fn test_debugger()
+{
+    let opts = &Options { theme: None, ..Default::default() };
+    let program =
+        assemble("MOVIqw R1, 16\nCALL32 R0 7\nMOVIqw R2, 2\nBREAK 3\nRET\nMOVqw R3, @R0(+2, +0)\nADD64 R1, R1\nRET")
+            .unwrap();
+
+    // Stepping over a call runs it to completion
+    let mut debugger = Debugger::new(Vm::new(&program).arguments(&[0x1111, 0x2222]), opts);
+    assert_eq!(debugger.current(), "=> 00000000  MOVIqw R1, 16");
+    assert_eq!(debugger.command("s"), Some(String::from("=> 00000004  CALL32 R0 7")));
+    assert_eq!(debugger.command("n"), Some(String::from("=> 0000000A  MOVIqw R2, 2")));
+    assert_eq!(debugger.vm.registers[1], 0x20);
+    assert!(debugger.command("r").unwrap().contains("R1    0x0000000000000020"));
+    assert_eq!(debugger.command("s"), Some(String::from("=> 0000000E  BREAK 3")));
+    assert_eq!(debugger.command(""), Some(String::from("BREAK 3\n=> 00000010  RET")));
+    assert_eq!(
+        debugger.command("x @R0(+2, +0) 8"),
+        Some(format!("00010000  {:<48} ........", "11 11 00 00 00 00 00 00"))
+    );
+    assert_eq!(debugger.command("c"), Some(String::from("Program returned 0x0")));
+    assert_eq!(debugger.command("s"), Some(String::from("The program has exited")));
+
+    // Stepping into a call stops at breakpoints inside it
+    let mut debugger = Debugger::new(Vm::new(&program), opts);
+    assert_eq!(debugger.command("b 15"), Some(String::from("Breakpoint at 00000015")));
+    assert_eq!(debugger.command("b"), Some(String::from(" * 00000015  ADD64 R1, R1")));
+    assert_eq!(debugger.command("c"), Some(String::from("Breakpoint at 00000015\n=> 00000015  ADD64 R1, R1")));
+    assert_eq!(debugger.command("l 2"), Some(String::from("=> 00000015  ADD64 R1, R1\n   00000017  RET")));
+    assert_eq!(debugger.command("stack 1"), Some(String::from("00010000  @R0(+0, +0)  0x000000000000000A")));
+    assert_eq!(debugger.command("d 15"), Some(String::from("Deleted breakpoint at 00000015")));
+    assert_eq!(debugger.command("d 15"), Some(String::from("No breakpoint at 00000015")));
+    assert_eq!(debugger.command("x 0 4"), Some(format!("00000000  {:<48} w1..", "77 31 10 00")));
+    assert_eq!(debugger.command("frobnicate"), Some(String::from("Unknown command: frobnicate (try help)")));
+    assert_eq!(debugger.command("b zz"), Some(String::from("Invalid address: zz")));
+    assert_eq!(debugger.command("q"), None);
+
+    // Zeros at IP are shown as padding instead of decoding what follows them
+    let mut debugger = Debugger::new(Vm::new(&[0x00, 0x00, OpCode::RET.to(), 0x00]), opts);
+    assert_eq!(debugger.command("l 2"), Some(String::from("=> 00000000  DB 0x00  ;; padding\n   00000001  BREAK 4")));
+    debugger.vm.ip = 3;
+    assert_eq!(debugger.current(), "=> 00000003  DB 0x00  ;; padding");
+
+    assert_eq!(parse_operand_offset("@R1(+5, +24)", 8).unwrap(), (Operand::new_general_purpose(1, true).unwrap(), 64));
+    assert_eq!(parse_operand_offset("@R1(-1, -8)", 4).unwrap().1, -12);
+    assert!(parse_operand_offset("(+1, +0)", 8).is_err());
+}
+
+#[test]
+pub fn test_services()
+{
+    // The README sample, with the string placed right after the code
+    let mut program = assemble(
+        "MOVnw R1, @R0(+1, +16)
+         MOVnw R1, @R1(+5, +24)
+         MOVRELw R2, 17
+         PUSHn R2
+         PUSHn R1
+         CALL32EXa @R1(+1, +0)
+         MOVqw R0, R0(+2, +0)
+         JMP8 -14
+         RET",
+    )
+    .unwrap();
+    assert_eq!(program.len(), 0x1D);
+    program.extend("Hello World!\n\0".encode_utf16().flat_map(u16::to_le_bytes));
+
+    for pointer_size in [8, 4]
+    {
+        let mut vm = Vm::new(&program).pointer_size(pointer_size);
+        let mut services = Services::new(&mut vm).unwrap();
+        let mut vm = vm.arguments(&[services.image_handle, services.system_table]);
+
+        while services.host.calls.len() < 2
+        {
+            assert_eq!(services.step(&mut vm), Ok(Step::Continue));
+        }
+
+        assert_eq!(services.host.console, "Hello World!\nHello World!\n");
+        assert_eq!(services.host.calls, ["OutputString", "OutputString"]);
+        assert_eq!(vm.registers[7], EFI_SUCCESS);
+        assert_eq!(vm.read(services.system_table, 8), Ok(u64::from_le_bytes(*b"IBI SYST")));
+    }
+
+    // Arguments are pushed in reverse so the first one is at R0
+    let call = |services: &mut Services, vm: &mut Vm, name: &str, arguments: &[u64]| {
+        for argument in arguments.iter().rev()
+        {
+            vm.push(8, *argument).unwrap();
+        }
+
+        let address = services.address(name).unwrap();
+        services.call(vm, address).unwrap();
+
+        vm.registers[0] += arguments.len() as u64 * 8;
+        vm.registers[7]
+    };
+
+    let mut vm = Vm::new(&[]);
+    let mut services = Services::new(&mut vm).unwrap().on("Stall", |vm, _| Ok(vm.argument(0)? * 2));
+    let out = vm.allocate(8).unwrap();
+    let guid = vm.allocate(16).unwrap();
+    vm.load(guid, &[0xAB; 16]).unwrap();
+
+    assert_eq!(call(&mut services, &mut vm, "AllocatePool", &[4, 32, out]), EFI_SUCCESS);
+    let buffer = vm.read(out, 8).unwrap();
+    assert_eq!(services.host.pool.get(&buffer), Some(&32));
+    assert_eq!(call(&mut services, &mut vm, "SetMem", &[buffer, 4, 0x5A]), EFI_SUCCESS);
+    assert_eq!(vm.read(buffer, 8), Ok(0x5A5A5A5A));
+    assert_eq!(call(&mut services, &mut vm, "CopyMem", &[buffer + 4, buffer, 2]), EFI_SUCCESS);
+    assert_eq!(vm.read(buffer, 8), Ok(0x5A5A5A5A5A5A));
+    assert_eq!(call(&mut services, &mut vm, "FreePool", &[buffer]), EFI_SUCCESS);
+    assert_eq!(call(&mut services, &mut vm, "FreePool", &[buffer]), error_status(EFI_INVALID_PARAMETER, 8));
+
+    // Sizes from the program are checked before any memory is touched
+    let length = vm.memory.len();
+    assert_eq!(
+        call(&mut services, &mut vm, "AllocatePool", &[4, u64::MAX, out]),
+        error_status(EFI_OUT_OF_RESOURCES, 8)
+    );
+    assert_eq!(call(&mut services, &mut vm, "AllocatePool", &[4, 1 << 40, out]), error_status(EFI_OUT_OF_RESOURCES, 8));
+    assert_eq!(
+        call(&mut services, &mut vm, "SetMem", &[buffer, u64::MAX, 0x5A]),
+        error_status(EFI_INVALID_PARAMETER, 8)
+    );
+    assert_eq!(call(&mut services, &mut vm, "SetMem", &[u64::MAX, 2, 0x5A]), error_status(EFI_INVALID_PARAMETER, 8));
+    assert_eq!(
+        call(&mut services, &mut vm, "CopyMem", &[buffer, buffer, u64::MAX]),
+        error_status(EFI_INVALID_PARAMETER, 8)
+    );
+    assert_eq!(call(&mut services, &mut vm, "CopyMem", &[u64::MAX, buffer, 2]), error_status(EFI_INVALID_PARAMETER, 8));
+    assert_eq!(vm.memory.len(), length);
+    assert_eq!(vm.allocate(u64::MAX), Err(Error::OutOfMemory(u64::MAX)));
+
+    assert_eq!(call(&mut services, &mut vm, "LocateProtocol", &[guid, 0, out]), error_status(EFI_NOT_FOUND, 8));
+    assert_eq!(call(&mut services, &mut vm, "InstallProtocolInterface", &[0, guid, 0, 0x1234]), EFI_SUCCESS);
+    assert_eq!(call(&mut services, &mut vm, "LocateProtocol", &[guid, 0, out]), EFI_SUCCESS);
+    assert_eq!(vm.read(out, 8), Ok(0x1234));
+    assert_eq!(call(&mut services, &mut vm, "HandleProtocol", &[0, guid, out + 1]), EFI_SUCCESS);
+
+    assert_eq!(call(&mut services, &mut vm, "Stall", &[21]), 42);
+    assert_eq!(call(&mut services, &mut vm, "GetTime", &[0, 0]), error_status(EFI_UNSUPPORTED, 8));
+    assert_eq!(error_status(EFI_UNSUPPORTED, 4), 0x80000003);
+    assert_eq!(services.call(&mut vm, 0), Err(Error::InvalidAddress(0)));
+}
+
+#[test]
+pub fn test_trace()
+{
+    let opts = &Options { theme: None, bytecode: false, address: None, ..Default::default() };
+    let program = assemble(
+        "MOVIqw R1, 3\nCMPI64weq R1, 0\nJMP8cs 4\nCALL32 R0 7\nRET\nRET\nMOVIqw R2, 1\nRET\nMOVIqw R7, 7\nRET",
+    )
+    .unwrap();
+
+    let mut vm = Vm::new(&program);
+    let mut lines = Vec::new();
+    let mut coverage = Coverage::default();
+    let stop = trace(&mut vm, None, 100, |instruction| {
+        coverage.record(instruction.offset as u64);
+        lines.push(format!("{:02X} {}", instruction.offset, instruction.emit(opts)));
+        Ok(())
+    });
+
+    assert_eq!(stop, Ok(Step::Halt));
+    assert_eq!(lines, [
+        "00 MOVIqw R1, 3  ;; R1 = 0x3",
+        "04 CMPI64weq R1, 0",
+        "08 JMP8cs 4",
+        "0A CALL32 R0 7  ;; R0 = 0x10000",
+        "17 MOVIqw R7, 7  ;; R7 = 0x7",
+        "1B RET  ;; R0 = 0x10010",
+        "10 RET",
+    ]);
+
+    // Running out of steps, and stopping at a breakpoint
+    let mut vm = Vm::new(&program);
+    assert_eq!(trace(&mut vm, None, 2, |_| Ok(())), Ok(Step::Continue));
+    assert_eq!(vm.ip, 0x08);
+    let mut vm = Vm::new(&assemble("BREAK 3\nRET").unwrap());
+    assert_eq!(trace(&mut vm, None, 2, |_| Ok(())), Ok(Step::Breakpoint));
+
+    let mut instructions = Decoder::new(&program).collect::<Result<Vec<_>, _>>().unwrap();
+    let labels = Labels::new(&instructions);
+    let functions = Functions::discover(&instructions, &labels, None);
+
+    coverage.annotate(&mut instructions, 0);
+    assert_eq!(instructions[0].emit(opts), "MOVIqw R1, 3  ;; executed 1x");
+    assert_eq!(instructions[5].emit(opts), "RET");
+
+    let report = coverage.report(&instructions, &labels, &functions, 0);
+    assert_eq!(report.functions, [
+        FunctionCoverage { name: String::from("sub_0000"), instructions: 7, executed: 5 },
+        FunctionCoverage { name: String::from("sub_0017"), instructions: 2, executed: 2 },
+    ]);
+    assert_eq!(report.functions[0].percentage(), 5.0 * 100.0 / 7.0);
+    assert_eq!(
+        report.emit(opts),
+        "; ---- coverage ----\n\
+         ; Function  Instructions  Executed  Coverage\n\
+         ; sub_0000  7             5         71.4%\n\
+         ; sub_0017  2             2         100.0%\n\
+         ; Total     9             7         77.8%"
+    );
+
+    // Relocated images run at their RVAs
+    let mut coverage = Coverage::default();
+    coverage.record(0x1004);
+    let mut instructions = Decoder::new(&program).collect::<Result<Vec<_>, _>>().unwrap();
+    coverage.annotate(&mut instructions, 0x1000);
+    assert_eq!(instructions[1].comment.as_deref(), Some("executed 1x"));
+    assert_eq!(coverage.hits(0x1000), 0);
+}
+
+#[test]
+pub fn test_diff()
+{
+    let opts = &Options { theme: None, ..Default::default() };
+    let decode = |source: &str| {
+        let instructions = Decoder::new(&assemble(source).unwrap()).collect::<Result<Vec<_>, _>>().unwrap();
+        let labels = Labels::new(&instructions);
+        let functions = Functions::discover(&instructions, &labels, None);
+
+        (instructions, labels, functions)
+    };
+
+    // The new build moves the subroutine and changes it
+    let (old, old_labels, old_functions) = decode("MOVIqw R1, 16\nCALL32 R0 1\nRET\nADD64 R1, R1\nRET");
+    let (new, new_labels, new_functions) = decode("MOVIqw R1, 16\nCALL32 R0 5\nRET\nMOVIqw R2, 2\nMUL64 R1, R1\nRET");
+
+    let old_units = units(&old, &old_labels, &old_functions);
+    let new_units = units(&new, &new_labels, &new_functions);
+    assert_eq!(old_units.iter().map(|unit| unit.name.as_str()).collect::<Vec<_>>(), ["sub_0000", "sub_000B"]);
+    assert_eq!(new_units.iter().map(|unit| unit.name.as_str()).collect::<Vec<_>>(), [
+        "sub_0000", "loc_000B", "sub_000F"
+    ]);
+
+    let diff = Diff::new(old_units, new_units);
+    assert_eq!(diff.units.len(), 3);
+    assert_eq!(diff.units[0].changes, [
+        Change::Same(&old[0], &new[0]),
+        Change::Moved(&old[1], &new[1]),
+        Change::Same(&old[2], &new[2]),
+    ]);
+    assert!(diff.units[1].old.is_none());
+    assert_eq!(diff.units[1].changes, [Change::Added(&new[3])]);
+    assert_eq!(diff.units[2].old.as_ref().unwrap().name, "sub_000B");
+    assert_eq!(diff.units[2].changes, [
+        Change::Removed(&old[3]),
+        Change::Added(&new[4]),
+        Change::Same(&old[4], &new[5]),
+    ]);
+    assert_eq!(diff.counts(), (1, 1, 2));
+
+    assert_eq!(
+        diff.emit(opts),
+        "@@ -0000 +0000 @@ sub_0000\n\
+         \x20 0000  MOVIqw R1, 16\n\
+         ~ 0004  CALL32 R0 5  ; moved, was CALL32 R0 1\n\
+         \x20 000A  RET\n\
+         @@ ----- +000B @@ loc_000B\n\
+         + 000B  MOVIqw R2, 2\n\
+         @@ -000B +000F @@ sub_000F\n\
+         - 000B  ADD64 R1, R1\n\
+         + 000F  MUL64 R1, R1\n\
+         \x20 0011  RET\n\
+         ; 3 of 3 functions or labels changed, 1 instructions removed, 2 added, 1 moved"
+    );
+
+    // Identical code has no hunks, whatever the name of a unit
+    let diff = Diff::new(units(&old, &old_labels, &old_functions), units(&old, &old_labels, &old_functions));
+    assert_eq!(diff.emit(opts), "; 0 of 2 functions or labels changed, 0 instructions removed, 0 added, 0 moved");
+
+    // Code without labels is one large unit, changed at both ends and in the middle
+    let body = "ADD64 R1, R1\nMUL64 R2, R2\nSUB64 R3, R3\n".repeat(200);
+    let (old, ..) = decode(&format!("MOVIqw R1, 16\n{}RET", body));
+    let (new, ..) =
+        decode(&format!("MOVIqw R1, 32\n{}\nXOR64 R4, R4\n{}", &body[.. body.len() / 2], &body[body.len() / 2 ..]));
+    let unit = |instructions: Vec<_>| Unit { name: String::from("loc_0000"), offset: 0, instructions };
+
+    let diff = Diff::new(vec![unit(old.iter().collect())], vec![unit(new.iter().collect())]);
+    assert_eq!(diff.units.len(), 1);
+    assert_eq!(diff.counts(), (0, 2, 2));
+    assert_eq!(diff.units[0].changes.iter().filter(|change| matches!(change, Change::Same(..))).count(), 600);
+}
@@ -6,6 +6,8 @@ pub const SPORE: Theme = Theme {
     opcode: color(217, 207, 199),
     error: color(207, 66, 31),
     bytecode: color(84, 71, 52),
+    address: color(158, 134, 104),
+    label: color(227, 202, 113),
     indirect: color(227, 202, 113),
     operand: color(135, 75, 41),
     index: color(217, 182, 130),
@@ -21,6 +23,8 @@ pub const INDUSTRIAL_COMPUTER: Theme = Theme {
     opcode: color(161, 156, 148),
     error: color(255, 0, 0),
     bytecode: color(77, 75, 73),
+    address: color(120, 117, 112),
+    label: color(82, 214, 0),
     indirect: color(255, 153, 0),
     operand: color(161, 156, 148),
     index: color(255, 153, 0),
@@ -36,6 +40,8 @@ pub const MATTERHORN_ZERMATT_VILLAGE: Theme = Theme {
     opcode: color(173, 185, 201),
     error: color(255, 153, 0),
     bytecode: color(211, 195, 212),
+    address: color(150, 160, 171),
+    label: color(237, 156, 76),
     indirect: color(107, 129, 138),
     operand: color(115, 131, 153),
     index: color(140, 135, 128),
@@ -47,11 +53,14 @@ pub const MATTERHORN_ZERMATT_VILLAGE: Theme = Theme {
     x64: color(237, 156, 76),
 };
 
+#[derive(Clone, Copy)]
 pub struct Theme
 {
     pub opcode: Color,
     pub error: Color,
     pub bytecode: Color,
+    pub address: Color,
+    pub label: Color,
     pub indirect: Color,
     pub operand: Color,
     pub index: Color,
@@ -116,6 +125,30 @@ pub fn color_bytecode(string: String, options: &Options) -> String
     }
 }
 
+pub fn color_address(string: String, options: &Options) -> String
+{
+    if let Some(color_theme) = &options.theme
+    {
+        colored_string(string, color_theme.address)
+    }
+    else
+    {
+        string
+    }
+}
+
+pub fn color_label(string: String, options: &Options) -> String
+{
+    if let Some(color_theme) = &options.theme
+    {
+        colored_string(string, color_theme.label)
+    }
+    else
+    {
+        string
+    }
+}
+
 pub fn color_indirect(string: String, options: &Options) -> String
 {
     if let Some(color_theme) = &options.theme
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+use crate::function::Functions;
+use crate::graph::Graph;
+use crate::instruction::{Decoder, Instruction};
+use crate::label::Labels;
+use crate::options::Options;
+use crate::services::Services;
+use crate::theme::*;
+use crate::vm::{Step, Vm};
+
+const REGISTERS: [&str; 8] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7"];
+
+/// Runs up to `steps` instructions, passing each one to `record` after it was
+/// executed. Its offset is the address it was executed at and its comment
+/// lists the registers it changed and the service it called, if any.
+///
+/// Native calls are skipped without services. Stops when the program returns
+/// or reaches a `BREAK 3`, and returns `Step::Continue` when it ran out of
+/// steps instead. An error from `record` stops it as well.
+pub fn trace(
+    vm: &mut Vm,
+    mut services: Option<&mut Services>,
+    steps: usize,
+    mut record: impl FnMut(Instruction) -> Result<(), Error>,
+) -> Result<Step, Error>
+{
+    for _ in 0 .. steps
+    {
+        let address = vm.ip;
+        let decoded = Decoder::new(&vm.memory)
+            .seek(address as usize)
+            .next()
+            .and_then(|result| result.ok())
+            .filter(|instruction| instruction.offset as u64 == address);
+
+        let registers = vm.registers;
+        let flags = vm.flags;
+        let mut notes = Vec::new();
+
+        let step = match (vm.step()?, services.as_deref_mut())
+        {
+            (Step::Native(target), Some(services)) =>
+            {
+                notes.push(format!("-> {}", services.call(vm, target)?));
+                Step::Continue
+            }
+
+            (Step::Native(_), None) =>
+            {
+                notes.push(String::from("native call skipped"));
+                Step::Continue
+            }
+
+            (step, _) => step,
+        };
+
+        // Anything the decoder cannot handle is an error from the VM first
+        let mut instruction = decoded.ok_or(Error::InvalidAddress(address))?;
+
+        for (i, (before, after)) in registers.iter().zip(vm.registers).enumerate()
+        {
+            if *before != after
+            {
+                notes.push(format!("{} = 0x{:X}", REGISTERS[i], after));
+            }
+        }
+
+        if flags != vm.flags
+        {
+            notes.push(format!("FLAGS = 0x{:X}", vm.flags));
+        }
+
+        if !notes.is_empty()
+        {
+            notes.splice(0 .. 0, instruction.comment.take());
+            instruction.comment = Some(notes.join(", "));
+        }
+
+        record(instruction)?;
+
+        if step != Step::Continue
+        {
+            return Ok(step);
+        }
+    }
+
+    Ok(Step::Continue)
+}
+
+/// How many times each address was executed.
+#[derive(Debug, Default)]
+pub struct Coverage
+{
+    hits: BTreeMap<u64, usize>,
+}
+
+impl Coverage
+{
+    pub fn record(&mut self, address: u64)
+    {
+        *self.hits.entry(address).or_default() += 1;
+    }
+
+    pub fn hits(&self, address: u64) -> usize
+    {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    /// Comments each instruction that was executed with how many times, where
+    /// `base` is the address the instructions were executed at.
+    pub fn annotate(&self, instructions: &mut [Instruction], base: u64)
+    {
+        for instruction in instructions.iter_mut()
+        {
+            let hits = self.hits(base + instruction.offset as u64);
+
+            if hits == 0
+            {
+                continue;
+            }
+
+            let comment = match instruction.comment.take()
+            {
+                Some(comment) => format!("{}, executed {}x", comment, hits),
+                None => format!("executed {}x", hits),
+            };
+
+            instruction.comment = Some(comment);
+        }
+    }
+
+    /// How many of the instructions reachable in each function were executed.
+    pub fn report(
+        &self,
+        instructions: &[Instruction],
+        labels: &Labels,
+        functions: &Functions,
+        base: u64,
+    ) -> CoverageReport
+    {
+        let graph = Graph::new(instructions, labels);
+        let functions = functions
+            .iter()
+            .map(|function| {
+                let reachable = graph
+                    .reachable(function.offset)
+                    .iter()
+                    .flat_map(|block| &instructions[block.instructions.clone()])
+                    .map(|instruction| self.hits(base + instruction.offset as u64))
+                    .collect::<Vec<_>>();
+
+                FunctionCoverage {
+                    name: function.name.clone(),
+                    instructions: reachable.len(),
+                    executed: reachable.iter().filter(|hits| **hits > 0).count(),
+                }
+            })
+            .collect();
+
+        CoverageReport { functions }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCoverage
+{
+    pub name: String,
+    pub instructions: usize, // Number of reachable instructions
+    pub executed: usize,     // How many of them were executed at least once
+}
+
+impl FunctionCoverage
+{
+    pub fn percentage(&self) -> f64
+    {
+        match self.instructions
+        {
+            0 => 0.0,
+            instructions => self.executed as f64 * 100.0 / instructions as f64,
+        }
+    }
+}
+
+/// The coverage table printed after the function table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageReport
+{
+    pub functions: Vec<FunctionCoverage>,
+}
+
+impl Emit for CoverageReport
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        let total = FunctionCoverage {
+            name: String::from("Total"),
+            instructions: self.functions.iter().map(|function| function.instructions).sum(),
+            executed: self.functions.iter().map(|function| function.executed).sum(),
+        };
+
+        let width = self.functions.iter().map(|function| function.name.len()).max().unwrap_or(0).max("Function".len());
+        let mut lines = vec![
+            String::from("; ---- coverage ----"),
+            format!("; {:<width$}  Instructions  Executed  Coverage", "Function"),
+        ];
+
+        for function in self.functions.iter().chain(std::iter::once(&total))
+        {
+            lines.push(format!(
+                "; {:<width$}  {:<12}  {:<8}  {:.1}%",
+                function.name,
+                function.instructions,
+                function.executed,
+                function.percentage()
+            ));
+        }
+
+        color_comment(lines.join("\n"), options)
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use crate::graph::{flow, Flow};
+use crate::instruction::{decode_at, Instruction, Reference};
+
+/// Bytes per data block, which is as many as fit in a `DB` line.
+const DATA_BLOCK_SIZE: usize = 16;
+
+/// Decodes the instructions reachable from `entry_points` by following
+/// jumps, branches and relative calls. Everything else is emitted as data
+/// blocks, so jump tables, strings and padding are never decoded.
+///
+/// A path ends at the first byte that cannot be decoded, at zeros (which are
+/// padding or `BREAK 0`) or at an instruction that would overlap one that has
+/// already been decoded.
+pub fn decode_recursive(bytes: &[u8], entry_points: &[usize]) -> Vec<Instruction>
+{
+    let mut code = BTreeMap::<usize, Instruction>::new();
+    let mut pending = entry_points.to_vec();
+
+    while let Some(position) = pending.pop()
+    {
+        if position >= bytes.len() || code.contains_key(&position)
+        {
+            continue;
+        }
+
+        let instruction = match decode_at(bytes, position)
+        {
+            Ok(Some(instruction)) => instruction,
+            _ => continue,
+        };
+
+        let overlaps_previous = code
+            .range(.. instruction.offset)
+            .next_back()
+            .is_some_and(|(_, previous)| previous.end() > instruction.offset);
+        let overlaps_next =
+            code.range(instruction.offset ..).next().is_some_and(|(offset, _)| *offset < instruction.end());
+
+        if overlaps_previous || overlaps_next
+        {
+            continue;
+        }
+
+        let target = |target: i64| usize::try_from(target).ok();
+
+        match flow(&instruction)
+        {
+            Flow::Next => pending.push(instruction.end()),
+            Flow::Jump(destination) => pending.extend(destination.and_then(target)),
+            Flow::Branch(destination) =>
+            {
+                pending.push(instruction.end());
+                pending.extend(destination.and_then(target));
+            }
+            Flow::Return => (),
+        }
+
+        if let Some((Reference::Call, destination)) = instruction.reference()
+        {
+            pending.extend(target(destination));
+        }
+
+        code.insert(instruction.offset, instruction);
+    }
+
+    let mut instructions = Vec::with_capacity(code.len());
+    let mut position = 0;
+
+    for (offset, instruction) in code
+    {
+        data_blocks(&mut instructions, bytes, position, offset);
+        position = instruction.end();
+        instructions.push(instruction);
+    }
+
+    data_blocks(&mut instructions, bytes, position, bytes.len());
+    instructions
+}
+
+fn data_blocks(instructions: &mut Vec<Instruction>, bytes: &[u8], start: usize, end: usize)
+{
+    for offset in (start .. end).step_by(DATA_BLOCK_SIZE)
+    {
+        let block_end = (offset + DATA_BLOCK_SIZE).min(end);
+
+        instructions.push(Instruction::data_block(offset, &bytes[offset .. block_end]));
+    }
+}
@@ -0,0 +1,629 @@
+/*
+A headless EBC interpreter.
+
+The VM executes instructions straight out of a flat memory image: the loaded
+bytecode (raw binary or PE code section) at address 0, followed by the stack.
+R0 is the stack pointer and starts at the top of memory. Instructions are
+decoded with `OpCode::decode` so the VM sees exactly what the disassembler
+prints.
+
+CALLEX cannot be executed by the VM since it calls native code. `step()`
+returns `Step::Native` with the target address instead, so the host can
+emulate the service (and set R7) before continuing. `Services` does this for
+the common UEFI services, using memory that `allocate()` adds past the stack.
+*/
+
+use crate::argument::Argument;
+use crate::error::Error;
+use crate::instruction::Instruction;
+use crate::opcode::OpCode;
+use crate::operand::Operand;
+
+const STACK_SIZE: usize = 0x10000;
+const HEAP_SIZE: u64 = 0x400_0000; // Most memory `allocate()` adds past the stack
+const VM_VERSION: u64 = 0x00010000; // Major version in the upper 16 bits
+const FLAGS_CONDITION: u64 = 0b01;
+const FLAGS_VALID: u64 = 0b11; // Condition code and single step
+
+/// What happened when a single instruction was executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step
+{
+    Continue,    // IP points to the next instruction to execute
+    Native(u64), // CALLEX to a native function, IP points past the call
+    Breakpoint,  // BREAK 3
+    Halt,        // RET with nothing left on the call stack
+}
+
+#[derive(Debug, Clone)]
+pub struct Vm
+{
+    pub registers: [u64; 8], // R0 - R7, R0 is the stack pointer
+    pub flags: u64,
+    pub ip: u64,
+    pub memory: Vec<u8>,
+    pub pointer_size: u64, // Size of a natural unit in bytes, 4 or 8
+    stack_top: u64,
+    heap: u64, // Address right past the stack, where `allocate()` starts
+}
+
+impl Vm
+{
+    /// Loads `image` at address 0 and places the stack directly after it.
+    pub fn new(image: &[u8]) -> Self
+    {
+        let mut memory = image.to_vec();
+        memory.resize(image.len() + STACK_SIZE, 0);
+
+        let stack_top = (memory.len() as u64) & !0xF;
+        let mut registers = [0; 8];
+        registers[0] = stack_top;
+
+        let heap = memory.len() as u64;
+
+        Self { registers, flags: 0, ip: 0, memory, pointer_size: 8, stack_top, heap }
+    }
+
+    pub fn pointer_size(mut self, pointer_size: u64) -> Self
+    {
+        self.pointer_size = pointer_size;
+        self
+    }
+
+    /// Sets up the stack as if native code had called into the bytecode, so
+    /// that argument `n` is found at `@R0(+n, +16)`. Since the size of each
+    /// argument is a natural unit, this has to come after `pointer_size()`.
+    pub fn arguments(mut self, arguments: &[u64]) -> Self
+    {
+        let natural = self.pointer_size;
+        let frame = (16 + arguments.len() as u64 * natural + 0xF) & !0xF;
+
+        self.stack_top -= frame;
+        self.registers[0] = self.stack_top;
+
+        for (i, argument) in arguments.iter().enumerate()
+        {
+            let address = self.stack_top + 16 + i as u64 * natural;
+
+            self.memory[address as usize .. (address + natural) as usize]
+                .copy_from_slice(&argument.to_le_bytes()[.. natural as usize]);
+        }
+
+        self
+    }
+
+    /// Copies `bytes` into memory at `address`.
+    pub fn load(&mut self, address: u64, bytes: &[u8]) -> Result<(), Error>
+    {
+        let range = self.range(address, bytes.len())?;
+        self.memory[range].copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// The `length` bytes of memory at `address`.
+    pub fn bytes(&self, address: u64, length: usize) -> Result<&[u8], Error>
+    {
+        let range = self.range(address, length)?;
+
+        Ok(&self.memory[range])
+    }
+
+    pub fn bytes_mut(&mut self, address: u64, length: usize) -> Result<&mut [u8], Error>
+    {
+        let range = self.range(address, length)?;
+
+        Ok(&mut self.memory[range])
+    }
+
+    /// Adds `size` zeroed bytes to the end of memory, past the stack, and
+    /// returns their 16 byte aligned address. Fails once all allocations
+    /// together would exceed `HEAP_SIZE`.
+    pub fn allocate(&mut self, size: u64) -> Result<u64, Error>
+    {
+        let address = (self.memory.len() as u64 + 0xF) & !0xF;
+
+        match address.checked_add(size)
+        {
+            Some(end) if end - self.heap <= HEAP_SIZE =>
+            {
+                self.memory.resize(end as usize, 0);
+                Ok(address)
+            }
+
+            _ => Err(Error::OutOfMemory(size)),
+        }
+    }
+
+    /// Argument `n` of a `CALLEX`, which the program pushed before the call so
+    /// that the arguments start at R0.
+    pub fn argument(&self, n: u64) -> Result<u64, Error>
+    {
+        let natural = self.pointer_size;
+
+        self.read(self.registers[0].wrapping_add(n * natural), natural as usize)
+    }
+
+    /// Reads a little endian value of `width` bytes.
+    pub fn read(&self, address: u64, width: usize) -> Result<u64, Error>
+    {
+        let range = self.range(address, width)?;
+
+        Ok(self.memory[range].iter().rev().fold(0, |value, byte| value << 8 | *byte as u64))
+    }
+
+    /// Writes the lower `width` bytes of `value` in little endian order.
+    pub fn write(&mut self, address: u64, width: usize, value: u64) -> Result<(), Error>
+    {
+        let range = self.range(address, width)?;
+        self.memory[range].copy_from_slice(&value.to_le_bytes()[.. width]);
+
+        Ok(())
+    }
+
+    pub fn push(&mut self, width: usize, value: u64) -> Result<(), Error>
+    {
+        self.registers[0] = self.registers[0].wrapping_sub(width as u64);
+        self.write(self.registers[0], width, value)
+    }
+
+    pub fn pop(&mut self, width: usize) -> Result<u64, Error>
+    {
+        let value = self.read(self.registers[0], width)?;
+        self.registers[0] = self.registers[0].wrapping_add(width as u64);
+
+        Ok(value)
+    }
+
+    /// Decodes and executes the instruction at IP.
+    pub fn step(&mut self) -> Result<Step, Error>
+    {
+        let bytes = match self.memory.get(self.ip as usize ..)
+        {
+            Some(bytes) if !bytes.is_empty() => bytes,
+            _ => return Err(Error::InvalidAddress(self.ip)),
+        };
+
+        // The decoder skips zeros as padding, but here they are a BREAK
+        if bytes[0] & 0b00111111 == OpCode::BREAK.to()
+        {
+            let code = *bytes.get(1).ok_or(Error::UnexpectedEnd)?;
+
+            return self.execute_break(code);
+        }
+
+        let instruction = OpCode::decode(&mut bytes.iter().cloned().peekable())?.ok_or(Error::RunawayBreak)?;
+        self.ip += instruction.length() as u64;
+
+        self.execute(&instruction)
+    }
+
+    /// Steps until anything other than `Step::Continue` happens.
+    pub fn run(&mut self) -> Result<Step, Error>
+    {
+        loop
+        {
+            match self.step()?
+            {
+                Step::Continue => (),
+                step => return Ok(step),
+            }
+        }
+    }
+
+    fn range(&self, address: u64, width: usize) -> Result<std::ops::Range<usize>, Error>
+    {
+        let start = usize::try_from(address).map_err(|_| Error::InvalidAddress(address))?;
+
+        match start.checked_add(width)
+        {
+            Some(end) if end <= self.memory.len() => Ok(start .. end),
+            _ => Err(Error::InvalidAddress(address)),
+        }
+    }
+
+    fn execute_break(&mut self, code: u8) -> Result<Step, Error>
+    {
+        self.ip += 2;
+
+        match code
+        {
+            0 => Err(Error::RunawayBreak),
+
+            1 =>
+            {
+                self.registers[7] = VM_VERSION;
+                Ok(Step::Continue)
+            }
+
+            3 => Ok(Step::Breakpoint),
+
+            // System call, thunk creation and compiler version have no effect
+            _ => Ok(Step::Continue),
+        }
+    }
+
+    /// Resolves an index or immediate argument to a byte offset.
+    fn offset(&self, argument: Option<Argument>) -> i64
+    {
+        match argument.and_then(|argument| argument.natural_index(self.pointer_size))
+        {
+            Some(index) => index.offset,
+            None => argument.and_then(|argument| argument.immediate()).unwrap_or(0),
+        }
+    }
+
+    /// The value of `{@}R {Index|Immed}` as a source operand.
+    fn source(&self, operand: Option<Operand>, argument: Option<Argument>, width: usize) -> Result<u64, Error>
+    {
+        let (index, indirect) = register(operand);
+        let value = self.registers[index].wrapping_add_signed(self.offset(argument));
+
+        if indirect
+        {
+            self.read(value, width)
+        }
+        else
+        {
+            Ok(value & mask(width))
+        }
+    }
+
+    /// Stores to `{@}R {Index}`. Registers receive the zero extended value.
+    fn destination(
+        &mut self,
+        operand: Option<Operand>,
+        argument: Option<Argument>,
+        width: usize,
+        value: u64,
+    ) -> Result<(), Error>
+    {
+        let (index, indirect) = register(operand);
+
+        if indirect
+        {
+            let address = self.registers[index].wrapping_add_signed(self.offset(argument));
+
+            self.write(address, width, value)
+        }
+        else
+        {
+            self.registers[index] = value & mask(width);
+
+            Ok(())
+        }
+    }
+
+    /// The operand of JMP32 and CALL32. R0 reads as zero when used directly.
+    fn branch_target(&self, instruction: &Instruction) -> Result<u64, Error>
+    {
+        let (index, indirect) = register(instruction.operand1);
+
+        if indirect
+        {
+            let address = self.registers[index].wrapping_add_signed(self.offset(instruction.argument1));
+
+            self.read(address, self.pointer_size as usize)
+        }
+        else
+        {
+            let base = if index == 0 { 0 } else { self.registers[index] };
+
+            Ok(base.wrapping_add_signed(self.offset(instruction.argument1)))
+        }
+    }
+
+    fn condition(&self) -> bool
+    {
+        self.flags & FLAGS_CONDITION != 0
+    }
+
+    fn set_condition(&mut self, condition: bool)
+    {
+        self.flags = if condition { self.flags | FLAGS_CONDITION } else { self.flags & !FLAGS_CONDITION };
+    }
+
+    fn execute(&mut self, instruction: &Instruction) -> Result<Step, Error>
+    {
+        let op = instruction.op.ok_or(Error::InvalidOpCode(instruction.bytecode[0]))?;
+        let byte0 = instruction.bytecode[0];
+        let byte1 = instruction.bytecode.get(1).cloned().unwrap_or(0);
+        let is_64_bit = byte0 & 0b01000000 != 0;
+        let width = if is_64_bit { 8 } else { 4 };
+        let natural = self.pointer_size as usize;
+        let next = self.ip;
+
+        match op
+        {
+            OpCode::BREAK => unreachable!(), // Handled before decoding
+
+            OpCode::RET =>
+            {
+                if self.registers[0] == self.stack_top
+                {
+                    return Ok(Step::Halt);
+                }
+
+                self.ip = self.read(self.registers[0], 8)?;
+                self.registers[0] = self.registers[0].wrapping_add(16);
+            }
+
+            OpCode::JMP8 =>
+            {
+                let conditional = byte0 & 0b10000000 != 0;
+                let condition_bit_set = byte0 & 0b01000000 != 0;
+
+                if !conditional || self.condition() == condition_bit_set
+                {
+                    let displacement = self.offset(instruction.argument1) * 2; // Jumps in 16 bit words
+
+                    self.ip = next.wrapping_add_signed(displacement);
+                }
+            }
+
+            OpCode::JMP =>
+            {
+                let conditional = byte1 & 0b10000000 != 0;
+                let condition_bit_set = byte1 & 0b01000000 != 0;
+                let is_relative_address = byte1 & 0b00010000 != 0;
+
+                if !conditional || self.condition() == condition_bit_set
+                {
+                    let target = if is_64_bit
+                    {
+                        self.offset(instruction.argument1) as u64
+                    }
+                    else
+                    {
+                        self.branch_target(instruction)?
+                    };
+
+                    self.ip = if is_relative_address { next.wrapping_add(target) } else { target };
+                }
+            }
+
+            OpCode::CALL =>
+            {
+                let is_native_call = byte1 & 0b00100000 != 0;
+                let is_relative_address = byte1 & 0b00010000 != 0;
+
+                // CALL64 is always an absolute address
+                let target = if is_64_bit
+                {
+                    self.offset(instruction.argument1) as u64
+                }
+                else
+                {
+                    let target = self.branch_target(instruction)?;
+
+                    if is_relative_address
+                    {
+                        next.wrapping_add(target)
+                    }
+                    else
+                    {
+                        target
+                    }
+                };
+
+                if is_native_call
+                {
+                    return Ok(Step::Native(target));
+                }
+
+                self.registers[0] = self.registers[0].wrapping_sub(16);
+                self.write(self.registers[0], 8, next)?;
+                self.ip = target;
+            }
+
+            OpCode::PUSH | OpCode::PUSHn =>
+            {
+                let width = if op == OpCode::PUSHn { natural } else { width };
+                let value = self.source(instruction.operand1, instruction.argument1, width)?;
+
+                self.push(width, value)?;
+            }
+
+            OpCode::POP | OpCode::POPn =>
+            {
+                let width = if op == OpCode::POPn { natural } else { width };
+                let value = self.pop(width)?;
+
+                self.destination(instruction.operand1, instruction.argument1, width, value)?;
+            }
+
+            OpCode::STORESP =>
+            {
+                let value = match instruction.operand2
+                {
+                    Some(Operand::Dedicated { register_index: 0, .. }) => self.flags,
+                    _ => next,
+                };
+
+                self.registers[register(instruction.operand1).0] = value;
+            }
+
+            OpCode::LOADSP =>
+            {
+                // Only FLAGS can be written
+                if let Some(Operand::Dedicated { register_index: index @ 1 .., .. }) = instruction.operand1
+                {
+                    return Err(Error::InvalidDedicatedRegister(index));
+                }
+
+                self.flags = self.registers[register(instruction.operand2).0] & FLAGS_VALID;
+            }
+
+            OpCode::CMPIeq | OpCode::CMPIlte | OpCode::CMPIgte | OpCode::CMPIulte | OpCode::CMPIugte =>
+            {
+                let op1 = self.source(instruction.operand1, instruction.argument1, width)?;
+                let op2 = self.offset(instruction.argument2) as u64 & mask(width);
+
+                self.set_condition(compare(op, op1, op2, width));
+            }
+
+            OpCode::MOVI =>
+            {
+                let move_width = 1 << ((byte1 >> 4) & 0b11);
+                let value = self.offset(instruction.argument2) as u64;
+
+                self.destination(instruction.operand1, instruction.argument1, move_width, value)?;
+            }
+
+            OpCode::MOVIn =>
+            {
+                let value = self.offset(instruction.argument2) as u64;
+
+                self.destination(instruction.operand1, instruction.argument1, natural, value)?;
+            }
+
+            OpCode::MOVREL =>
+            {
+                let value = next.wrapping_add_signed(self.offset(instruction.argument2));
+
+                self.destination(instruction.operand1, instruction.argument1, natural, value)?;
+            }
+
+            OpCode::MOVsnw | OpCode::MOVsnd =>
+            {
+                let value = self.source(instruction.operand2, instruction.argument2, natural)?;
+                let value = sign_extend(value, natural) as u64;
+
+                // Registers receive the sign extended value
+                match register(instruction.operand1)
+                {
+                    (index, false) => self.registers[index] = value,
+                    _ => self.destination(instruction.operand1, instruction.argument1, natural, value)?,
+                }
+            }
+
+            OpCode::MOVbw
+            | OpCode::MOVww
+            | OpCode::MOVdw
+            | OpCode::MOVqw
+            | OpCode::MOVbd
+            | OpCode::MOVwd
+            | OpCode::MOVdd
+            | OpCode::MOVqd
+            | OpCode::MOVqq
+            | OpCode::MOVnw
+            | OpCode::MOVnd =>
+            {
+                let move_width = match op
+                {
+                    OpCode::MOVbw | OpCode::MOVbd => 1,
+                    OpCode::MOVww | OpCode::MOVwd => 2,
+                    OpCode::MOVdw | OpCode::MOVdd => 4,
+                    OpCode::MOVnw | OpCode::MOVnd => natural,
+                    _ => 8,
+                };
+
+                let value = self.source(instruction.operand2, instruction.argument2, move_width)?;
+
+                self.destination(instruction.operand1, instruction.argument1, move_width, value)?;
+            }
+
+            OpCode::CMPeq | OpCode::CMPlte | OpCode::CMPgte | OpCode::CMPulte | OpCode::CMPugte =>
+            {
+                let op1 = self.registers[register(instruction.operand1).0] & mask(width);
+                let op2 = self.source(instruction.operand2, instruction.argument2, width)?;
+
+                self.set_condition(compare(op, op1, op2, width));
+            }
+
+            _ =>
+            {
+                // Arithmetic, logic and shifts: Op1 <= Op1 OP Op2
+                let op2 = self.source(instruction.operand2, instruction.argument2, width)?;
+                let op1 = self.source(instruction.operand1, None, width)?;
+                let value = calculate(op, op1, op2, width)?;
+
+                self.destination(instruction.operand1, None, width, value)?;
+            }
+        }
+
+        Ok(Step::Continue)
+    }
+}
+
+/// The register index of an operand and whether it is indirect.
+fn register(operand: Option<Operand>) -> (usize, bool)
+{
+    match operand
+    {
+        Some(Operand::GeneralPurpose { register_index, indirect }) => (register_index as usize, indirect),
+        _ => (0, false),
+    }
+}
+
+fn mask(width: usize) -> u64
+{
+    if width >= 8
+    {
+        u64::MAX
+    }
+    else
+    {
+        (1 << (width * 8)) - 1
+    }
+}
+
+fn sign_extend(value: u64, width: usize) -> i64
+{
+    let shift = 64 - width.min(8) as u32 * 8;
+
+    ((value << shift) as i64) >> shift
+}
+
+fn compare(op: OpCode, op1: u64, op2: u64, width: usize) -> bool
+{
+    let (signed1, signed2) = (sign_extend(op1, width), sign_extend(op2, width));
+
+    match op
+    {
+        OpCode::CMPeq | OpCode::CMPIeq => op1 == op2,
+        OpCode::CMPlte | OpCode::CMPIlte => signed1 <= signed2,
+        OpCode::CMPgte | OpCode::CMPIgte => signed1 >= signed2,
+        OpCode::CMPulte | OpCode::CMPIulte => op1 <= op2,
+        OpCode::CMPugte | OpCode::CMPIugte => op1 >= op2,
+        _ => unreachable!(),
+    }
+}
+
+/// Operands are zero extended to 64 bits and the result is truncated to
+/// `width` by the caller, so only signed operations need extra care.
+fn calculate(op: OpCode, op1: u64, op2: u64, width: usize) -> Result<u64, Error>
+{
+    let (signed1, signed2) = (sign_extend(op1, width), sign_extend(op2, width));
+
+    if op2 == 0 && matches!(op, OpCode::DIV | OpCode::DIVU | OpCode::MOD | OpCode::MODU)
+    {
+        return Err(Error::DivideByZero);
+    }
+
+    let value = match op
+    {
+        OpCode::ADD => op1.wrapping_add(op2),
+        OpCode::SUB => op1.wrapping_sub(op2),
+        OpCode::MUL => signed1.wrapping_mul(signed2) as u64,
+        OpCode::MULU => op1.wrapping_mul(op2),
+        OpCode::DIV => signed1.wrapping_div(signed2) as u64,
+        OpCode::DIVU => op1 / op2,
+        OpCode::MOD => signed1.wrapping_rem(signed2) as u64,
+        OpCode::MODU => op1 % op2,
+        OpCode::AND => op1 & op2,
+        OpCode::OR => op1 | op2,
+        OpCode::XOR => op1 ^ op2,
+        OpCode::NOT => !op2,
+        OpCode::NEG => op2.wrapping_neg(),
+        OpCode::SHL => op1.wrapping_shl(op2 as u32),
+        OpCode::SHR => op1.wrapping_shr(op2 as u32),
+        OpCode::ASHR => signed1.wrapping_shr(op2 as u32) as u64,
+        OpCode::EXTNDB => op2 as i8 as u64,
+        OpCode::EXTNDW => op2 as i16 as u64,
+        OpCode::EXTNDD => op2 as i32 as u64,
+        _ => unreachable!(),
+    };
+
+    Ok(value)
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+
+use crate::address::Origin;
+use crate::instruction::{Instruction, Reference};
+use crate::options::Options;
+use crate::theme::*;
+
+/// An instruction that jumps to, calls or loads the address of a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xref
+{
+    pub from: usize,          // Offset of the referencing instruction
+    pub reference: Reference, // How the target is referenced
+    pub mnemonic: String,     // Mnemonic of the referencing instruction, e.g. JMP8cs
+}
+
+/// The annotation printed above the target.
+impl Emit for Xref
+{
+    fn emit(&self, options: &Options) -> String
+    {
+        color_comment(format!("; XREF from 0x{:04X} ({})", self.from, self.mnemonic), options)
+    }
+}
+
+/// Every reference between the decoded instructions, indexed by target.
+#[derive(Debug, Default)]
+pub struct Xrefs
+{
+    targets: BTreeMap<usize, Vec<Xref>>,
+}
+
+impl Xrefs
+{
+    /// Collects relative jumps, calls and `MOVREL` loads along with absolute
+    /// `JMP64` and `CALL64` immediates. Absolute addresses are converted to
+    /// offsets using `origin`, so they only resolve when the image is loaded
+    /// at its preferred base.
+    pub fn new(instructions: &[Instruction], origin: &Origin) -> Self
+    {
+        let mut targets = BTreeMap::<usize, Vec<Xref>>::new();
+        let base = origin.image_base + origin.rva;
+
+        for instruction in instructions
+        {
+            let relative = instruction
+                .reference()
+                .and_then(|(reference, target)| Some((reference, usize::try_from(target).ok()?)));
+            let absolute = instruction
+                .absolute_reference()
+                .and_then(|(reference, address)| Some((reference, usize::try_from(address.checked_sub(base)?).ok()?)));
+
+            if let Some((reference, target)) = relative.or(absolute)
+            {
+                targets.entry(target).or_default().push(Xref {
+                    from: instruction.offset,
+                    reference,
+                    mnemonic: instruction.mnemonic(),
+                });
+            }
+        }
+
+        Self { targets }
+    }
+
+    /// The references to `target`, in order of offset.
+    pub fn to(&self, target: usize) -> &[Xref]
+    {
+        self.targets.get(&target).map_or(&[], |xrefs| xrefs.as_slice())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &[Xref])>
+    {
+        self.targets.iter().map(|(target, xrefs)| (*target, xrefs.as_slice()))
+    }
+}